@@ -0,0 +1,313 @@
+use err::{ColumnConstraintsError, ColumnTypeError, SchemaLoadError};
+use expr::{compile, Check};
+use mask::PII_TAG;
+use regexes::{IDENT_UNDERSCORE_RE, KEY_RE, REFERENCES_RE};
+use super::{ColumnType, CsvxColumnType, CsvxSchema};
+use try_from::TryFrom;
+
+/// Builds a schema column by column
+///
+/// Enforces the same rules as loading a schema from a file: identifiers must
+/// be valid and unique, types and key or reference names must be well-formed,
+/// primary key columns must not be nullable, constraints must apply to the
+/// column's type, columns tagged `pii` must be maskable, defaults and examples
+/// must be valid values and `CHECK` expressions must fit the schema.
+#[derive(Clone, Debug, Default)]
+pub struct CsvxSchemaBuilder {
+    columns: Vec<CsvxColumnType>,
+}
+
+impl CsvxSchemaBuilder {
+    pub fn new() -> CsvxSchemaBuilder {
+        CsvxSchemaBuilder::default()
+    }
+
+    /// Append a column
+    ///
+    /// On error, the builder is left unchanged.
+    pub fn add_column(&mut self, col: CsvxColumnType) -> Result<&mut Self, SchemaLoadError> {
+        if !IDENT_UNDERSCORE_RE.is_match(&col.id) {
+            return Err(SchemaLoadError::BadIdentifier(col.id));
+        }
+
+        if self.columns.iter().any(|c| c.id == col.id) {
+            return Err(SchemaLoadError::DuplicateIdentifier(col.id));
+        }
+
+        check_type(&col.ty).map_err(SchemaLoadError::BadType)?;
+
+        if let Some(fragment) = malformed_constraint(&col) {
+            return Err(SchemaLoadError::BadConstraints(
+                ColumnConstraintsError::MalformedConstraints(fragment),
+            ));
+        }
+
+        if col.constraints.primary && col.constraints.nullable {
+            return Err(SchemaLoadError::NullablePrimaryKey(col.id));
+        }
+
+        if let Some(constraint) = inapplicable_constraint(&col) {
+            return Err(SchemaLoadError::InapplicableConstraint(col.id, constraint));
+        }
+
+        // masked values are fitted into limits, but not into patterns
+        if col.constraints.pattern.is_some() && col.metadata.tags.iter().any(|t| t == PII_TAG) {
+            return Err(SchemaLoadError::UnmaskableColumn(col.id));
+        }
+
+        if let Some(Err(e)) = col.default.as_ref().map(|v| col.validate_value(v)) {
+            return Err(SchemaLoadError::BadDefault(col.id, Box::new(e)));
+        }
+
+        if let Some(Err(e)) = col.example.as_ref().map(|v| col.validate_value(v)) {
+            return Err(SchemaLoadError::BadExample(col.id, Box::new(e)));
+        }
+
+        self.columns.push(col);
+        Ok(self)
+    }
+
+    /// Finish the schema
+    ///
+    /// `CHECK` expressions may refer to any column, so they are only checked
+    /// once all columns have been added.
+    pub fn build(self) -> Result<CsvxSchema, SchemaLoadError> {
+        let mut checks = Vec::new();
+        for (idx, col) in self.columns.iter().enumerate() {
+            for expr in &col.constraints.checks {
+                checks.push(Check {
+                    column: idx,
+                    expr: expr.clone(),
+                    compiled: compile(expr, &self.columns).map_err(|e| {
+                        SchemaLoadError::BadCheck(col.id.clone(), Box::new(e))
+                    })?,
+                });
+            }
+        }
+
+        Ok(CsvxSchema {
+            columns: self.columns,
+            checks: checks,
+        })
+    }
+}
+
+/// Check that a type survives being written to and read from a schema file
+fn check_type(ty: &ColumnType) -> Result<(), ColumnTypeError> {
+    let s = ty.to_string();
+    let parsed = ColumnType::try_from(s.as_str())?;
+
+    // variants must neither be empty nor contain commas
+    if let ColumnType::Enum(ref variants) = *ty {
+        if parsed != *ty || variants.iter().any(|v| v.is_empty()) {
+            return Err(ColumnTypeError::BadEnum(s));
+        }
+    }
+
+    Ok(())
+}
+
+/// First `KEY` or `REFERENCES` constraint of a column with invalid names
+fn malformed_constraint(col: &CsvxColumnType) -> Option<String> {
+    let ccs = &col.constraints;
+
+    for name in &ccs.keys {
+        let fragment = format!("KEY({})", name);
+        if !KEY_RE.is_match(&fragment) {
+            return Some(fragment);
+        }
+    }
+
+    if let Some(ref fk) = ccs.references {
+        let fragment = format!("REFERENCES({}.{})", fk.schema, fk.column);
+        if !REFERENCES_RE.is_match(&fragment) {
+            return Some(fragment);
+        }
+    }
+
+    None
+}
+
+/// First constraint of a column that does not apply to its type
+fn inapplicable_constraint(col: &CsvxColumnType) -> Option<String> {
+    let ccs = &col.constraints;
+
+    if !matches!(col.ty, ColumnType::Integer | ColumnType::Decimal(_)) {
+        if let Some(ref min) = ccs.min {
+            return Some(format!("MIN({})", min));
+        }
+        if let Some(ref max) = ccs.max {
+            return Some(format!("MAX({})", max));
+        }
+    }
+
+    if col.ty != ColumnType::String {
+        if let Some(max_len) = ccs.max_len {
+            return Some(format!("MAXLEN({})", max_len));
+        }
+        if ccs.pattern.is_some() {
+            return Some("PATTERN".to_owned());
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ForeignKey;
+    use format::check_format;
+
+    fn zoo_schema() -> CsvxSchema {
+        let mut id = CsvxColumnType::new("id", ColumnType::Integer);
+        id.constraints.unique = true;
+        id.description = "Internal zoo id".to_owned();
+
+        let mut caretaker = CsvxColumnType::new("caretaker", ColumnType::String);
+        caretaker.constraints.nullable = true;
+        caretaker.description = "Designated caretaker, \"if any\"".to_owned();
+
+        let mut builder = CsvxSchemaBuilder::new();
+        builder
+            .add_column(id)
+            .unwrap()
+            .add_column(CsvxColumnType::new(
+                "class",
+                ColumnType::Enum(vec!["MAMMAL".to_owned(), "BIRD".to_owned()]),
+            ))
+            .unwrap()
+            .add_column(caretaker)
+            .unwrap();
+        builder.build().unwrap()
+    }
+
+    #[test]
+    fn invalid_columns_are_rejected() {
+        let mut builder = CsvxSchemaBuilder::new();
+        builder
+            .add_column(CsvxColumnType::new("id", ColumnType::Integer))
+            .unwrap();
+
+        match builder.add_column(CsvxColumnType::new("id", ColumnType::String)) {
+            Err(SchemaLoadError::DuplicateIdentifier(ref id)) => assert_eq!(id, "id"),
+            res => panic!("unexpected result {:?}", res),
+        }
+        match builder.add_column(CsvxColumnType::new("Name", ColumnType::String)) {
+            Err(SchemaLoadError::BadIdentifier(ref id)) => assert_eq!(id, "Name"),
+            res => panic!("unexpected result {:?}", res),
+        }
+
+        let bad_types = vec![
+            ColumnType::Enum(vec![]),
+            ColumnType::Enum(vec!["MAMMAL".to_owned(), "".to_owned()]),
+            ColumnType::Enum(vec!["mammal".to_owned()]),
+            ColumnType::Enum(vec!["MAMMAL,BIRD".to_owned()]),
+            ColumnType::Decimal(Some((0, 0))),
+            ColumnType::Decimal(Some((2, 3))),
+            ColumnType::DateTime(10),
+            ColumnType::DateTimeTz(12),
+        ];
+        for ty in bad_types {
+            match builder.add_column(CsvxColumnType::new("class", ty)) {
+                Err(SchemaLoadError::BadType(_)) => (),
+                res => panic!("unexpected result {:?}", res),
+            }
+        }
+
+        let mut visit = CsvxColumnType::new("visit", ColumnType::Date);
+        visit.constraints.keys.push("Visit".to_owned());
+        match builder.add_column(visit) {
+            Err(SchemaLoadError::BadConstraints(ColumnConstraintsError::MalformedConstraints(
+                ref fragment,
+            ))) => assert_eq!(fragment, "KEY(Visit)"),
+            res => panic!("unexpected result {:?}", res),
+        }
+
+        let mut keeper = CsvxColumnType::new("keeper", ColumnType::String);
+        keeper.constraints.references = Some(ForeignKey {
+            schema: "staff_1".to_owned(),
+            column: "id".to_owned(),
+        });
+        match builder.add_column(keeper) {
+            Err(SchemaLoadError::BadConstraints(ColumnConstraintsError::MalformedConstraints(
+                ref fragment,
+            ))) => assert_eq!(fragment, "REFERENCES(staff_1.id)"),
+            res => panic!("unexpected result {:?}", res),
+        }
+
+        assert_eq!(builder.build().unwrap().iter_columns().count(), 1);
+
+        // loading schema files goes through the builder as well
+        let err = CsvxSchema::from_string(
+            "id,type,constraints,description\r\n\
+             id,INTEGER,,\r\n\
+             id,STRING,,\r\n",
+            "schema.csv",
+        ).unwrap_err();
+        match *err.error() {
+            SchemaLoadError::DuplicateIdentifier(_) => (),
+            ref e => panic!("unexpected error {:?}", e),
+        }
+    }
+
+    #[test]
+    fn written_schemas_round_trip() {
+        let schema = zoo_schema();
+
+        let mut out = Vec::new();
+        schema.write_to(&mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out.clone()).unwrap(),
+            "id,type,constraints,description\r\n\
+             id,INTEGER,UNIQUE,Internal zoo id\r\n\
+             class,\"ENUM(MAMMAL,BIRD)\",,\r\n\
+             caretaker,STRING,NULLABLE,\"Designated caretaker, \"\"if any\"\"\"\r\n"
+        );
+        assert!(check_format(&out, "schema.csv").is_empty());
+
+        let loaded = CsvxSchema::from_string(&String::from_utf8(out).unwrap(), "schema.csv")
+            .unwrap();
+        let mut again = Vec::new();
+        loaded.write_to(&mut again).unwrap();
+        let mut orig = Vec::new();
+        schema.write_to(&mut orig).unwrap();
+        assert_eq!(again, orig);
+    }
+
+    #[test]
+    fn built_schemas_load() {
+        let mut id = CsvxColumnType::new("id", ColumnType::Integer);
+        id.constraints.primary = true;
+
+        let mut visit = CsvxColumnType::new("visit", ColumnType::DateTimeTz(3));
+        visit.constraints.keys.push("visit_time".to_owned());
+
+        let mut keeper = CsvxColumnType::new("keeper", ColumnType::String);
+        keeper.constraints.keys.push("visit_time".to_owned());
+        keeper.constraints.references = Some(ForeignKey {
+            schema: "staff-1".to_owned(),
+            column: "name".to_owned(),
+        });
+
+        let mut builder = CsvxSchemaBuilder::new();
+        builder
+            .add_column(id)
+            .unwrap()
+            .add_column(visit)
+            .unwrap()
+            .add_column(keeper)
+            .unwrap()
+            .add_column(CsvxColumnType::new("fee", ColumnType::Decimal(Some((10, 2)))))
+            .unwrap();
+
+        let mut out = Vec::new();
+        builder.build().unwrap().write_to(&mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+
+        let loaded = CsvxSchema::from_string(&out, "schema.csv").unwrap();
+        let mut again = Vec::new();
+        loaded.write_to(&mut again).unwrap();
+        assert_eq!(String::from_utf8(again).unwrap(), out);
+    }
+}
@@ -0,0 +1,367 @@
+//! Deserialization of rows into user-defined types
+//!
+//! Rows are parsed and checked using the schema first, the resulting values
+//! are then handed to serde. Structs are matched by column id, tuples and
+//! sequences by position.
+
+use err::{ErrorAtLocation, ErrorLoc, ValidationError};
+use serde::de::{self, DeserializeOwned, DeserializeSeed, IntoDeserializer, Visitor};
+use std::cell::Cell;
+use std::vec;
+use super::{ColumnType, CsvxSchema, Value};
+
+impl CsvxSchema {
+    /// Parse a row and deserialize it into `T`
+    ///
+    /// Errors are located at the 1-based index of the offending field, or at
+    /// `0` if no specific field is to blame (e.g. a missing struct field).
+    pub fn deserialize_row<T: DeserializeOwned, F: AsRef<[String]>>(
+        &self,
+        fields: &F,
+    ) -> Result<T, ErrorAtLocation<ValidationError, usize>> {
+        let values = self.parse_row(fields)?;
+        self.deserialize_values(values)
+    }
+
+    /// Deserialize already parsed values into `T`
+    pub fn deserialize_values<T: DeserializeOwned>(
+        &self,
+        values: Vec<Option<Value>>,
+    ) -> Result<T, ErrorAtLocation<ValidationError, usize>> {
+        if values.len() != self.columns.len() {
+            return Err(ValidationError::SchemaMismatch.at(0));
+        }
+
+        let current = Cell::new(0);
+        let rv = T::deserialize(RowDeserializer {
+            schema: self,
+            values: values.into_iter(),
+            idx: 0,
+            current: &current,
+        });
+
+        rv.map_err(|e| e.at(current.get()))
+    }
+}
+
+/// Deserializer for a whole row
+///
+/// `current` tracks the 1-based index of the field being deserialized, to
+/// allow locating errors afterwards.
+struct RowDeserializer<'a> {
+    schema: &'a CsvxSchema,
+    values: vec::IntoIter<Option<Value>>,
+    idx: usize,
+    current: &'a Cell<usize>,
+}
+
+impl<'a> RowDeserializer<'a> {
+    fn next_value(&mut self) -> Option<ValueDeserializer<'a>> {
+        let value = self.values.next()?;
+        let ty = &self.schema.columns[self.idx].ty;
+
+        self.idx += 1;
+        self.current.set(self.idx);
+
+        Some(ValueDeserializer {
+            ty: ty,
+            value: value,
+        })
+    }
+}
+
+impl<'de, 'a> de::Deserializer<'de> for RowDeserializer<'a> {
+    type Error = ValidationError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        self.deserialize_map(visitor)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_map(self)
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.deserialize_map(visitor)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_seq(self)
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(
+        self,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.deserialize_seq(visitor)
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit unit_struct newtype_struct enum identifier
+        ignored_any
+    }
+}
+
+impl<'de, 'a> de::MapAccess<'de> for RowDeserializer<'a> {
+    type Error = ValidationError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, Self::Error> {
+        match self.schema.columns.get(self.idx) {
+            Some(col) => {
+                self.current.set(self.idx + 1);
+                seed.deserialize(col.id.as_str().into_deserializer()).map(Some)
+            }
+            None => {
+                // errors raised after all fields have been consumed (such as
+                // missing fields) concern the row as a whole
+                self.current.set(0);
+                Ok(None)
+            }
+        }
+    }
+
+    fn next_value_seed<S: DeserializeSeed<'de>>(&mut self, seed: S) -> Result<S::Value, Self::Error> {
+        match self.next_value() {
+            Some(value) => seed.deserialize(value),
+            None => Err(de::Error::custom("value requested after end of row")),
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.values.len())
+    }
+}
+
+impl<'de, 'a> de::SeqAccess<'de> for RowDeserializer<'a> {
+    type Error = ValidationError;
+
+    fn next_element_seed<S: DeserializeSeed<'de>>(
+        &mut self,
+        seed: S,
+    ) -> Result<Option<S::Value>, Self::Error> {
+        match self.next_value() {
+            Some(value) => seed.deserialize(value).map(Some),
+            None => {
+                self.current.set(0);
+                Ok(None)
+            }
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.values.len())
+    }
+}
+
+/// Deserializer for a single, already parsed value
+///
+/// Dates and times are passed on as strings in the formats understood by
+/// chrono's own deserializers.
+struct ValueDeserializer<'a> {
+    ty: &'a ColumnType,
+    value: Option<Value>,
+}
+
+impl<'a> ValueDeserializer<'a> {
+    fn variant_name(&self, idx: usize) -> Result<String, ValidationError> {
+        match *self.ty {
+            ColumnType::Enum(ref variants) if idx < variants.len() => Ok(variants[idx].clone()),
+            _ => Err(de::Error::custom(
+                format_args!("invalid enum index {} for {}", idx, self.ty),
+            )),
+        }
+    }
+}
+
+impl<'de, 'a> de::Deserializer<'de> for ValueDeserializer<'a> {
+    type Error = ValidationError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        let value = match self.value {
+            Some(ref value) => value.clone(),
+            None => return visitor.visit_none(),
+        };
+
+        match value {
+            Value::String(s) => visitor.visit_string(s),
+            Value::Decimal(d) => visitor.visit_string(d.to_string()),
+            Value::Bool(b) => visitor.visit_bool(b),
+            Value::Integer(i) => visitor.visit_i64(i),
+            Value::Enum(idx) => visitor.visit_string(self.variant_name(idx)?),
+            Value::Date(d) => visitor.visit_string(d.format("%Y-%m-%d").to_string()),
+            Value::DateTime(dt) => {
+                visitor.visit_string(dt.format("%Y-%m-%dT%H:%M:%S%.f").to_string())
+            }
+            Value::DateTimeTz(dt) => visitor.visit_string(dt.to_rfc3339()),
+            Value::Time(t) => visitor.visit_string(t.format("%H:%M:%S").to_string()),
+            Value::Uuid(u) => visitor.visit_string(u.to_hyphenated().to_string()),
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        if self.value.is_some() {
+            visitor.visit_some(self)
+        } else {
+            visitor.visit_none()
+        }
+    }
+
+    fn deserialize_f32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        self.deserialize_f64(visitor)
+    }
+
+    fn deserialize_f64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match self.value {
+            Some(Value::Decimal(ref d)) => visitor.visit_f64(d.to_f64()),
+            Some(Value::Integer(i)) => visitor.visit_f64(i as f64),
+            _ => self.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        let variant = match self.value {
+            Some(Value::Enum(idx)) => self.variant_name(idx)?,
+            Some(Value::String(ref s)) => s.clone(),
+            Some(_) => return self.deserialize_any(visitor),
+            None => return Err(de::Error::invalid_type(de::Unexpected::Option, &"an enum")),
+        };
+
+        visitor.visit_enum(variant.into_deserializer())
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 char str string bytes
+        byte_buf unit unit_struct seq tuple tuple_struct map struct identifier
+        ignored_any
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::NaiveDate;
+    use err::ValueError;
+    use super::*;
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    enum Class {
+        #[serde(rename = "MAMMAL")]
+        Mammal,
+        #[serde(rename = "BIRD")]
+        Bird,
+    }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Animal {
+        id: u32,
+        name: String,
+        birthday: NaiveDate,
+        class: Class,
+        weight: f64,
+        caretaker: Option<String>,
+    }
+
+    fn zoo_schema() -> CsvxSchema {
+        CsvxSchema::from_string(
+            "id,type,constraints,description\r\n\
+             id,INTEGER,UNIQUE,Internal zoo id\r\n\
+             name,STRING,,Name of the animal\r\n\
+             birthday,DATE,,The animals birthday\r\n\
+             class,\"ENUM(MAMMAL,BIRD)\",,Class of species\r\n\
+             weight,DECIMAL,,Weight in kilograms\r\n\
+             caretaker,STRING,NULLABLE,Designated caretaker\r\n",
+            "schema.csv",
+        ).unwrap()
+    }
+
+    fn row(fields: &[&str]) -> Vec<String> {
+        fields.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn deserializes_structs_by_column_id() {
+        let schema = zoo_schema();
+
+        let animal: Animal = schema
+            .deserialize_row(&row(&["1", "Tweety", "19991209", "BIRD", "0.03", ""]))
+            .unwrap();
+        assert_eq!(
+            animal,
+            Animal {
+                id: 1,
+                name: "Tweety".to_owned(),
+                birthday: NaiveDate::from_ymd_opt(1999, 12, 9).unwrap(),
+                class: Class::Bird,
+                weight: 0.03,
+                caretaker: None,
+            }
+        );
+
+        // tuples are filled in column order
+        let (id, name): (i64, String) = schema
+            .deserialize_row(&row(&["2", "Brian", "20141125", "MAMMAL", "150", "Sam"]))
+            .unwrap();
+        assert_eq!((id, name.as_str()), (2, "Brian"));
+    }
+
+    #[test]
+    fn errors_are_located_at_fields() {
+        let schema = zoo_schema();
+
+        // value errors are caught by the schema before deserialization
+        let err = schema
+            .deserialize_row::<Animal, _>(&row(&["1", "Tweety", "1999", "BIRD", "0.03", ""]))
+            .unwrap_err();
+        assert_eq!(*err.location(), 3);
+        match *err.error() {
+            ValidationError::ValueError(ValueError::InvalidDate(_)) => (),
+            ref e => panic!("unexpected error {:?}", e),
+        }
+
+        #[derive(Debug, Deserialize)]
+        struct Small {
+            #[allow(dead_code)]
+            id: u8,
+        }
+
+        let err = schema
+            .deserialize_row::<Small, _>(&row(&["1000", "Brian", "20141125", "MAMMAL", "1", ""]))
+            .unwrap_err();
+        assert_eq!(*err.location(), 1);
+        match *err.error() {
+            ValidationError::Serde(_) => (),
+            ref e => panic!("unexpected error {:?}", e),
+        }
+    }
+}
@@ -0,0 +1,343 @@
+//! Arbitrary-precision decimal numbers
+
+use err::ValueError;
+use num_bigint::{BigInt, Sign};
+use num_traits::{pow, Signed, Zero};
+use safe_unwrap::SafeUnwrap;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::{cmp, fmt, hash, iter, ops, str};
+
+/// An exact decimal number
+///
+/// Stored as an integer mantissa and a scale, the value being `mantissa *
+/// 10^-scale`. The scale is kept as parsed, `1.50` displays as `1.50`, but
+/// comparison and hashing only consider the numeric value, so `1.50 == 1.5`.
+#[derive(Clone, Debug, Default)]
+pub struct Decimal {
+    mantissa: BigInt,
+    scale: u32,
+}
+
+impl Decimal {
+    /// Create a decimal from its mantissa and scale
+    pub fn new(mantissa: BigInt, scale: u32) -> Decimal {
+        Decimal {
+            mantissa: mantissa,
+            scale: scale,
+        }
+    }
+
+    /// Split into mantissa and scale
+    pub fn into_parts(self) -> (BigInt, u32) {
+        (self.mantissa, self.scale)
+    }
+
+    pub fn mantissa(&self) -> &BigInt {
+        &self.mantissa
+    }
+
+    /// Number of digits after the decimal point
+    pub fn scale(&self) -> u32 {
+        self.scale
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.mantissa.is_zero()
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.mantissa.is_negative()
+    }
+
+    /// Remove all trailing zeros after the decimal point
+    pub fn normalize(&self) -> Decimal {
+        let ten = BigInt::from(10);
+        let mut rv = self.clone();
+
+        if rv.mantissa.is_zero() {
+            rv.scale = 0;
+        }
+
+        while rv.scale > 0 && (&rv.mantissa % &ten).is_zero() {
+            rv.mantissa /= &ten;
+            rv.scale -= 1;
+        }
+        rv
+    }
+
+    /// Change the scale without losing precision
+    ///
+    /// Returns `None` if digits that are not zero would have to be dropped.
+    pub fn rescale(&self, scale: u32) -> Option<Decimal> {
+        let normalized = self.normalize();
+        if normalized.scale > scale {
+            return None;
+        }
+
+        Some(Decimal::new(normalized.widen(scale), scale))
+    }
+
+    /// Nearest `f64` to the decimal
+    pub fn to_f64(&self) -> f64 {
+        self.to_string().parse().safe_unwrap(
+            "decimal representation is a valid float",
+        )
+    }
+
+    /// Mantissa of the same value at a scale larger than or equal to ours
+    fn widen(&self, scale: u32) -> BigInt {
+        debug_assert!(scale >= self.scale);
+        &self.mantissa * pow(BigInt::from(10), (scale - self.scale) as usize)
+    }
+}
+
+impl From<i64> for Decimal {
+    fn from(i: i64) -> Decimal {
+        Decimal::new(BigInt::from(i), 0)
+    }
+}
+
+impl str::FromStr for Decimal {
+    type Err = ValueError;
+
+    fn from_str(s: &str) -> Result<Decimal, ValueError> {
+        let err = || ValueError::InvalidDecimal(s.to_owned());
+
+        let (sign, unsigned) = match s.strip_prefix('-') {
+            Some(rest) => (Sign::Minus, rest),
+            None => (Sign::Plus, s),
+        };
+
+        let (int_part, frac_part) = match unsigned.find('.') {
+            Some(pos) => (&unsigned[..pos], &unsigned[pos + 1..]),
+            None => (unsigned, ""),
+        };
+
+        if int_part.is_empty() || (unsigned.contains('.') && frac_part.is_empty()) ||
+            !int_part.bytes().chain(frac_part.bytes()).all(|c| c.is_ascii_digit())
+        {
+            return Err(err());
+        }
+
+        let digits = int_part.to_owned() + frac_part;
+        let magnitude = BigInt::parse_bytes(digits.as_bytes(), 10).ok_or_else(err)?;
+
+        Ok(Decimal::new(
+            if sign == Sign::Minus { -magnitude } else { magnitude },
+            frac_part.len() as u32,
+        ))
+    }
+}
+
+impl fmt::Display for Decimal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let digits = self.mantissa.abs().to_string();
+        let scale = self.scale as usize;
+
+        if self.is_negative() {
+            write!(f, "-")?;
+        }
+
+        if scale == 0 {
+            write!(f, "{}", digits)
+        } else if digits.len() <= scale {
+            write!(f, "0.{}{}", "0".repeat(scale - digits.len()), digits)
+        } else {
+            let (int_part, frac_part) = digits.split_at(digits.len() - scale);
+            write!(f, "{}.{}", int_part, frac_part)
+        }
+    }
+}
+
+impl PartialEq for Decimal {
+    fn eq(&self, other: &Decimal) -> bool {
+        self.cmp(other) == cmp::Ordering::Equal
+    }
+}
+
+impl Eq for Decimal {}
+
+impl PartialOrd for Decimal {
+    fn partial_cmp(&self, other: &Decimal) -> Option<cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Decimal {
+    fn cmp(&self, other: &Decimal) -> cmp::Ordering {
+        let scale = cmp::max(self.scale, other.scale);
+        self.widen(scale).cmp(&other.widen(scale))
+    }
+}
+
+impl hash::Hash for Decimal {
+    fn hash<H: hash::Hasher>(&self, state: &mut H) {
+        // equal values must hash equally, regardless of their scale
+        let normalized = self.normalize();
+        normalized.mantissa.hash(state);
+        normalized.scale.hash(state);
+    }
+}
+
+impl ops::Add<&Decimal> for &Decimal {
+    type Output = Decimal;
+
+    fn add(self, other: &Decimal) -> Decimal {
+        let scale = cmp::max(self.scale, other.scale);
+        Decimal::new(self.widen(scale) + other.widen(scale), scale)
+    }
+}
+
+impl ops::Add for Decimal {
+    type Output = Decimal;
+
+    fn add(self, other: Decimal) -> Decimal {
+        &self + &other
+    }
+}
+
+impl ops::Sub<&Decimal> for &Decimal {
+    type Output = Decimal;
+
+    fn sub(self, other: &Decimal) -> Decimal {
+        let scale = cmp::max(self.scale, other.scale);
+        Decimal::new(self.widen(scale) - other.widen(scale), scale)
+    }
+}
+
+impl ops::Sub for Decimal {
+    type Output = Decimal;
+
+    fn sub(self, other: Decimal) -> Decimal {
+        &self - &other
+    }
+}
+
+impl ops::Mul<&Decimal> for &Decimal {
+    type Output = Decimal;
+
+    fn mul(self, other: &Decimal) -> Decimal {
+        Decimal::new(&self.mantissa * &other.mantissa, self.scale + other.scale)
+    }
+}
+
+impl ops::Mul for Decimal {
+    type Output = Decimal;
+
+    fn mul(self, other: Decimal) -> Decimal {
+        &self * &other
+    }
+}
+
+impl ops::Neg for Decimal {
+    type Output = Decimal;
+
+    fn neg(self) -> Decimal {
+        Decimal::new(-self.mantissa, self.scale)
+    }
+}
+
+impl iter::Sum for Decimal {
+    fn sum<I: Iterator<Item = Decimal>>(iter: I) -> Decimal {
+        iter.fold(Decimal::default(), |acc, d| acc + d)
+    }
+}
+
+impl<'a> iter::Sum<&'a Decimal> for Decimal {
+    fn sum<I: Iterator<Item = &'a Decimal>>(iter: I) -> Decimal {
+        iter.fold(Decimal::default(), |acc, d| &acc + d)
+    }
+}
+
+impl Serialize for Decimal {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Decimal {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Decimal, D::Error> {
+        deserializer.deserialize_str(DecimalVisitor)
+    }
+}
+
+struct DecimalVisitor;
+
+impl<'de> de::Visitor<'de> for DecimalVisitor {
+    type Value = Decimal;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a decimal number")
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<Decimal, E> {
+        v.parse()
+            .map_err(|_| E::invalid_value(de::Unexpected::Str(v), &self))
+    }
+
+    fn visit_i64<E: de::Error>(self, v: i64) -> Result<Decimal, E> {
+        Ok(Decimal::from(v))
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> Result<Decimal, E> {
+        Ok(Decimal::new(BigInt::from(v), 0))
+    }
+
+    fn visit_f64<E: de::Error>(self, v: f64) -> Result<Decimal, E> {
+        v.to_string()
+            .parse()
+            .map_err(|_| E::invalid_value(de::Unexpected::Float(v), &self))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+    use super::*;
+
+    fn dec(s: &str) -> Decimal {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parses_and_displays() {
+        for s in &["0", "12", "1.50", "-0.003", "123456789012345678901234567890.5"] {
+            assert_eq!(dec(s).to_string(), *s);
+        }
+
+        assert_eq!(dec("1.50").scale(), 2);
+        assert_eq!(dec("1.50").into_parts(), (BigInt::from(150), 2));
+
+        for s in &["", "-", ".5", "1.", "1.2.3", "1e5", "+1", " 1"] {
+            assert!(s.parse::<Decimal>().is_err(), "{:?} should not parse", s);
+        }
+    }
+
+    #[test]
+    fn compares_numeric_values() {
+        assert_eq!(dec("1.50"), dec("1.5"));
+        assert_eq!(dec("0.000"), dec("0"));
+        assert!(dec("-2") < dec("1.99"));
+        assert!(dec("10.01") > dec("10.009"));
+
+        let set: HashSet<_> = vec![dec("1.5"), dec("1.50"), dec("1.500")].into_iter().collect();
+        assert_eq!(set.len(), 1);
+
+        assert_eq!(dec("1.500").normalize().to_string(), "1.5");
+        assert_eq!(dec("1.5").rescale(3).unwrap().to_string(), "1.500");
+        assert!(dec("1.25").rescale(1).is_none());
+    }
+
+    #[test]
+    fn arithmetic_is_exact() {
+        // the classic `0.1 + 0.2 != 0.3` does not apply
+        assert_eq!(dec("0.1") + dec("0.2"), dec("0.3"));
+        assert_eq!((dec("1.05") - dec("2")).to_string(), "-0.95");
+        assert_eq!((dec("1.5") * dec("-0.25")).to_string(), "-0.375");
+
+        let costs = vec![dec("0.10"); 1000];
+        let total: Decimal = costs.iter().sum();
+        assert_eq!(total.to_string(), "100.00");
+        assert_eq!(total.to_f64(), 100.0);
+    }
+}
@@ -0,0 +1,718 @@
+//! Comparison of schema versions
+
+use safe_unwrap::SafeUnwrap;
+use std::fmt;
+use super::{ColumnType, CsvxSchema, Decimal, Expr, ForeignKey, TableKey};
+
+/// A single difference between an old and a new version of a schema
+///
+/// Positions are 0-based column indices.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum SchemaChange {
+    /// Column only present in the new schema
+    ColumnAdded(String),
+
+    /// Column only present in the old schema
+    ColumnRemoved(String),
+
+    /// Column present in both, but in a different order relative to the
+    /// other shared columns
+    ColumnMoved {
+        id: String,
+        from: usize,
+        to: usize,
+    },
+
+    /// Column type changed (other than adding or removing enum variants)
+    TypeChanged {
+        id: String,
+        from: ColumnType,
+        to: ColumnType,
+    },
+
+    /// Variant added to an `ENUM` column
+    EnumVariantAdded { id: String, variant: String },
+
+    /// Variant removed from an `ENUM` column
+    EnumVariantRemoved { id: String, variant: String },
+
+    /// Column became `NULLABLE`
+    NullableAdded(String),
+
+    /// Column is no longer `NULLABLE`
+    NullableRemoved(String),
+
+    /// Column became `UNIQUE`
+    UniqueAdded(String),
+
+    /// Column is no longer `UNIQUE`
+    UniqueRemoved(String),
+
+    /// Column gained a `REFERENCES` constraint
+    ReferenceAdded { id: String, to: ForeignKey },
+
+    /// Column lost its `REFERENCES` constraint
+    ReferenceRemoved { id: String, from: ForeignKey },
+
+    /// Column references a different column
+    ReferenceChanged {
+        id: String,
+        from: ForeignKey,
+        to: ForeignKey,
+    },
+
+    /// `MIN` added, removed or changed, `None` standing for no bound
+    MinChanged {
+        id: String,
+        from: Option<Decimal>,
+        to: Option<Decimal>,
+    },
+
+    /// `MAX` added, removed or changed
+    MaxChanged {
+        id: String,
+        from: Option<Decimal>,
+        to: Option<Decimal>,
+    },
+
+    /// `MAXLEN` added, removed or changed
+    MaxLenChanged {
+        id: String,
+        from: Option<usize>,
+        to: Option<usize>,
+    },
+
+    /// `PATTERN` added, removed or changed, given as regular expressions
+    PatternChanged {
+        id: String,
+        from: Option<String>,
+        to: Option<String>,
+    },
+
+    /// `CHECK` constraint only present in the new schema, declared on the
+    /// given column
+    CheckAdded { id: String, check: Expr },
+
+    /// `CHECK` constraint only present in the old schema, declared on the
+    /// given column
+    CheckRemoved { id: String, check: Expr },
+
+    /// Primary key or named key only present in the new schema
+    KeyAdded(TableKey),
+
+    /// Primary key or named key only present in the old schema
+    KeyRemoved(TableKey),
+
+    /// Key present in both, but made up of different columns
+    ///
+    /// Only breaking if columns were removed from the key, adding columns
+    /// makes it less strict.
+    KeyChanged { from: TableKey, to: TableKey },
+}
+
+impl SchemaChange {
+    /// Id of the column affected by the change
+    ///
+    /// Returns `None` for changes to the table as a whole, i.e. its keys.
+    pub fn column(&self) -> Option<&str> {
+        let id = match *self {
+            SchemaChange::ColumnAdded(ref id) |
+            SchemaChange::ColumnRemoved(ref id) |
+            SchemaChange::ColumnMoved { ref id, .. } |
+            SchemaChange::TypeChanged { ref id, .. } |
+            SchemaChange::EnumVariantAdded { ref id, .. } |
+            SchemaChange::EnumVariantRemoved { ref id, .. } |
+            SchemaChange::NullableAdded(ref id) |
+            SchemaChange::NullableRemoved(ref id) |
+            SchemaChange::UniqueAdded(ref id) |
+            SchemaChange::UniqueRemoved(ref id) |
+            SchemaChange::ReferenceAdded { ref id, .. } |
+            SchemaChange::ReferenceRemoved { ref id, .. } |
+            SchemaChange::ReferenceChanged { ref id, .. } |
+            SchemaChange::MinChanged { ref id, .. } |
+            SchemaChange::MaxChanged { ref id, .. } |
+            SchemaChange::MaxLenChanged { ref id, .. } |
+            SchemaChange::PatternChanged { ref id, .. } |
+            SchemaChange::CheckAdded { ref id, .. } |
+            SchemaChange::CheckRemoved { ref id, .. } => id,
+            SchemaChange::KeyAdded(_) |
+            SchemaChange::KeyRemoved(_) |
+            SchemaChange::KeyChanged { .. } => return None,
+        };
+        Some(id)
+    }
+
+    /// Whether the change is breaking
+    ///
+    /// A change is breaking if a file that is valid under the old schema
+    /// might be invalid under the new one. Since headers must match the
+    /// schema exactly, adding, removing or reordering columns is always
+    /// breaking.
+    pub fn is_breaking(&self) -> bool {
+        match *self {
+            // any value is a valid string, and any fixed-precision decimal
+            // a valid decimal, but no other conversion is lossless; e.g.
+            // integers may be negative, decimals may not
+            SchemaChange::TypeChanged { ref from, ref to, .. } => {
+                !matches!(
+                    (from, to),
+                    (_, ColumnType::String) | (ColumnType::Decimal(_), ColumnType::Decimal(None))
+                )
+            }
+            // only tightened limits are breaking; patterns cannot be compared
+            SchemaChange::MinChanged {
+                ref from,
+                to: Some(ref to),
+                ..
+            } => !matches!(*from, Some(ref from) if to <= from),
+            SchemaChange::MaxChanged {
+                ref from,
+                to: Some(ref to),
+                ..
+            } => !matches!(*from, Some(ref from) if to >= from),
+            SchemaChange::MaxLenChanged {
+                ref from,
+                to: Some(to),
+                ..
+            } => !matches!(*from, Some(from) if to >= from),
+            // values unique in combination stay unique if more columns are
+            // added to the combination
+            SchemaChange::KeyChanged { ref from, ref to } => {
+                !from.columns.iter().all(|id| to.columns.contains(id))
+            }
+            SchemaChange::MinChanged { to: None, .. } |
+            SchemaChange::MaxChanged { to: None, .. } |
+            SchemaChange::MaxLenChanged { to: None, .. } |
+            SchemaChange::PatternChanged { to: None, .. } |
+            SchemaChange::EnumVariantAdded { .. } |
+            SchemaChange::NullableAdded(_) |
+            SchemaChange::UniqueRemoved(_) |
+            SchemaChange::ReferenceRemoved { .. } |
+            SchemaChange::CheckRemoved { .. } |
+            SchemaChange::KeyRemoved(_) => false,
+            _ => true,
+        }
+    }
+}
+
+impl fmt::Display for SchemaChange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SchemaChange::ColumnAdded(ref id) => write!(f, "column `{}` added", id),
+            SchemaChange::ColumnRemoved(ref id) => write!(f, "column `{}` removed", id),
+            SchemaChange::ColumnMoved { ref id, from, to } => {
+                write!(
+                    f,
+                    "column `{}` moved from position {} to {}",
+                    id,
+                    from + 1,
+                    to + 1
+                )
+            }
+            SchemaChange::TypeChanged {
+                ref id,
+                ref from,
+                ref to,
+            } => write!(f, "column `{}` changed type from {} to {}", id, from, to),
+            SchemaChange::EnumVariantAdded {
+                ref id,
+                ref variant,
+            } => write!(f, "variant `{}` added to column `{}`", variant, id),
+            SchemaChange::EnumVariantRemoved {
+                ref id,
+                ref variant,
+            } => write!(f, "variant `{}` removed from column `{}`", variant, id),
+            SchemaChange::NullableAdded(ref id) => write!(f, "column `{}` is now NULLABLE", id),
+            SchemaChange::NullableRemoved(ref id) => {
+                write!(f, "column `{}` is no longer NULLABLE", id)
+            }
+            SchemaChange::UniqueAdded(ref id) => write!(f, "column `{}` is now UNIQUE", id),
+            SchemaChange::UniqueRemoved(ref id) => {
+                write!(f, "column `{}` is no longer UNIQUE", id)
+            }
+            SchemaChange::ReferenceAdded { ref id, ref to } => {
+                write!(f, "column `{}` now references `{}`", id, to)
+            }
+            SchemaChange::ReferenceRemoved { ref id, ref from } => {
+                write!(f, "column `{}` no longer references `{}`", id, from)
+            }
+            SchemaChange::ReferenceChanged {
+                ref id,
+                ref from,
+                ref to,
+            } => write!(f, "column `{}` references `{}` instead of `{}`", id, to, from),
+            SchemaChange::MinChanged {
+                ref id,
+                ref from,
+                ref to,
+            } => {
+                let render = |n: &Decimal| format!("MIN({})", n);
+                limit_changed(f, id, from.as_ref().map(&render), to.as_ref().map(&render))
+            }
+            SchemaChange::MaxChanged {
+                ref id,
+                ref from,
+                ref to,
+            } => {
+                let render = |n: &Decimal| format!("MAX({})", n);
+                limit_changed(f, id, from.as_ref().map(&render), to.as_ref().map(&render))
+            }
+            SchemaChange::MaxLenChanged { ref id, from, to } => {
+                let render = |n: usize| format!("MAXLEN({})", n);
+                limit_changed(f, id, from.map(&render), to.map(&render))
+            }
+            SchemaChange::PatternChanged {
+                ref id,
+                ref from,
+                ref to,
+            } => {
+                let render = |re: &String| format!("PATTERN('{}')", re.replace('\'', "''"));
+                limit_changed(f, id, from.as_ref().map(&render), to.as_ref().map(&render))
+            }
+            SchemaChange::CheckAdded { ref id, ref check } => {
+                write!(f, "check `{}` added to column `{}`", check, id)
+            }
+            SchemaChange::CheckRemoved { ref id, ref check } => {
+                write!(f, "check `{}` removed from column `{}`", check, id)
+            }
+            SchemaChange::KeyAdded(ref key) => write!(f, "{} added", key),
+            SchemaChange::KeyRemoved(ref key) => write!(f, "{} removed", key),
+            SchemaChange::KeyChanged { ref from, ref to } => {
+                write!(f, "{} changed to ({})", from, to.columns.join(", "))
+            }
+        }
+    }
+}
+
+/// Describe a change of a limit, given as rendered constraints
+fn limit_changed(
+    f: &mut fmt::Formatter,
+    id: &str,
+    from: Option<String>,
+    to: Option<String>,
+) -> fmt::Result {
+    match (from, to) {
+        (Some(from), Some(to)) => write!(f, "column `{}` changed {} to {}", id, from, to),
+        (None, Some(to)) => write!(f, "column `{}` now has {}", id, to),
+        (Some(from), None) => write!(f, "column `{}` no longer has {}", id, from),
+        (None, None) => write!(f, "column `{}` is unchanged", id),
+    }
+}
+
+impl CsvxSchema {
+    /// List all changes from `self` to the `new` version of the schema
+    pub fn diff(&self, new: &CsvxSchema) -> Vec<SchemaChange> {
+        let mut changes = Vec::new();
+
+        for col in &self.columns {
+            if new.col_idx(&col.id).is_none() {
+                changes.push(SchemaChange::ColumnRemoved(col.id.clone()));
+            }
+        }
+
+        for col in &new.columns {
+            if self.col_idx(&col.id).is_none() {
+                changes.push(SchemaChange::ColumnAdded(col.id.clone()));
+            }
+        }
+
+        // order is only compared among the columns present in both versions,
+        // otherwise every insertion would move all columns behind it
+        let shared_old: Vec<_> = self.columns
+            .iter()
+            .filter(|c| new.col_idx(&c.id).is_some())
+            .map(|c| c.id.as_str())
+            .collect();
+        let shared_new: Vec<_> = new.columns
+            .iter()
+            .filter(|c| self.col_idx(&c.id).is_some())
+            .collect();
+
+        for (pos, col) in shared_new.iter().enumerate() {
+            let old_col = &self.columns[self.col_idx(&col.id).safe_unwrap("column is shared")];
+
+            if shared_old[pos] != col.id {
+                changes.push(SchemaChange::ColumnMoved {
+                    id: col.id.clone(),
+                    from: self.col_idx(&col.id).safe_unwrap("column is shared"),
+                    to: new.col_idx(&col.id).safe_unwrap("column is shared"),
+                });
+            }
+
+            match (&old_col.ty, &col.ty) {
+                (ColumnType::Enum(old_variants), ColumnType::Enum(new_variants)) => {
+                    for variant in old_variants {
+                        if !new_variants.contains(variant) {
+                            changes.push(SchemaChange::EnumVariantRemoved {
+                                id: col.id.clone(),
+                                variant: variant.clone(),
+                            });
+                        }
+                    }
+                    for variant in new_variants {
+                        if !old_variants.contains(variant) {
+                            changes.push(SchemaChange::EnumVariantAdded {
+                                id: col.id.clone(),
+                                variant: variant.clone(),
+                            });
+                        }
+                    }
+                }
+                (old_ty, new_ty) if old_ty != new_ty => {
+                    changes.push(SchemaChange::TypeChanged {
+                        id: col.id.clone(),
+                        from: old_ty.clone(),
+                        to: new_ty.clone(),
+                    })
+                }
+                _ => (),
+            }
+
+            match (old_col.constraints.nullable, col.constraints.nullable) {
+                (false, true) => changes.push(SchemaChange::NullableAdded(col.id.clone())),
+                (true, false) => changes.push(SchemaChange::NullableRemoved(col.id.clone())),
+                _ => (),
+            }
+
+            match (old_col.constraints.unique, col.constraints.unique) {
+                (false, true) => changes.push(SchemaChange::UniqueAdded(col.id.clone())),
+                (true, false) => changes.push(SchemaChange::UniqueRemoved(col.id.clone())),
+                _ => (),
+            }
+
+            let (old_ccs, ccs) = (&old_col.constraints, &col.constraints);
+            if old_ccs.min != ccs.min {
+                changes.push(SchemaChange::MinChanged {
+                    id: col.id.clone(),
+                    from: old_ccs.min.clone(),
+                    to: ccs.min.clone(),
+                });
+            }
+            if old_ccs.max != ccs.max {
+                changes.push(SchemaChange::MaxChanged {
+                    id: col.id.clone(),
+                    from: old_ccs.max.clone(),
+                    to: ccs.max.clone(),
+                });
+            }
+            if old_ccs.max_len != ccs.max_len {
+                changes.push(SchemaChange::MaxLenChanged {
+                    id: col.id.clone(),
+                    from: old_ccs.max_len,
+                    to: ccs.max_len,
+                });
+            }
+            let old_pattern = old_ccs.pattern.as_ref().map(|p| p.as_str().to_owned());
+            let pattern = ccs.pattern.as_ref().map(|p| p.as_str().to_owned());
+            if old_pattern != pattern {
+                changes.push(SchemaChange::PatternChanged {
+                    id: col.id.clone(),
+                    from: old_pattern,
+                    to: pattern,
+                });
+            }
+
+            match (&old_col.constraints.references, &col.constraints.references) {
+                (None, Some(to)) => changes.push(SchemaChange::ReferenceAdded {
+                    id: col.id.clone(),
+                    to: to.clone(),
+                }),
+                (Some(from), None) => changes.push(SchemaChange::ReferenceRemoved {
+                    id: col.id.clone(),
+                    from: from.clone(),
+                }),
+                (Some(from), Some(to)) if from != to => {
+                    changes.push(SchemaChange::ReferenceChanged {
+                        id: col.id.clone(),
+                        from: from.clone(),
+                        to: to.clone(),
+                    })
+                }
+                _ => (),
+            }
+        }
+
+        // checks may refer to any column, moving one to another column does
+        // not change its meaning
+        let old_checks: Vec<_> = self.columns.iter().flat_map(|c| &c.constraints.checks).collect();
+        let new_checks: Vec<_> = new.columns.iter().flat_map(|c| &c.constraints.checks).collect();
+        for col in &self.columns {
+            for check in &col.constraints.checks {
+                if !new_checks.contains(&check) {
+                    changes.push(SchemaChange::CheckRemoved {
+                        id: col.id.clone(),
+                        check: check.clone(),
+                    });
+                }
+            }
+        }
+        for col in &new.columns {
+            for check in &col.constraints.checks {
+                if !old_checks.contains(&check) {
+                    changes.push(SchemaChange::CheckAdded {
+                        id: col.id.clone(),
+                        check: check.clone(),
+                    });
+                }
+            }
+        }
+
+        // keys are matched by name, the primary key being the unnamed one
+        let old_keys = self.keys();
+        let new_keys = new.keys();
+        for key in &old_keys {
+            if !new_keys.iter().any(|k| k.name == key.name) {
+                changes.push(SchemaChange::KeyRemoved(key.clone()));
+            }
+        }
+        for key in &new_keys {
+            match old_keys.iter().find(|k| k.name == key.name) {
+                None => changes.push(SchemaChange::KeyAdded(key.clone())),
+                Some(old_key) if old_key.columns != key.columns => {
+                    changes.push(SchemaChange::KeyChanged {
+                        from: old_key.clone(),
+                        to: key.clone(),
+                    })
+                }
+                _ => (),
+            }
+        }
+
+        changes
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn schema(src: &str) -> CsvxSchema {
+        CsvxSchema::from_string(
+            &("id,type,constraints,description\r\n".to_owned() + src),
+            "schema.csv",
+        ).unwrap()
+    }
+
+    #[test]
+    fn detects_changes() {
+        let old = schema(
+            "id,INTEGER,UNIQUE,\r\n\
+             name,STRING,,\r\n\
+             class,\"ENUM(MAMMAL,BIRD,INSECT)\",,\r\n\
+             weight,DECIMAL,NULLABLE,\r\n\
+             legs,INTEGER,,\r\n",
+        );
+        let new = schema(
+            "id,INTEGER,,\r\n\
+             class,\"ENUM(MAMMAL,BIRD,REPTILE)\",,\r\n\
+             name,STRING,NULLABLE,\r\n\
+             weight,DECIMAL,,\r\n\
+             legs,STRING,,\r\n\
+             caretaker,STRING,NULLABLE,\r\n",
+        );
+
+        let changes: Vec<_> = old.diff(&new)
+            .iter()
+            .map(|c| (c.to_string(), c.is_breaking()))
+            .collect();
+        assert_eq!(
+            changes,
+            vec![
+                ("column `caretaker` added".to_owned(), true),
+                ("column `id` is no longer UNIQUE".to_owned(), false),
+                ("column `class` moved from position 3 to 2".to_owned(), true),
+                ("variant `INSECT` removed from column `class`".to_owned(), true),
+                ("variant `REPTILE` added to column `class`".to_owned(), false),
+                ("column `name` moved from position 2 to 3".to_owned(), true),
+                ("column `name` is now NULLABLE".to_owned(), false),
+                ("column `weight` is no longer NULLABLE".to_owned(), true),
+                ("column `legs` changed type from INTEGER to STRING".to_owned(), false),
+            ]
+        );
+
+        assert!(old.diff(&old).is_empty());
+    }
+
+    #[test]
+    fn narrowing_types_is_breaking() {
+        let old = schema("amount,INTEGER,,\r\n");
+        let new = schema("amount,DECIMAL,,\r\n");
+
+        let changes = old.diff(&new);
+        assert_eq!(changes.len(), 1);
+        assert!(changes[0].is_breaking());
+        assert!(!new.diff(&schema("amount,STRING,,\r\n"))[0].is_breaking());
+
+        let fixed = schema("amount,\"DECIMAL(10,2)\",,\r\n");
+        assert!(new.diff(&fixed)[0].is_breaking());
+        assert!(!fixed.diff(&new)[0].is_breaking());
+    }
+
+    #[test]
+    fn limit_changes() {
+        let old = schema(
+            "weight,INTEGER,\"MIN(0),MAX(500)\",\r\n\
+             legs,INTEGER,MAX(8),\r\n\
+             cost,DECIMAL,MIN(1.5),\r\n\
+             name,STRING,\"MAXLEN(20),PATTERN('[A-Z].*')\",\r\n\
+             tag,STRING,MAXLEN(6),\r\n",
+        );
+        let new = schema(
+            "weight,INTEGER,\"MIN(1),MAX(1000)\",\r\n\
+             legs,INTEGER,,\r\n\
+             cost,DECIMAL,\"MIN(1.50),MAX(99.5)\",\r\n\
+             name,STRING,\"MAXLEN(12),PATTERN('[A-Z]''.*')\",\r\n\
+             tag,STRING,\"MAXLEN(8),PATTERN('[a-z]+')\",\r\n",
+        );
+
+        let changes: Vec<_> = old.diff(&new)
+            .iter()
+            .map(|c| (c.to_string(), c.is_breaking()))
+            .collect();
+        assert_eq!(
+            changes,
+            vec![
+                ("column `weight` changed MIN(0) to MIN(1)".to_owned(), true),
+                ("column `weight` changed MAX(500) to MAX(1000)".to_owned(), false),
+                ("column `legs` no longer has MAX(8)".to_owned(), false),
+                ("column `cost` now has MAX(99.5)".to_owned(), true),
+                ("column `name` changed MAXLEN(20) to MAXLEN(12)".to_owned(), true),
+                (
+                    "column `name` changed PATTERN('[A-Z].*') to PATTERN('[A-Z]''.*')".to_owned(),
+                    true,
+                ),
+                ("column `tag` changed MAXLEN(6) to MAXLEN(8)".to_owned(), false),
+                ("column `tag` now has PATTERN('[a-z]+')".to_owned(), true),
+            ]
+        );
+
+        let loose = schema(
+            "weight,INTEGER,,\r\n\
+             legs,INTEGER,,\r\n\
+             cost,DECIMAL,,\r\n\
+             name,STRING,,\r\n\
+             tag,STRING,,\r\n",
+        );
+        assert!(old.diff(&loose).iter().all(|c| !c.is_breaking()));
+        assert!(loose.diff(&old).iter().all(|c| c.is_breaking()));
+    }
+
+    #[test]
+    fn reference_changes() {
+        let old = schema(
+            "caretaker,INTEGER,,\r\n\
+             vet,INTEGER,REFERENCES(staff-1.id),\r\n\
+             keeper,INTEGER,REFERENCES(staff-1.id),\r\n",
+        );
+        let new = schema(
+            "caretaker,INTEGER,REFERENCES(staff-1.id),\r\n\
+             vet,INTEGER,,\r\n\
+             keeper,INTEGER,REFERENCES(staff-2.id),\r\n",
+        );
+
+        let changes: Vec<_> = old.diff(&new)
+            .iter()
+            .map(|c| (c.to_string(), c.is_breaking()))
+            .collect();
+        assert_eq!(
+            changes,
+            vec![
+                ("column `caretaker` now references `staff-1.id`".to_owned(), true),
+                ("column `vet` no longer references `staff-1.id`".to_owned(), false),
+                (
+                    "column `keeper` references `staff-2.id` instead of `staff-1.id`".to_owned(),
+                    true,
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn key_changes() {
+        let old = schema(
+            "zoo,STRING,\"PRIMARY,KEY(visit)\",\r\n\
+             id,INTEGER,\"PRIMARY,KEY(tag)\",\r\n\
+             date,DATE,KEY(visit),\r\n",
+        );
+        let new = schema(
+            "zoo,STRING,\"PRIMARY,KEY(visit)\",\r\n\
+             id,INTEGER,\"PRIMARY,KEY(visit)\",\r\n\
+             date,DATE,\"KEY(visit),KEY(day)\",\r\n",
+        );
+
+        let changes = old.diff(&new);
+        assert!(changes.iter().all(|c| c.column().is_none()));
+        let changes: Vec<_> = changes
+            .iter()
+            .map(|c| (c.to_string(), c.is_breaking()))
+            .collect();
+        assert_eq!(
+            changes,
+            vec![
+                ("key `tag` (id) removed".to_owned(), false),
+                ("key `visit` (zoo, date) changed to (zoo, id, date)".to_owned(), false),
+                ("key `day` (date) added".to_owned(), true),
+            ]
+        );
+
+        let keyless = schema("zoo,STRING,,\r\nid,INTEGER,,\r\ndate,DATE,,\r\n");
+        assert_eq!(keyless.diff(&new)[0].to_string(), "primary key (zoo, id) added");
+    }
+
+    #[test]
+    fn widened_keys_are_compatible() {
+        let narrow = schema(
+            "zoo,STRING,PRIMARY,\r\n\
+             id,INTEGER,,\r\n\
+             date,DATE,KEY(visit),\r\n",
+        );
+        let wide = schema(
+            "zoo,STRING,\"PRIMARY,KEY(visit)\",\r\n\
+             id,INTEGER,PRIMARY,\r\n\
+             date,DATE,KEY(visit),\r\n",
+        );
+
+        let changes = narrow.diff(&wide);
+        assert_eq!(changes.len(), 2);
+        assert!(changes.iter().all(|c| !c.is_breaking()));
+
+        let changes = wide.diff(&narrow);
+        assert_eq!(changes.len(), 2);
+        assert!(changes.iter().all(|c| c.is_breaking()));
+
+        // a column swapped for another is still a removal
+        let swapped = schema(
+            "zoo,STRING,PRIMARY,\r\n\
+             id,INTEGER,KEY(visit),\r\n\
+             date,DATE,,\r\n",
+        );
+        assert!(narrow.diff(&swapped)[0].is_breaking());
+    }
+
+    #[test]
+    fn check_changes() {
+        let old = schema(
+            "weight,INTEGER,CHECK(weight > 0),\r\n\
+             legs,INTEGER,CHECK(legs <= 8),\r\n\
+             wings,INTEGER,\"CHECK(wings >= 0),CHECK(wings < legs)\",\r\n",
+        );
+        let new = schema(
+            "weight,INTEGER,CHECK(weight >= 0),\r\n\
+             legs,INTEGER,CHECK(wings < legs),\r\n\
+             wings,INTEGER,CHECK(wings>=0),\r\n",
+        );
+
+        let changes: Vec<_> = old.diff(&new)
+            .iter()
+            .map(|c| (c.to_string(), c.is_breaking()))
+            .collect();
+        assert_eq!(
+            changes,
+            vec![
+                ("check `weight > 0` removed from column `weight`".to_owned(), false),
+                ("check `legs <= 8` removed from column `legs`".to_owned(), false),
+                ("check `weight >= 0` added to column `weight`".to_owned(), true),
+            ]
+        );
+    }
+}
@@ -1,4 +1,6 @@
 use csv;
+use {ColumnType, Decimal, Excerpt, Expr, ForeignKey, Source, TableKey};
+use serde::{de, ser};
 use std::{cmp, error, fmt, io};
 use std::error::Error;
 use term_painter::{Attr, Color, ToStyle};
@@ -14,13 +16,15 @@ pub trait Helpful {
 /// A location in input data
 #[derive(Clone, Debug)]
 pub enum Location {
-    // /// File, Line, Colum
-    // ///
-    // /// Note that Column refers to character columns
-    // FileLineColumn(String, usize, usize),
-    /// File, Row, Field
+    /// File, Line, Column
     ///
-    /// Fields are CSV columns (compare `FileLineColumn`)
+    /// Note that Column refers to character columns
+    FileLineColumn(String, usize, usize),
+
+    /// File, Line, Field
+    ///
+    /// Fields are CSV columns (compare `FileLineColumn`), the line is the one
+    /// the field starts on.
     FileLineField(String, usize, usize),
 
     /// File, Line
@@ -33,6 +37,19 @@ pub enum Location {
     Unspecified,
 }
 
+impl Location {
+    /// File the location is in, if any
+    pub fn file(&self) -> Option<&str> {
+        match *self {
+            Location::FileLineColumn(ref file, _, _) |
+            Location::FileLineField(ref file, _, _) |
+            Location::FileLine(ref file, _) |
+            Location::File(ref file) => Some(file),
+            Location::Unspecified => None,
+        }
+    }
+}
+
 impl Default for Location {
     fn default() -> Location {
         Location::Unspecified
@@ -42,12 +59,13 @@ impl Default for Location {
 impl fmt::Display for Location {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
-            // Location::FileLineColumn(ref file, line, col) =>
-            // write!(f, "{}:{}:{}", file, line, col),
+            Location::FileLineColumn(ref file, line, col) => {
+                write!(f, "{}:{}:{}", file, line, col)
+            }
             Location::FileLineField(ref file, row, field) => {
                 write!(f, "{}:{}[field {}]", file, row, field)
             }
-            Location::FileLine(ref file, line) => write!(f, "{}:{}]", file, line),
+            Location::FileLine(ref file, line) => write!(f, "{}:{}", file, line),
             Location::File(ref file) => write!(f, "{}", file),
             Location::Unspecified => Ok(()),
         }
@@ -107,15 +125,30 @@ impl<E, L: Default> ErrorAtLocation<E, L> {
             error: self.error.into(),
         }
     }
+
+    pub fn map_location<M, F: FnOnce(L) -> M>(self, f: F) -> ErrorAtLocation<E, M> {
+        ErrorAtLocation {
+            location: f(self.location),
+            error: self.error,
+        }
+    }
 }
 
 /// Supports printing out help
 pub trait HelpPrinter {
+    /// Print help, without an excerpt of the file
     fn print_help(&self);
+
+    /// Print help, along with an excerpt taken from `source` if given
+    fn print_help_with(&self, source: Option<&Source>);
 }
 
 impl<E: fmt::Display + Helpful> HelpPrinter for ErrorAtLocation<E, Location> {
     fn print_help(&self) {
+        self.print_help_with(None);
+    }
+
+    fn print_help_with(&self, source: Option<&Source>) {
         println!(
             "{}{} {}",
             Attr::Bold.paint((Color::Red.paint("error"))),
@@ -127,6 +160,10 @@ impl<E: fmt::Display + Helpful> HelpPrinter for ErrorAtLocation<E, Location> {
             _ => println!("  --> {}", Color::Yellow.paint(self.location())),
         }
 
+        if let Some(excerpt) = source.and_then(|s| s.excerpt(self.location())) {
+            print_excerpt(&excerpt);
+        }
+
         let dims = term_size::dimensions().unwrap_or((80, 25));
 
         let term_width = cmp::max(dims.0, 4);
@@ -138,6 +175,45 @@ impl<E: fmt::Display + Helpful> HelpPrinter for ErrorAtLocation<E, Location> {
     }
 }
 
+/// Print an excerpt in the style of rustc, underlining with carets
+fn print_excerpt(excerpt: &Excerpt) {
+    let last = excerpt.lines.iter().rposition(|l| l.is_some());
+    let width = excerpt
+        .lines
+        .iter()
+        .filter_map(|l| l.as_ref().map(|l| l.number.to_string().len()))
+        .max()
+        .map_or(2, |w| cmp::max(w, 2));
+    let gutter = |s: &str| Color::Blue.paint(Attr::Bold.paint(format!("{:>1$} |", s, width)));
+
+    println!("{}", gutter(""));
+    for (idx, line) in excerpt.lines.iter().enumerate() {
+        let line = match *line {
+            Some(ref line) => line,
+            None => {
+                println!("{}", Color::Blue.paint(Attr::Bold.paint("...")));
+                continue;
+            }
+        };
+
+        println!("{} {}", gutter(&line.number.to_string()), line.text);
+        if let Some((from, len)) = line.underline {
+            let label = match excerpt.label {
+                Some(ref label) if Some(idx) == last => format!(" {}", label),
+                _ => String::new(),
+            };
+            let carets: String = (0..len).map(|_| '^').collect();
+            println!(
+                "{} {:>3$}{}",
+                gutter(""),
+                "",
+                Color::Red.paint(Attr::Bold.paint(carets + &label)),
+                from - 1
+            );
+        }
+    }
+}
+
 impl<E, L> ErrorAtLocation<E, L> {
     pub fn error(&self) -> &E {
         &self.error
@@ -199,6 +275,9 @@ pub enum CheckError {
 
     /// Schema in data file name does not match schema
     SchemaMismatch { schema: String, data: String },
+
+    /// No key given for masking
+    MissingMaskKey,
 }
 
 impl From<SchemaLoadError> for CheckError {
@@ -240,6 +319,7 @@ impl error::Error for CheckError {
             CheckError::SchemaLoadError(_) => "could not load schema",
             CheckError::SchemaPathUtf8Error => "filename UTF8 decoding error",
             CheckError::SchemaMismatch { .. } => "schema mismatch",
+            CheckError::MissingMaskKey => "no key given for masking",
 
         }
     }
@@ -278,7 +358,9 @@ impl Helpful for CheckError {
                 used to validate its contents.\n\n\
                 Example: With a table name of `nyc-zoo`, a date of Dec 31st, \
                 2015 and using a schema named `animals-2`, the resulting \
-                filename should be `nyc-zoo_20151231_animals-2.csv`."
+                filename should be `nyc-zoo_20151231_animals-2.csv`. \
+                Compressed files carry an additional `.gzip` or `.xz` suffix, \
+                e.g. `nyc-zoo_20151231_animals-2.csv.xz`."
                     .to_owned()
             }
             CheckError::SchemaPathUtf8Error => {
@@ -297,6 +379,13 @@ impl Helpful for CheckError {
                 `zoo-nyc_20170401_animals-2.csv`."
                     .to_owned()
             }
+            CheckError::MissingMaskKey => {
+                "Masking requires a secret key, given either using `--key` or \
+                the `CSVX_MASK_KEY` environment variable. Use the same key \
+                for all files that reference each other, and keep it secret: \
+                anyone knowing it can check guesses of the original values."
+                    .to_owned()
+            }
         }
     }
 }
@@ -305,6 +394,8 @@ impl Helpful for CheckError {
 pub enum ColumnConstraintsError {
     MalformedConstraints(String),
     UnknownConstraint(String),
+    BadExpression(String, Box<ExprError>),
+    BadPattern(String, String),
 }
 
 impl fmt::Display for ColumnConstraintsError {
@@ -316,6 +407,12 @@ impl fmt::Display for ColumnConstraintsError {
             ColumnConstraintsError::UnknownConstraint(ref s) => {
                 write!(f, "unknown constraint: `{}`", s)
             }
+            ColumnConstraintsError::BadExpression(ref s, ref e) => {
+                write!(f, "invalid expression `{}`: {}", s, e)
+            }
+            ColumnConstraintsError::BadPattern(ref s, _) => {
+                write!(f, "invalid regular expression `{}`", s)
+            }
         }
     }
 }
@@ -325,11 +422,14 @@ impl error::Error for ColumnConstraintsError {
         match *self {
             ColumnConstraintsError::MalformedConstraints(_) => "malformed constraints",
             ColumnConstraintsError::UnknownConstraint(_) => "unknown constraint",
+            ColumnConstraintsError::BadExpression(_, _) => "invalid expression",
+            ColumnConstraintsError::BadPattern(_, _) => "invalid regular expression",
         }
     }
 
     fn cause(&self) -> Option<&error::Error> {
         match *self {
+            ColumnConstraintsError::BadExpression(_, ref e) => Some(e),
             _ => None,
         }
     }
@@ -341,12 +441,140 @@ impl Helpful for ColumnConstraintsError {
             ColumnConstraintsError::MalformedConstraints(_) => {
                 "The constraints could be not recognized. Constraints must be \
                 all uppercase letters, comma-separated, with no spaces in \
-                between."
+                between. Arguments are given in parentheses, as in \
+                `REFERENCES(staff-1.id)`."
                     .to_owned()
             }
             ColumnConstraintsError::UnknownConstraint(_) => {
                 "The constraint is not known to be a valid constraint. Valid \
-                constraints are `NULLABLE` and `UNIQUE`."
+                constraints are `NULLABLE`, `UNIQUE`, `PRIMARY`, `KEY(name)`, \
+                `REFERENCES(schema.column)`, `CHECK(expression)`, `MIN(n)`, \
+                `MAX(n)`, `MAXLEN(n)` and `PATTERN('regex')`."
+                    .to_owned()
+            }
+            ColumnConstraintsError::BadExpression(_, ref e) => e.help(),
+            ColumnConstraintsError::BadPattern(_, ref msg) => {
+                format!(
+                    "The argument of `PATTERN` must be a valid regular \
+                    expression, enclosed in single quotes. The regular \
+                    expression could not be compiled:\n\n{}",
+                    msg
+                )
+            }
+        }
+    }
+}
+
+/// Error in a `CHECK` expression
+#[derive(Clone, Debug)]
+pub enum ExprError {
+    /// Character that cannot start a token
+    InvalidCharacter(char),
+
+    /// Quoted string literal missing its closing quote
+    UnterminatedString,
+
+    /// Token not valid at this point of the expression
+    UnexpectedToken(String),
+
+    /// Expression ended prematurely
+    UnexpectedEnd,
+
+    /// Expression refers to a column not in the schema
+    UnknownColumn(String),
+
+    /// Two columns of types that cannot be compared
+    IncompatibleColumns(String, String),
+
+    /// Ordering comparison on a column of type `BOOL` or `ENUM`
+    Unordered(String),
+
+    /// Literal that is not a valid value of the column it is compared to
+    InvalidLiteral(String, ColumnType),
+
+    /// Comparison or `NULL` test without a column
+    NoColumn(String),
+}
+
+impl fmt::Display for ExprError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ExprError::InvalidCharacter(c) => write!(f, "invalid character `{}`", c),
+            ExprError::UnterminatedString => write!(f, "unterminated string"),
+            ExprError::UnexpectedToken(ref s) => write!(f, "unexpected `{}`", s),
+            ExprError::UnexpectedEnd => write!(f, "unexpected end of expression"),
+            ExprError::UnknownColumn(ref id) => write!(f, "unknown column `{}`", id),
+            ExprError::IncompatibleColumns(ref a, ref b) => {
+                write!(f, "columns `{}` and `{}` cannot be compared", a, b)
+            }
+            ExprError::Unordered(ref id) => write!(f, "column `{}` has no ordering", id),
+            ExprError::InvalidLiteral(ref lit, ref ty) => {
+                write!(f, "`{}` is not a valid {} value", lit, ty)
+            }
+            ExprError::NoColumn(ref s) => write!(f, "`{}` does not refer to any column", s),
+        }
+    }
+}
+
+impl error::Error for ExprError {
+    fn description(&self) -> &str {
+        match *self {
+            ExprError::InvalidCharacter(_) => "invalid character",
+            ExprError::UnterminatedString => "unterminated string",
+            ExprError::UnexpectedToken(_) => "unexpected token",
+            ExprError::UnexpectedEnd => "unexpected end of expression",
+            ExprError::UnknownColumn(_) => "unknown column",
+            ExprError::IncompatibleColumns(_, _) => "incompatible columns",
+            ExprError::Unordered(_) => "unordered column",
+            ExprError::InvalidLiteral(_, _) => "invalid literal",
+            ExprError::NoColumn(_) => "no column",
+        }
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        None
+    }
+}
+
+impl Helpful for ExprError {
+    fn help(&self) -> String {
+        match *self {
+            ExprError::InvalidCharacter(_) |
+            ExprError::UnterminatedString |
+            ExprError::UnexpectedToken(_) |
+            ExprError::UnexpectedEnd => {
+                "The expression could not be parsed. Expressions compare \
+                columns and literals using `=`, `!=`, `<`, `<=`, `>` and \
+                `>=`, test for empty values using `IS NULL` or `IS NOT NULL` \
+                and combine these using `AND`, `OR`, `NOT` and parentheses, \
+                e.g. `weight > 0 OR class = INSECT`. String literals are \
+                enclosed in single quotes."
+                    .to_owned()
+            }
+            ExprError::UnknownColumn(_) => {
+                "The expression refers to a column that is not part of the \
+                schema. Columns are referred to by their id."
+                    .to_owned()
+            }
+            ExprError::IncompatibleColumns(_, _) => {
+                "Only columns of the same type can be compared with each \
+                other, with the exception of `INTEGER` and `DECIMAL` columns."
+                    .to_owned()
+            }
+            ExprError::Unordered(_) => {
+                "Values of `BOOL` and `ENUM` columns can only be compared \
+                using `=` and `!=`."
+                    .to_owned()
+            }
+            ExprError::InvalidLiteral(_, _) => {
+                "Literals are interpreted according to the type of the column \
+                they are compared to, and must be valid values of that type, \
+                e.g. `20170101` when compared to a `DATE` column."
+                    .to_owned()
+            }
+            ExprError::NoColumn(_) => {
+                "Every comparison or `NULL` test must involve at least one \
+                column."
                     .to_owned()
             }
         }
@@ -360,6 +588,12 @@ pub enum ColumnTypeError {
 
     /// Type is intended to be an `ENUM`, but invalid
     BadEnum(String),
+
+    /// Type is intended to be a `DECIMAL(p,s)`, but invalid
+    BadDecimal(String),
+
+    /// Type is intended to be a `DATETIME` or `DATETIMETZ`, but invalid
+    BadDateTime(String),
 }
 
 impl fmt::Display for ColumnTypeError {
@@ -367,6 +601,8 @@ impl fmt::Display for ColumnTypeError {
         match *self {
             ColumnTypeError::UnknownType(ref s) => write!(f, "unknown column type `{}`", s),
             ColumnTypeError::BadEnum(ref s) => write!(f, "bad enum `{}`", s),
+            ColumnTypeError::BadDecimal(ref s) => write!(f, "bad decimal `{}`", s),
+            ColumnTypeError::BadDateTime(ref s) => write!(f, "bad datetime `{}`", s),
         }
     }
 }
@@ -376,6 +612,8 @@ impl error::Error for ColumnTypeError {
         match *self {
             ColumnTypeError::UnknownType(_) => "unknown column type",
             ColumnTypeError::BadEnum(_) => "bad enum",
+            ColumnTypeError::BadDecimal(_) => "bad decimal",
+            ColumnTypeError::BadDateTime(_) => "bad datetime",
         }
     }
 
@@ -390,7 +628,8 @@ impl Helpful for ColumnTypeError {
             ColumnTypeError::UnknownType(_) => {
                 "The column type specified is not known. Valid types are \
                 `STRING`, `BOOL`, `INTEGER`, `ENUM(...)`, `DECIMAL`, \
-                `DATE`, `DATETIME` and `TIME`"
+                `DECIMAL(p,s)`, `DATE`, `DATETIME`, `DATETIME(n)`, \
+                `DATETIMETZ`, `DATETIMETZ(n)`, `TIME` and `UUID`"
                     .to_owned()
             }
             ColumnTypeError::BadEnum(_) => {
@@ -400,6 +639,22 @@ impl Helpful for ColumnTypeError {
                 with no spaces allowed in between"
                     .to_owned()
             }
+            ColumnTypeError::BadDecimal(_) => {
+                "The `DECIMAL` specified is not valid. A `DECIMAL` with a \
+                fixed precision and scale is written as `DECIMAL(p,s)`, e.g. \
+                `DECIMAL(10,2)`, where the precision `p` is the total number \
+                of digits and the scale `s` the number of digits after the \
+                decimal point. The precision must be at least 1 and at least \
+                as large as the scale."
+                    .to_owned()
+            }
+            ColumnTypeError::BadDateTime(_) => {
+                "The `DATETIME` specified is not valid. Timestamps with \
+                fractional seconds are written as `DATETIME(n)`, timestamps \
+                with a UTC offset as `DATETIMETZ` or `DATETIMETZ(n)`, where \
+                `n` is the number of fractional digits, from 1 to 9."
+                    .to_owned()
+            }
         }
     }
 }
@@ -422,6 +677,30 @@ pub enum SchemaLoadError {
     /// The identifier is invalid
     BadIdentifier(String),
 
+    /// The identifier is used by more than one column
+    DuplicateIdentifier(String),
+
+    /// A column that is part of the primary key is `NULLABLE`
+    NullablePrimaryKey(String),
+
+    /// A `CHECK` expression of the column does not fit the schema
+    BadCheck(String, Box<ExprError>),
+
+    /// Constraint that does not apply to the column's type
+    InapplicableConstraint(String, String),
+
+    /// The default value of the column is not a valid value of the column
+    BadDefault(String, Box<ValueError>),
+
+    /// The example value of the column is not a valid value of the column
+    BadExample(String, Box<ValueError>),
+
+    /// Invalid value of a metadata field, e.g. `since`
+    BadMetadata(String, String),
+
+    /// The column is tagged `pii`, but its constraints rule out masking it
+    UnmaskableColumn(String),
+
     /// Bad column type
     BadType(ColumnTypeError),
 
@@ -433,6 +712,30 @@ impl fmt::Display for SchemaLoadError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             SchemaLoadError::BadIdentifier(ref ident) => write!(f, "bad identifier `{}`", ident),
+            SchemaLoadError::DuplicateIdentifier(ref ident) => {
+                write!(f, "duplicate identifier `{}`", ident)
+            }
+            SchemaLoadError::NullablePrimaryKey(ref ident) => {
+                write!(f, "primary key column `{}` is NULLABLE", ident)
+            }
+            SchemaLoadError::BadCheck(ref ident, ref e) => {
+                write!(f, "invalid check on column `{}`: {}", ident, e)
+            }
+            SchemaLoadError::InapplicableConstraint(ref ident, ref constraint) => {
+                write!(f, "`{}` cannot be applied to column `{}`", constraint, ident)
+            }
+            SchemaLoadError::BadDefault(ref ident, ref e) => {
+                write!(f, "invalid default for column `{}`: {}", ident, e)
+            }
+            SchemaLoadError::BadExample(ref ident, ref e) => {
+                write!(f, "invalid example for column `{}`: {}", ident, e)
+            }
+            SchemaLoadError::BadMetadata(ref field, ref value) => {
+                write!(f, "invalid {} `{}`", field, value)
+            }
+            SchemaLoadError::UnmaskableColumn(ref ident) => {
+                write!(f, "column `{}` is tagged `pii`, but cannot be masked", ident)
+            }
             _ => {
                 if let Some(cause) = self.cause() {
                     write!(f, "{}", cause)
@@ -453,6 +756,14 @@ impl error::Error for SchemaLoadError {
             SchemaLoadError::MissingHeader => "missing header",
             SchemaLoadError::BadHeader => "header is invalid",
             SchemaLoadError::BadIdentifier(_) => "bad identifier",
+            SchemaLoadError::DuplicateIdentifier(_) => "duplicate identifier",
+            SchemaLoadError::NullablePrimaryKey(_) => "nullable primary key",
+            SchemaLoadError::BadCheck(_, _) => "invalid check",
+            SchemaLoadError::InapplicableConstraint(_, _) => "inapplicable constraint",
+            SchemaLoadError::BadDefault(_, _) => "invalid default",
+            SchemaLoadError::BadExample(_, _) => "invalid example",
+            SchemaLoadError::BadMetadata(_, _) => "invalid metadata",
+            SchemaLoadError::UnmaskableColumn(_) => "unmaskable column",
             SchemaLoadError::BadType(_) => "bad type",
             SchemaLoadError::BadConstraints(_) => "invalid constraints",
         }
@@ -464,6 +775,9 @@ impl error::Error for SchemaLoadError {
             SchemaLoadError::Csv(ref e) => Some(e),
             SchemaLoadError::BadType(ref e) => Some(e),
             SchemaLoadError::BadConstraints(ref e) => Some(e),
+            SchemaLoadError::BadCheck(_, ref e) => Some(&**e),
+            SchemaLoadError::BadDefault(_, ref e) |
+            SchemaLoadError::BadExample(_, ref e) => Some(&**e),
             _ => None,
         }
     }
@@ -486,9 +800,12 @@ impl Helpful for SchemaLoadError {
             SchemaLoadError::MissingHeader => "The CSV file has no header; it's empty.".to_owned(),
             SchemaLoadError::BadHeader => {
                 "The CSV file has an invalid header. A valid header for \
-                a schema file contains exactly four fields and looks like \
+                a schema file starts with four fields and looks like \
                 this: \n\n\
-                id,type,constraints,description"
+                id,type,constraints,description\n\n\
+                It may be followed by any of the optional fields `default`, \
+                `example`, `unit`, `tags`, `deprecated` and `since`, in any \
+                order, each at most once."
                     .to_owned()
             }
             SchemaLoadError::BadIdentifier(_) => {
@@ -496,6 +813,44 @@ impl Helpful for SchemaLoadError {
                 contain only lowercase letters, numbers or underscores."
                     .to_owned()
             }
+            SchemaLoadError::DuplicateIdentifier(_) => {
+                "Another column with the same identifier has already been \
+                defined. Column identifiers must be unique within a schema."
+                    .to_owned()
+            }
+            SchemaLoadError::NullablePrimaryKey(_) => {
+                "Every row must have a primary key, so columns marked \
+                `PRIMARY` cannot be `NULLABLE` as well. Use `KEY(name)` for \
+                keys that may be incomplete."
+                    .to_owned()
+            }
+            SchemaLoadError::BadCheck(_, ref e) => e.help(),
+            SchemaLoadError::InapplicableConstraint(_, _) => {
+                "`MIN` and `MAX` only apply to `INTEGER` and `DECIMAL` columns, \
+                `MAXLEN` and `PATTERN` only to `STRING` columns."
+                    .to_owned()
+            }
+            SchemaLoadError::BadDefault(_, ref e) |
+            SchemaLoadError::BadExample(_, ref e) => {
+                format!(
+                    "Defaults and examples must be valid values of their \
+                    column, including its constraints, or left empty.\n\n{}",
+                    e.help()
+                )
+            }
+            SchemaLoadError::BadMetadata(_, _) => {
+                "Metadata fields may be left empty. Otherwise, `tags` is a \
+                comma-separated list of identifiers with underscores, \
+                `deprecated` is either `TRUE` or `FALSE` and `since` is a \
+                date, formatted as YYYYmmDD. `unit` may contain any text."
+                    .to_owned()
+            }
+            SchemaLoadError::UnmaskableColumn(_) => {
+                "Values of columns tagged `pii` are masked by replacing them \
+                with hashes, which cannot be guaranteed to match a `PATTERN`. \
+                Please remove either the tag or the pattern."
+                    .to_owned()
+            }
             SchemaLoadError::BadType(ref e) => e.help(),
             SchemaLoadError::BadConstraints(ref e) => e.help(),
         }
@@ -516,6 +871,9 @@ impl From<io::Error> for SchemaLoadError {
 
 #[derive(Debug)]
 pub enum ValidationError {
+    /// Generic IO error
+    Io(io::Error),
+
     /// Generic CSV error
     Csv(csv::Error),
 
@@ -528,13 +886,106 @@ pub enum ValidationError {
     /// A value error occured
     ValueError(ValueError),
 
+    /// Value in a `UNIQUE` column already appeared at the given location
+    DuplicateValue(String, Location),
+
+    /// Combination of values of a key already appeared at the given location
+    DuplicateKey(Box<TableKey>, Vec<String>, Location),
+
+    /// The row does not satisfy a `CHECK` constraint
+    CheckFailed(Expr),
+
+    /// The row has a different number of fields than the schema has columns,
+    /// expected and actual count
+    FieldCount(usize, usize),
+
+    /// The file violates the csvx format rules
+    FormatError(FormatError),
+
+    /// A row could not be converted from or into a user-defined type
+    Serde(String),
+
+    /// No schema with the given name has been loaded
+    UnknownSchema(String),
+
+    /// The column referenced by a `REFERENCES` constraint does not exist
+    UnresolvedReference(ForeignKey),
+
+    /// Value does not appear in the referenced column
+    DanglingReference(String, ForeignKey),
+
+    /// The referenced column is of an incompatible type, with the types of
+    /// the referencing and the referenced column
+    IncompatibleReference(ForeignKey, ColumnType, ColumnType),
+
     /// The request conversion or operation is not possible.
     SchemaMismatch,
 }
 
+impl ValidationError {
+    /// Short, machine-readable identifier of the kind of error
+    ///
+    /// Value and format errors are identified by their specific kind.
+    pub fn kind(&self) -> &'static str {
+        match *self {
+            ValidationError::Io(_) => "io",
+            ValidationError::Csv(_) => "csv",
+            ValidationError::MissingHeaders => "missing_headers",
+            ValidationError::HeaderMismatch(_) => "header_mismatch",
+            ValidationError::ValueError(ref e) => e.kind(),
+            ValidationError::DuplicateValue(_, _) => "duplicate_value",
+            ValidationError::DuplicateKey(_, _, _) => "duplicate_key",
+            ValidationError::CheckFailed(_) => "check_failed",
+            ValidationError::FieldCount(_, _) => "field_count",
+            ValidationError::FormatError(ref e) => e.kind(),
+            ValidationError::Serde(_) => "serde",
+            ValidationError::UnknownSchema(_) => "unknown_schema",
+            ValidationError::UnresolvedReference(_) => "unresolved_reference",
+            ValidationError::DanglingReference(_, _) => "dangling_reference",
+            ValidationError::IncompatibleReference(_, _, _) => "incompatible_reference",
+            ValidationError::SchemaMismatch => "schema_mismatch",
+        }
+    }
+}
+
 impl fmt::Display for ValidationError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
+            ValidationError::DuplicateValue(ref s, ref first) => {
+                write!(f, "duplicate value `{}`, first seen at {}", s, first)
+            }
+            ValidationError::DuplicateKey(ref key, ref values, ref first) => {
+                write!(
+                    f,
+                    "duplicate value `({})` for {}, first seen at {}",
+                    values.join(", "),
+                    key,
+                    first
+                )
+            }
+            ValidationError::CheckFailed(ref expr) => write!(f, "check `{}` failed", expr),
+            ValidationError::FieldCount(expected, actual) => {
+                write!(f, "expected {} fields, found {}", expected, actual)
+            }
+            ValidationError::Serde(ref msg) => write!(f, "{}", msg),
+            ValidationError::UnknownSchema(ref name) => {
+                write!(f, "no schema named `{}` loaded", name)
+            }
+            ValidationError::UnresolvedReference(ref fk) => {
+                write!(f, "referenced column `{}` not found", fk)
+            }
+            ValidationError::DanglingReference(ref s, ref fk) => {
+                write!(f, "value `{}` not found in `{}`", s, fk)
+            }
+            ValidationError::IncompatibleReference(ref fk, ref ty, ref target_ty) => {
+                write!(
+                    f,
+                    "{} column cannot reference `{}` of type {}",
+                    ty,
+                    fk,
+                    target_ty
+                )
+            }
             _ => {
                 if let Some(cause) = self.cause() {
                     write!(f, "{}", cause)
@@ -550,18 +1001,31 @@ impl fmt::Display for ValidationError {
 impl error::Error for ValidationError {
     fn description(&self) -> &str {
         match *self {
+            ValidationError::Io(_) => "IO error",
             ValidationError::Csv(_) => "invalid CSV",
             ValidationError::MissingHeaders => "missing headers",
             ValidationError::HeaderMismatch(_) => "header mismatch",
             ValidationError::ValueError(_) => "value error",
+            ValidationError::DuplicateValue(_, _) => "duplicate value",
+            ValidationError::DuplicateKey(_, _, _) => "duplicate key",
+            ValidationError::CheckFailed(_) => "check failed",
+            ValidationError::FieldCount(_, _) => "wrong number of fields",
+            ValidationError::FormatError(_) => "format error",
+            ValidationError::Serde(_) => "conversion error",
+            ValidationError::UnknownSchema(_) => "unknown schema",
+            ValidationError::UnresolvedReference(_) => "unresolved reference",
+            ValidationError::DanglingReference(_, _) => "dangling reference",
+            ValidationError::IncompatibleReference(_, _, _) => "incompatible reference",
             ValidationError::SchemaMismatch => "schema mismatch",
         }
     }
 
     fn cause(&self) -> Option<&error::Error> {
         match *self {
+            ValidationError::Io(ref e) => Some(e),
             ValidationError::Csv(ref e) => Some(e),
             ValidationError::ValueError(ref e) => Some(e),
+            ValidationError::FormatError(ref e) => Some(e),
             _ => None,
         }
     }
@@ -576,6 +1040,7 @@ impl From<ValueError> for ValidationError {
 impl Helpful for ValidationError {
     fn help(&self) -> String {
         match *self {
+            ValidationError::Io(_) => "There was an error accessing the CSV file.".to_owned(),
             ValidationError::Csv(_) => {
                 "An error occured parsing the CSV fragment. Please ensure \
                 the CSV file is valid CSVX and RFC4180."
@@ -588,6 +1053,64 @@ impl Helpful for ValidationError {
                 "A header did not match the one specified.".to_owned()
             }
             ValidationError::ValueError(ref e) => e.help(),
+            ValidationError::DuplicateValue(_, _) => {
+                "The column is marked `UNIQUE`, but the value already appeared \
+                in a previous row. Every non-empty value in a `UNIQUE` column \
+                must occur at most once per file."
+                    .to_owned()
+            }
+            ValidationError::DuplicateKey(_, _, _) => {
+                "The columns of the key must be unique in combination, but \
+                the same combination of values already appeared in a previous \
+                row. Rows with an empty value in any of the key's columns are \
+                not checked."
+                    .to_owned()
+            }
+            ValidationError::CheckFailed(_) => {
+                "The row does not satisfy a `CHECK` constraint of the schema. \
+                Comparisons involving empty values are neither true nor \
+                false, a check only fails if its expression is false."
+                    .to_owned()
+            }
+            ValidationError::FieldCount(_, _) => {
+                "Every row must have exactly one field per column of the \
+                schema, the same number as the header. Empty values still \
+                need their field, e.g. `1,,Sam` instead of `1,Sam`. Fields \
+                containing commas must be quoted."
+                    .to_owned()
+            }
+            ValidationError::FormatError(ref e) => e.help(),
+            ValidationError::Serde(_) => {
+                "The row could not be converted from or to the requested type. \
+                Fields are matched by column id, please ensure that their names and \
+                types match the schema."
+                    .to_owned()
+            }
+            ValidationError::UnknownSchema(_) => {
+                "The file's schema was not among the schemas supplied. Please \
+                pass every schema used by the files being checked."
+                    .to_owned()
+            }
+            ValidationError::UnresolvedReference(_) => {
+                "The column has a `REFERENCES` constraint, but either the \
+                referenced schema has not been supplied or it has no column \
+                with the given id."
+                    .to_owned()
+            }
+            ValidationError::DanglingReference(_, _) => {
+                "The column has a `REFERENCES` constraint, but the value does \
+                not appear in the referenced column of any of the files \
+                checked. Please ensure all files of the referenced table are \
+                included."
+                    .to_owned()
+            }
+            ValidationError::IncompatibleReference(_, _, _) => {
+                "The column has a `REFERENCES` constraint, but its type differs \
+                from that of the referenced column. Both columns must have the \
+                same base type, only precision, scale and `ENUM` variants may \
+                differ."
+                    .to_owned()
+            }
             ValidationError::SchemaMismatch => {
                 "The schema used loaded does not match the API call. This is \
                 most likely a programming error."
@@ -598,12 +1121,160 @@ impl Helpful for ValidationError {
     }
 }
 
+impl From<io::Error> for ValidationError {
+    fn from(e: io::Error) -> ValidationError {
+        ValidationError::Io(e)
+    }
+}
+
 impl From<csv::Error> for ValidationError {
     fn from(e: csv::Error) -> ValidationError {
         ValidationError::Csv(e)
     }
 }
 
+impl de::Error for ValidationError {
+    fn custom<T: fmt::Display>(msg: T) -> ValidationError {
+        ValidationError::Serde(msg.to_string())
+    }
+}
+
+impl ser::Error for ValidationError {
+    fn custom<T: fmt::Display>(msg: T) -> ValidationError {
+        ValidationError::Serde(msg.to_string())
+    }
+}
+
+impl From<FormatError> for ValidationError {
+    fn from(e: FormatError) -> ValidationError {
+        ValidationError::FormatError(e)
+    }
+}
+
+/// Violation of the byte-level csvx format rules
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum FormatError {
+    /// File starts with a byte order mark
+    ByteOrderMark,
+
+    /// File is not valid UTF-8
+    InvalidUtf8,
+
+    /// Text is not normalized to NFC
+    NotNfc,
+
+    /// Line is terminated by `\n` instead of `\r\n`
+    BareLineFeed,
+
+    /// Line is terminated by `\r` instead of `\r\n`
+    BareCarriageReturn,
+
+    /// Line contains no fields at all
+    EmptyLine,
+
+    /// Last line is not terminated by `\r\n`
+    MissingFinalLineBreak,
+
+    /// Field is quoted although it contains no characters requiring quotes
+    UnnecessaryQuotes,
+
+    /// Quotes are unbalanced or appear in the middle of a field
+    MalformedQuoting,
+}
+
+impl FormatError {
+    /// Short, machine-readable identifier of the kind of error
+    pub fn kind(&self) -> &'static str {
+        match *self {
+            FormatError::ByteOrderMark => "byte_order_mark",
+            FormatError::InvalidUtf8 => "invalid_utf8",
+            FormatError::NotNfc => "not_nfc",
+            FormatError::BareLineFeed => "bare_line_feed",
+            FormatError::BareCarriageReturn => "bare_carriage_return",
+            FormatError::EmptyLine => "empty_line",
+            FormatError::MissingFinalLineBreak => "missing_final_line_break",
+            FormatError::UnnecessaryQuotes => "unnecessary_quotes",
+            FormatError::MalformedQuoting => "malformed_quoting",
+        }
+    }
+}
+
+impl fmt::Display for FormatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.description())
+    }
+}
+
+impl error::Error for FormatError {
+    fn description(&self) -> &str {
+        match *self {
+            FormatError::ByteOrderMark => "byte order mark found",
+            FormatError::InvalidUtf8 => "invalid UTF-8",
+            FormatError::NotNfc => "text not normalized to NFC",
+            FormatError::BareLineFeed => "line terminated by bare `\\n`",
+            FormatError::BareCarriageReturn => "line terminated by bare `\\r`",
+            FormatError::EmptyLine => "empty line",
+            FormatError::MissingFinalLineBreak => "missing line break at end of file",
+            FormatError::UnnecessaryQuotes => "unnecessary quotes",
+            FormatError::MalformedQuoting => "malformed quoting",
+        }
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        None
+    }
+}
+
+impl Helpful for FormatError {
+    fn help(&self) -> String {
+        match *self {
+            FormatError::ByteOrderMark => {
+                "The file starts with a byte order mark (BOM). CSVX files must \
+                be UTF-8 encoded without a byte order mark; please save the \
+                file again without it."
+                    .to_owned()
+            }
+            FormatError::InvalidUtf8 => {
+                "The file contains bytes that are not valid UTF-8. CSVX files \
+                must be UTF-8 encoded; other encodings like Latin-1 or \
+                Windows-1252 are not allowed."
+                    .to_owned()
+            }
+            FormatError::NotNfc => {
+                "The line contains text that is not normalized to Unicode \
+                normalization form C (NFC). A common cause are accented \
+                characters written as a base letter followed by a combining \
+                accent instead of a single precomposed character."
+                    .to_owned()
+            }
+            FormatError::BareLineFeed | FormatError::BareCarriageReturn => {
+                "Lines in CSVX files must be terminated by a carriage return \
+                followed by a line feed (`\\r\\n`), even on systems that use \
+                other line endings natively."
+                    .to_owned()
+            }
+            FormatError::EmptyLine => "CSVX files must not contain empty lines.".to_owned(),
+            FormatError::MissingFinalLineBreak => {
+                "The last line of a CSVX file must be terminated by `\\r\\n` \
+                as well."
+                    .to_owned()
+            }
+            FormatError::UnnecessaryQuotes => {
+                "CSVX requires minimal quoting: Only fields containing line \
+                breaks, quotation marks or commas may be quoted."
+                    .to_owned()
+            }
+            FormatError::MalformedQuoting => {
+                "The quotes in this field are malformed. Quoted fields must \
+                start and end with a quotation mark `\"`, quotation marks \
+                inside them must be escaped by doubling them (`\"\"`) and \
+                unquoted fields must not contain quotation marks at all."
+                    .to_owned()
+            }
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum ValueError {
     /// A field that was not NULLABLE had no value
@@ -627,10 +1298,66 @@ pub enum ValueError {
     /// Invalid datetime value
     InvalidDateTime(String),
 
+    /// Invalid datetime value with a UTC offset
+    InvalidDateTimeTz(String),
+
     /// Invalid time value
     InvalidTime(String),
 
-    // FIXME: Add OutOfRange and other errors
+    /// Invalid or non-canonical UUID
+    InvalidUuid(String),
+
+    /// Value does not match the column type
+    WrongType(String),
+
+    /// Decimal with a different number of fractional digits than the
+    /// declared scale
+    WrongScale(String, u32),
+
+    /// Datetime with a different number of fractional second digits than
+    /// declared
+    WrongFraction(String, u32),
+
+    /// Decimal with more digits than the declared precision
+    PrecisionExceeded(String, u32, u32),
+
+    /// Number outside of the range given by `MIN` and `MAX`
+    OutOfRange(String, Option<Decimal>, Option<Decimal>),
+
+    /// String longer (in characters) than allowed by `MAXLEN`
+    TooLong(usize, usize),
+
+    /// String not matching the `PATTERN`
+    PatternMismatch(String, String),
+
+    /// Masked replacement of a value violating the column's constraints
+    Unmaskable(Box<ValueError>),
+}
+
+impl ValueError {
+    /// Short, machine-readable identifier of the kind of error
+    pub fn kind(&self) -> &'static str {
+        match *self {
+            ValueError::NonNullable => "non_nullable",
+            ValueError::InvalidBool(_) => "invalid_bool",
+            ValueError::InvalidInt(_) => "invalid_int",
+            ValueError::InvalidEnum(_, _) => "invalid_enum",
+            ValueError::InvalidDecimal(_) => "invalid_decimal",
+            ValueError::InvalidDate(_) => "invalid_date",
+            ValueError::InvalidDateTime(_) => "invalid_datetime",
+            ValueError::InvalidDateTimeTz(_) => "invalid_datetimetz",
+            ValueError::InvalidTime(_) => "invalid_time",
+            ValueError::InvalidUuid(_) => "invalid_uuid",
+            ValueError::WrongType(_) => "wrong_type",
+            ValueError::WrongScale(_, _) => "wrong_scale",
+            ValueError::WrongFraction(_, _) => "wrong_fraction",
+            ValueError::PrecisionExceeded(_, _, _) => "precision_exceeded",
+            ValueError::OutOfRange(_, _, _) => "out_of_range",
+            ValueError::TooLong(_, _) => "too_long",
+            ValueError::PatternMismatch(_, _) => "pattern_mismatch",
+            ValueError::Unmaskable(_) => "unmaskable",
+        }
+    }
 }
 
 impl fmt::Display for ValueError {
@@ -644,7 +1371,40 @@ impl fmt::Display for ValueError {
             ValueError::InvalidDecimal(ref s) => write!(f, "could not parse ` {}` as DECIMAL", s),
             ValueError::InvalidDate(ref s) => write!(f, "could not parse `{}` as DATE", s),
             ValueError::InvalidDateTime(ref s) => write!(f, "could not parse ` {}` as DATETIME", s),
+            ValueError::InvalidDateTimeTz(ref s) => {
+                write!(f, "could not parse `{}` as DATETIMETZ", s)
+            }
             ValueError::InvalidTime(ref s) => write!(f, "could not parse `{}` as TIME", s),
+            ValueError::InvalidUuid(ref s) => write!(f, "`{}` is not a canonical UUID", s),
+            ValueError::WrongType(ref ty) => write!(f, "value is not of type {}", ty),
+            ValueError::WrongFraction(ref s, 0) => {
+                write!(f, "`{}` must not have fractional seconds", s)
+            }
+            ValueError::WrongFraction(ref s, digits) => {
+                write!(f, "`{}` does not have exactly {} fractional second digits", s, digits)
+            }
+            ValueError::WrongScale(ref s, scale) => {
+                write!(f, "`{}` does not have exactly {} fractional digits", s, scale)
+            }
+            ValueError::PrecisionExceeded(ref s, precision, scale) => {
+                write!(f, "`{}` exceeds DECIMAL({},{})", s, precision, scale)
+            }
+            ValueError::OutOfRange(ref s, ref min, ref max) => {
+                write!(f, "`{}` is out of range, ", s)?;
+                match (min, max) {
+                    (Some(min), Some(max)) => write!(f, "must be between {} and {}", min, max),
+                    (Some(min), None) => write!(f, "must be at least {}", min),
+                    (None, Some(max)) => write!(f, "must be at most {}", max),
+                    (None, None) => write!(f, "no range given"),
+                }
+            }
+            ValueError::TooLong(len, max) => {
+                write!(f, "value is {} characters long, at most {} allowed", len, max)
+            }
+            ValueError::PatternMismatch(ref s, ref pattern) => {
+                write!(f, "`{}` does not match pattern `{}`", s, pattern)
+            }
+            ValueError::Unmaskable(ref e) => write!(f, "cannot mask value: {}", e),
             _ => write!(f, "{}", self.description()),
         }
     }
@@ -660,7 +1420,17 @@ impl error::Error for ValueError {
             ValueError::InvalidDecimal(_) => "invalid decimal",
             ValueError::InvalidDate(_) => "invalid date",
             ValueError::InvalidDateTime(_) => "invalid datetime",
+            ValueError::InvalidDateTimeTz(_) => "invalid datetime with offset",
             ValueError::InvalidTime(_) => "invalid time",
+            ValueError::InvalidUuid(_) => "invalid uuid",
+            ValueError::WrongType(_) => "wrong type",
+            ValueError::WrongFraction(_, _) => "wrong fractional seconds",
+            ValueError::WrongScale(_, _) => "wrong scale",
+            ValueError::PrecisionExceeded(_, _, _) => "precision exceeded",
+            ValueError::OutOfRange(_, _, _) => "out of range",
+            ValueError::TooLong(_, _) => "value too long",
+            ValueError::PatternMismatch(_, _) => "pattern mismatch",
+            ValueError::Unmaskable(_) => "cannot mask value",
         }
     }
 
@@ -717,7 +1487,18 @@ impl Helpful for ValueError {
                 Ex: The 31st of Dec 2015, 23:01:58 would be encoded as \
                 `20151231230158`.\n\n
                 Otherwise datetimes must correspond to valid calendar dates \
-                and clock times."
+                and clock times. Columns declared as `DATETIME(n)` have \
+                exactly `n` fractional second digits after a dot `.`, e.g. \
+                `20151231230158.250` for `DATETIME(3)`."
+                    .to_owned()
+            }
+            ValueError::InvalidDateTimeTz(_) => {
+                "The value is not a valid DATETIMETZ. These values are \
+                formatted like a DATETIME, followed by the offset from UTC: \
+                Either `Z` for UTC itself, or `+HHMM` / `-HHMM`.\n\n\
+                Ex: The 31st of Dec 2015, 23:01:58 in UTC would be encoded as \
+                `20151231230158Z`, the same time in Central European Time as \
+                `20151231230158+0100`."
                     .to_owned()
             }
             ValueError::InvalidTime(_) => {
@@ -728,11 +1509,75 @@ impl Helpful for ValueError {
                 Times must be valid clock times."
                     .to_owned()
             }
+            ValueError::InvalidUuid(_) => {
+                "The value is not a valid UUID in canonical form. UUIDs must \
+                be written as 32 lowercase hexadecimal digits, in groups of \
+                8, 4, 4, 4 and 12, separated by hyphens `-`. Braces, a `urn:` \
+                prefix or uppercase digits are not allowed.\n\n\
+                Ex: `936da01f-9abd-4d9d-80c7-02af85c822a8`."
+                    .to_owned()
+            }
             ValueError::NonNullable => {
                 "The field was not marked as `NULLABLE`, but did not contain \
                 a value."
                     .to_owned()
             }
+            ValueError::WrongType(_) => {
+                "The value passed in does not match the type of the column \
+                it is written to. This is most likely a programming error."
+                    .to_owned()
+            }
+            ValueError::WrongFraction(_, digits) => {
+                format!(
+                    "The column is declared with {} fractional second digits. \
+                    Every value must have exactly that many digits after the \
+                    seconds, separated by a dot `.`, e.g. `20151231230158.250` \
+                    for three digits. Columns without fractional digits must \
+                    not have a fractional part at all.",
+                    digits
+                )
+            }
+            ValueError::WrongScale(_, scale) => {
+                format!(
+                    "The column is declared with a fixed scale, every value \
+                    must have exactly {} digits after the decimal point, \
+                    padded with zeros if necessary.",
+                    scale
+                )
+            }
+            ValueError::PrecisionExceeded(_, precision, scale) => {
+                format!(
+                    "The value has too many digits. The column allows at most \
+                    {} digits in total, {} of them after the decimal point.",
+                    precision,
+                    scale
+                )
+            }
+            ValueError::OutOfRange(_, _, _) => {
+                "The value is outside of the range given by the `MIN` and \
+                `MAX` constraints of the column. Both limits are inclusive."
+                    .to_owned()
+            }
+            ValueError::TooLong(_, _) => {
+                "The value is longer than allowed by the `MAXLEN` constraint \
+                of the column. Length is measured in characters, not bytes."
+                    .to_owned()
+            }
+            ValueError::PatternMismatch(_, _) => {
+                "The value does not match the regular expression given by the \
+                `PATTERN` constraint of the column. The whole value must \
+                match, not just a part of it."
+                    .to_owned()
+            }
+            ValueError::Unmaskable(_) => {
+                "Masked values only depend on the original value and its type, \
+                so that equal values are masked equally in every column and \
+                file. The replacement does not satisfy the constraints of \
+                this column; `MIN`, `MAX`, `MAXLEN`, `PATTERN` and fixed \
+                precision limit which columns can be masked. Loosen the \
+                constraints or remove the `pii` tag."
+                    .to_owned()
+            }
         }
     }
 }
@@ -0,0 +1,685 @@
+//! Row check expressions, as used by `CHECK(...)` constraints
+//!
+//! The grammar, from lowest to highest precedence:
+//!
+//! ```text
+//! expr    = and ("OR" and)*
+//! and     = not ("AND" not)*
+//! not     = "NOT" not | primary
+//! primary = "(" expr ")" | operand "IS" ["NOT"] "NULL" | operand cmp operand
+//! cmp     = "=" | "!=" | "<" | "<=" | ">" | ">="
+//! operand = column id | number | uppercase word | 'quoted string'
+//! ```
+//!
+//! Literals are interpreted according to the type of the column they are
+//! compared to, e.g. `20170101` is a date when compared to a `DATE` column.
+//! `NULL` values make comparisons unknown, following SQL; a check only fails
+//! if it evaluates to false.
+
+use err::ExprError;
+use std::{cmp, fmt};
+use super::{ColumnType, CsvxColumnType, Decimal, Value};
+
+const KEYWORDS: &[&str] = &["AND", "IS", "NOT", "NULL", "OR"];
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum CmpOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl CmpOp {
+    fn is_ordering(&self) -> bool {
+        !matches!(*self, CmpOp::Eq | CmpOp::Ne)
+    }
+
+    fn matches(&self, ord: cmp::Ordering) -> bool {
+        match *self {
+            CmpOp::Eq => ord == cmp::Ordering::Equal,
+            CmpOp::Ne => ord != cmp::Ordering::Equal,
+            CmpOp::Lt => ord == cmp::Ordering::Less,
+            CmpOp::Le => ord != cmp::Ordering::Greater,
+            CmpOp::Gt => ord == cmp::Ordering::Greater,
+            CmpOp::Ge => ord != cmp::Ordering::Less,
+        }
+    }
+}
+
+impl fmt::Display for CmpOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match *self {
+                CmpOp::Eq => "=",
+                CmpOp::Ne => "!=",
+                CmpOp::Lt => "<",
+                CmpOp::Le => "<=",
+                CmpOp::Gt => ">",
+                CmpOp::Ge => ">=",
+            }
+        )
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Operand {
+    /// Value of the column with the given id
+    Column(String),
+
+    /// Literal, not yet interpreted
+    Literal(String),
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Operand::Column(ref id) => write!(f, "{}", id),
+            Operand::Literal(ref s) => {
+                // only quote if the literal would not be read back as one
+                match tokenize(s).as_ref().map(|t| t.as_slice()) {
+                    Ok([Token::Number(_)]) => write!(f, "{}", s),
+                    Ok([Token::Word(ref w)]) if !KEYWORDS.contains(&w.as_str()) => {
+                        write!(f, "{}", s)
+                    }
+                    _ => write!(f, "'{}'", s.replace('\'', "''")),
+                }
+            }
+        }
+    }
+}
+
+/// A parsed check expression
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Expr {
+    Cmp(Operand, CmpOp, Operand),
+
+    /// `IS NULL`, or `IS NOT NULL` if the flag is set
+    IsNull(Operand, bool),
+
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    /// Parse an expression
+    pub fn parse(src: &str) -> Result<Expr, ExprError> {
+        let mut parser = Parser {
+            tokens: tokenize(src)?,
+            pos: 0,
+        };
+
+        let expr = parser.parse_or()?;
+        match parser.next() {
+            None => Ok(expr),
+            Some(tok) => Err(ExprError::UnexpectedToken(tok.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Expr::Cmp(ref lhs, op, ref rhs) => write!(f, "{} {} {}", lhs, op, rhs),
+            Expr::IsNull(ref operand, false) => write!(f, "{} IS NULL", operand),
+            Expr::IsNull(ref operand, true) => write!(f, "{} IS NOT NULL", operand),
+            Expr::Not(ref e) => {
+                match **e {
+                    Expr::And(..) | Expr::Or(..) => write!(f, "NOT ({})", e),
+                    _ => write!(f, "NOT {}", e),
+                }
+            }
+            Expr::And(ref a, ref b) => {
+                for (i, e) in [a, b].iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " AND ")?;
+                    }
+                    match ***e {
+                        Expr::Or(..) => write!(f, "({})", e)?,
+                        _ => write!(f, "{}", e)?,
+                    }
+                }
+                Ok(())
+            }
+            Expr::Or(ref a, ref b) => write!(f, "{} OR {}", a, b),
+        }
+    }
+}
+
+/// Resolve columns and literals against the columns of a schema
+pub fn compile(expr: &Expr, columns: &[CsvxColumnType]) -> Result<Compiled, ExprError> {
+    let column = |id: &str| {
+        columns.iter().position(|c| c.id == id).ok_or_else(|| {
+            ExprError::UnknownColumn(id.to_owned())
+        })
+    };
+
+    Ok(match *expr {
+        Expr::Cmp(ref lhs, op, ref rhs) => {
+            let (l, r) = match (lhs, rhs) {
+                (Operand::Column(a), Operand::Column(b)) => {
+                    let (a_idx, b_idx) = (column(a)?, column(b)?);
+                    let (a_ty, b_ty) = (&columns[a_idx].ty, &columns[b_idx].ty);
+                    if a_ty != b_ty && !(is_numeric(a_ty) && is_numeric(b_ty)) {
+                        return Err(ExprError::IncompatibleColumns(a.clone(), b.clone()));
+                    }
+                    (Term::Column(a_idx), Term::Column(b_idx))
+                }
+                (Operand::Column(id), Operand::Literal(lit)) => {
+                    let idx = column(id)?;
+                    (Term::Column(idx), Term::Value(literal(&columns[idx].ty, lit)?))
+                }
+                (Operand::Literal(lit), Operand::Column(id)) => {
+                    let idx = column(id)?;
+                    (Term::Value(literal(&columns[idx].ty, lit)?), Term::Column(idx))
+                }
+                (Operand::Literal(_), Operand::Literal(_)) => {
+                    return Err(ExprError::NoColumn(expr.to_string()));
+                }
+            };
+
+            if op.is_ordering() {
+                for operand in &[lhs, rhs] {
+                    if let Operand::Column(ref id) = **operand {
+                        match columns[column(id)?].ty {
+                            ColumnType::Bool | ColumnType::Enum(_) | ColumnType::Uuid => {
+                                return Err(ExprError::Unordered(id.clone()))
+                            }
+                            _ => (),
+                        }
+                    }
+                }
+            }
+
+            Compiled::Cmp(l, op, r)
+        }
+        Expr::IsNull(Operand::Column(ref id), negated) => {
+            Compiled::IsNull(column(id)?, negated)
+        }
+        Expr::IsNull(Operand::Literal(_), _) => {
+            return Err(ExprError::NoColumn(expr.to_string()));
+        }
+        Expr::Not(ref e) => Compiled::Not(Box::new(compile(e, columns)?)),
+        Expr::And(ref a, ref b) => {
+            Compiled::And(Box::new(compile(a, columns)?), Box::new(compile(b, columns)?))
+        }
+        Expr::Or(ref a, ref b) => {
+            Compiled::Or(Box::new(compile(a, columns)?), Box::new(compile(b, columns)?))
+        }
+    })
+}
+
+/// Operand of a compiled comparison
+#[derive(Clone, Debug)]
+pub enum Term {
+    /// Index of a column
+    Column(usize),
+    Value(Value),
+}
+
+impl Term {
+    fn value<'a>(&'a self, row: &'a [Option<Value>]) -> Option<&'a Value> {
+        match *self {
+            Term::Column(idx) => row.get(idx).and_then(|v| v.as_ref()),
+            Term::Value(ref v) => Some(v),
+        }
+    }
+}
+
+/// An expression checked against a schema, ready for evaluation
+#[derive(Clone, Debug)]
+pub enum Compiled {
+    Cmp(Term, CmpOp, Term),
+    IsNull(usize, bool),
+    Not(Box<Compiled>),
+    And(Box<Compiled>, Box<Compiled>),
+    Or(Box<Compiled>, Box<Compiled>),
+}
+
+impl Compiled {
+    /// Evaluate against a row of values
+    ///
+    /// Returns `None` if the result is unknown because of `NULL` values.
+    pub fn eval(&self, row: &[Option<Value>]) -> Option<bool> {
+        match *self {
+            Compiled::Cmp(ref lhs, op, ref rhs) => {
+                compare(lhs.value(row)?, rhs.value(row)?).map(|ord| op.matches(ord))
+            }
+            Compiled::IsNull(idx, negated) => {
+                Some(row.get(idx).and_then(|v| v.as_ref()).is_none() != negated)
+            }
+            Compiled::Not(ref e) => e.eval(row).map(|b| !b),
+            Compiled::And(ref a, ref b) => {
+                match (a.eval(row), b.eval(row)) {
+                    (Some(false), _) | (_, Some(false)) => Some(false),
+                    (Some(true), Some(true)) => Some(true),
+                    _ => None,
+                }
+            }
+            Compiled::Or(ref a, ref b) => {
+                match (a.eval(row), b.eval(row)) {
+                    (Some(true), _) | (_, Some(true)) => Some(true),
+                    (Some(false), Some(false)) => Some(false),
+                    _ => None,
+                }
+            }
+        }
+    }
+}
+
+/// A `CHECK` constraint of a schema
+#[derive(Clone, Debug)]
+pub struct Check {
+    /// Index of the column the constraint was declared on
+    pub column: usize,
+    pub expr: Expr,
+    pub compiled: Compiled,
+}
+
+fn is_numeric(ty: &ColumnType) -> bool {
+    matches!(*ty, ColumnType::Integer | ColumnType::Decimal(_))
+}
+
+/// Interpret a literal as a value of the given type
+///
+/// Precision and scale of the type are ignored, e.g. `0` is a valid literal
+/// for a `DECIMAL(10,2)` column. Comparisons are numeric regardless.
+fn literal(ty: &ColumnType, lit: &str) -> Result<Value, ExprError> {
+    let invalid = || ExprError::InvalidLiteral(lit.to_owned(), ty.clone());
+
+    // the empty string is a valid string, even if it would be read as NULL
+    if *ty == ColumnType::String {
+        return Ok(Value::String(lit.to_owned()));
+    }
+
+    // datetimes may have any number of fractional second digits
+    let candidates = match *ty {
+        ColumnType::Decimal(_) => vec![ColumnType::Decimal(None)],
+        ColumnType::DateTime(_) => (0..10).map(ColumnType::DateTime).collect(),
+        ColumnType::DateTimeTz(_) => (0..10).map(ColumnType::DateTimeTz).collect(),
+        ref ty => vec![ty.clone()],
+    };
+    candidates
+        .into_iter()
+        .filter_map(|ty| CsvxColumnType::new("", ty).validate_value(&lit).ok())
+        .next()
+        .and_then(|v| v)
+        .ok_or_else(invalid)
+}
+
+/// Compare two values of compatible types
+fn compare(a: &Value, b: &Value) -> Option<cmp::Ordering> {
+    match (a, b) {
+        (Value::String(a), Value::String(b)) => Some(a.cmp(b)),
+        (Value::Bool(a), Value::Bool(b)) => Some(a.cmp(b)),
+        (Value::Integer(a), Value::Integer(b)) => Some(a.cmp(b)),
+        (Value::Enum(a), Value::Enum(b)) => Some(a.cmp(b)),
+        (Value::Decimal(a), Value::Decimal(b)) => Some(a.cmp(b)),
+        (Value::Integer(a), Value::Decimal(b)) => Some(Decimal::from(*a).cmp(b)),
+        (Value::Decimal(a), Value::Integer(b)) => Some(a.cmp(&Decimal::from(*b))),
+        (Value::Date(a), Value::Date(b)) => Some(a.cmp(b)),
+        (Value::DateTime(a), Value::DateTime(b)) => Some(a.cmp(b)),
+        (Value::DateTimeTz(a), Value::DateTimeTz(b)) => Some(a.cmp(b)),
+        (Value::Time(a), Value::Time(b)) => Some(a.cmp(b)),
+        (Value::Uuid(a), Value::Uuid(b)) => Some(a.cmp(b)),
+        _ => None,
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Token {
+    /// Lowercase identifier, a column id
+    Ident(String),
+
+    /// Uppercase word, a keyword or a literal
+    Word(String),
+    Number(String),
+    Str(String),
+    Op(CmpOp),
+    LParen,
+    RParen,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Token::Ident(ref s) | Token::Word(ref s) | Token::Number(ref s) => write!(f, "{}", s),
+            Token::Str(ref s) => write!(f, "'{}'", s.replace('\'', "''")),
+            Token::Op(op) => write!(f, "{}", op),
+            Token::LParen => write!(f, "("),
+            Token::RParen => write!(f, ")"),
+        }
+    }
+}
+
+fn tokenize(src: &str) -> Result<Vec<Token>, ExprError> {
+    let mut tokens = Vec::new();
+    let mut chars = src.chars().peekable();
+
+    // consume characters as long as `pred` holds
+    fn take_while<I, F>(chars: &mut ::std::iter::Peekable<I>, buf: &mut String, pred: F)
+    where
+        I: Iterator<Item = char>,
+        F: Fn(char) -> bool,
+    {
+        while let Some(&c) = chars.peek() {
+            if !pred(c) {
+                break;
+            }
+            buf.push(c);
+            chars.next();
+        }
+    }
+
+    while let Some(&c) = chars.peek() {
+        match c {
+            ' ' => {
+                chars.next();
+            }
+            '(' => {
+                chars.next();
+                tokens.push(Token::LParen);
+            }
+            ')' => {
+                chars.next();
+                tokens.push(Token::RParen);
+            }
+            '=' => {
+                chars.next();
+                tokens.push(Token::Op(CmpOp::Eq));
+            }
+            '!' | '<' | '>' => {
+                chars.next();
+                let eq = chars.peek() == Some(&'=');
+                if eq {
+                    chars.next();
+                }
+                tokens.push(Token::Op(match (c, eq) {
+                    ('!', true) => CmpOp::Ne,
+                    ('<', false) => CmpOp::Lt,
+                    ('<', true) => CmpOp::Le,
+                    ('>', false) => CmpOp::Gt,
+                    ('>', true) => CmpOp::Ge,
+                    _ => return Err(ExprError::InvalidCharacter(c)),
+                }));
+            }
+            '\'' => {
+                chars.next();
+                let mut s = String::new();
+                loop {
+                    match chars.next() {
+                        Some('\'') if chars.peek() == Some(&'\'') => {
+                            chars.next();
+                            s.push('\'');
+                        }
+                        Some('\'') => break,
+                        Some(c) => s.push(c),
+                        None => return Err(ExprError::UnterminatedString),
+                    }
+                }
+                tokens.push(Token::Str(s));
+            }
+            '-' | '0'..='9' => {
+                let mut s = String::new();
+                s.push(c);
+                chars.next();
+                take_while(&mut chars, &mut s, |c| c.is_ascii_digit() || c == '.');
+
+                let digits = s.trim_start_matches('-');
+                let valid = !digits.is_empty() && !digits.starts_with('.') &&
+                    !digits.ends_with('.') && digits.matches('.').count() <= 1;
+                if !valid {
+                    return Err(ExprError::UnexpectedToken(s));
+                }
+                tokens.push(Token::Number(s));
+            }
+            'a'..='z' => {
+                let mut s = String::new();
+                take_while(&mut chars, &mut s, |c| {
+                    c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_'
+                });
+                tokens.push(Token::Ident(s));
+            }
+            'A'..='Z' => {
+                let mut s = String::new();
+                take_while(&mut chars, &mut s, |c| {
+                    c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_'
+                });
+                tokens.push(Token::Word(s));
+            }
+            _ => return Err(ExprError::InvalidCharacter(c)),
+        }
+    }
+
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let tok = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        tok
+    }
+
+    /// Consume the next token if it is the given keyword
+    fn keyword(&mut self, kw: &str) -> bool {
+        let found = match self.peek() {
+            Some(Token::Word(w)) => w == kw,
+            _ => false,
+        };
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn expect_keyword(&mut self, kw: &str) -> Result<(), ExprError> {
+        if self.keyword(kw) {
+            return Ok(());
+        }
+        match self.next() {
+            Some(tok) => Err(ExprError::UnexpectedToken(tok.to_string())),
+            None => Err(ExprError::UnexpectedEnd),
+        }
+    }
+
+    fn parse_or(&mut self) -> Result<Expr, ExprError> {
+        let mut expr = self.parse_and()?;
+        while self.keyword("OR") {
+            expr = Expr::Or(Box::new(expr), Box::new(self.parse_and()?));
+        }
+        Ok(expr)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr, ExprError> {
+        let mut expr = self.parse_not()?;
+        while self.keyword("AND") {
+            expr = Expr::And(Box::new(expr), Box::new(self.parse_not()?));
+        }
+        Ok(expr)
+    }
+
+    fn parse_not(&mut self) -> Result<Expr, ExprError> {
+        if self.keyword("NOT") {
+            return Ok(Expr::Not(Box::new(self.parse_not()?)));
+        }
+        self.parse_primary()
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr, ExprError> {
+        if self.peek() == Some(&Token::LParen) {
+            self.pos += 1;
+            let expr = self.parse_or()?;
+            return match self.next() {
+                Some(Token::RParen) => Ok(expr),
+                Some(tok) => Err(ExprError::UnexpectedToken(tok.to_string())),
+                None => Err(ExprError::UnexpectedEnd),
+            };
+        }
+
+        let lhs = self.parse_operand()?;
+
+        if self.keyword("IS") {
+            let negated = self.keyword("NOT");
+            self.expect_keyword("NULL")?;
+            return Ok(Expr::IsNull(lhs, negated));
+        }
+
+        match self.next() {
+            Some(Token::Op(op)) => Ok(Expr::Cmp(lhs, op, self.parse_operand()?)),
+            Some(tok) => Err(ExprError::UnexpectedToken(tok.to_string())),
+            None => Err(ExprError::UnexpectedEnd),
+        }
+    }
+
+    fn parse_operand(&mut self) -> Result<Operand, ExprError> {
+        match self.next() {
+            Some(Token::Ident(id)) => Ok(Operand::Column(id)),
+            Some(Token::Number(s)) | Some(Token::Str(s)) => Ok(Operand::Literal(s)),
+            Some(Token::Word(ref w)) if !KEYWORDS.contains(&w.as_str()) => {
+                Ok(Operand::Literal(w.clone()))
+            }
+            Some(tok) => Err(ExprError::UnexpectedToken(tok.to_string())),
+            None => Err(ExprError::UnexpectedEnd),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use super::super::CsvxSchema;
+
+    fn animals() -> CsvxSchema {
+        CsvxSchema::from_string(
+            "id,type,constraints,description\r\n\
+             class,\"ENUM(MAMMAL,BIRD,INSECT)\",,\r\n\
+             weight,INTEGER,NULLABLE,\r\n\
+             food_cost,DECIMAL,,\r\n\
+             arrived,DATE,,\r\n\
+             left,DATE,NULLABLE,\r\n\
+             name,STRING,,\r\n",
+            "schema.csv",
+        ).unwrap()
+    }
+
+    fn eval(src: &str, row: &[&str]) -> Option<bool> {
+        let schema = animals();
+        let columns: Vec<_> = schema.iter_columns().cloned().collect();
+        let values: Vec<_> = columns
+            .iter()
+            .zip(row.iter())
+            .map(|(col, s)| col.validate_value(s).unwrap())
+            .collect();
+        compile(&Expr::parse(src).unwrap(), &columns).unwrap().eval(&values)
+    }
+
+    #[test]
+    fn parses_and_displays() {
+        for src in &[
+            "weight > 0 OR class = INSECT",
+            "NOT (left IS NULL OR left >= arrived) AND name != 'O''Brien'",
+            "(weight > 0 OR weight IS NULL) AND food_cost <= 2.5",
+            "NOT NOT weight IS NOT NULL",
+        ] {
+            assert_eq!(Expr::parse(src).unwrap().to_string(), *src);
+        }
+
+        // precedence: AND binds tighter than OR
+        assert_eq!(
+            Expr::parse("weight = 1 OR weight = 2 AND weight = 3").unwrap(),
+            Expr::parse("weight = 1 OR (weight = 2 AND weight = 3)").unwrap()
+        );
+
+        for bad in &["", "weight >", "weight > 0 AND", "(weight > 0", "weight => 0",
+                     "weight = NULL", "name = 'open", "weight > 0 weight", "1.2.3 = weight"]
+        {
+            assert!(Expr::parse(bad).is_err(), "{:?} should not parse", bad);
+        }
+    }
+
+    #[test]
+    fn expressions_are_type_checked() {
+        let columns: Vec<_> = animals().iter_columns().cloned().collect();
+        let compile = |src| compile(&Expr::parse(src).unwrap(), &columns);
+
+        assert!(compile("weight > food_cost").is_ok());
+        assert!(compile("left >= arrived AND arrived > 20000101").is_ok());
+
+        match compile("size > 0") {
+            Err(ExprError::UnknownColumn(ref id)) => assert_eq!(id, "size"),
+            res => panic!("unexpected result {:?}", res),
+        }
+        match compile("class = REPTILE") {
+            Err(ExprError::InvalidLiteral(ref lit, _)) => assert_eq!(lit, "REPTILE"),
+            res => panic!("unexpected result {:?}", res),
+        }
+        match compile("arrived > weight") {
+            Err(ExprError::IncompatibleColumns(_, _)) => (),
+            res => panic!("unexpected result {:?}", res),
+        }
+        match compile("class > BIRD") {
+            Err(ExprError::Unordered(_)) => (),
+            res => panic!("unexpected result {:?}", res),
+        }
+        match compile("1 = 1") {
+            Err(ExprError::NoColumn(_)) => (),
+            res => panic!("unexpected result {:?}", res),
+        }
+    }
+
+    #[test]
+    fn evaluates_with_nulls() {
+        let row = ["INSECT", "", "0.10", "20170101", "", "Bee"];
+        assert_eq!(eval("weight > 0", &row), None);
+        assert_eq!(eval("weight > 0 OR class = INSECT", &row), Some(true));
+        assert_eq!(eval("weight > 0 AND class = INSECT", &row), None);
+        assert_eq!(eval("weight > 0 AND class = BIRD", &row), Some(false));
+        assert_eq!(eval("NOT weight IS NULL", &row), Some(false));
+        assert_eq!(eval("food_cost > 0 AND food_cost < 1", &row), Some(true));
+
+        let row = ["BIRD", "12", "5", "20170101", "20161231", "Polly"];
+        assert_eq!(eval("left >= arrived", &row), Some(false));
+        assert_eq!(eval("weight > food_cost", &row), Some(true));
+        assert_eq!(eval("name < 'Q'", &row), Some(true));
+    }
+
+    #[test]
+    fn literals_ignore_precision() {
+        let schema = CsvxSchema::from_string(
+            "id,type,constraints,description\r\n\
+             cost,\"DECIMAL(10,2)\",CHECK(cost > 0),Cost\r\n\
+             fed,DATETIME(3),\"CHECK(fed >= 20170101120000 AND fed < 20170101120000.500)\",\r\n",
+            "schema.csv",
+        ).unwrap();
+        let columns: Vec<_> = schema.iter_columns().cloned().collect();
+        let eval = |src, row: &[&str]| {
+            let values: Vec<_> = columns
+                .iter()
+                .zip(row.iter())
+                .map(|(col, s)| col.validate_value(s).unwrap())
+                .collect();
+            compile(&Expr::parse(src).unwrap(), &columns).unwrap().eval(&values)
+        };
+
+        let row = ["0.50", "20170101120000.250"];
+        assert_eq!(eval("cost > 0", &row), Some(true));
+        assert_eq!(eval("cost >= 0.5 AND cost <= 0.500", &row), Some(true));
+        assert_eq!(eval("fed > 20170101120000.25", &row), Some(false));
+        assert_eq!(eval("fed < 20170101120000.500", &row), Some(true));
+    }
+}
@@ -0,0 +1,244 @@
+//! Byte-level format conformance checks
+//!
+//! The CSV reader is lenient about line endings, quoting and encoding. The
+//! functions in this module check the raw contents of a file against the
+//! stricter rules of the csvx format.
+
+use err::{ErrorAtLocation, ErrorLoc, FormatError, Location, ValidationError};
+use std::str;
+use unicode_normalization::is_nfc;
+
+const BOM: &[u8] = b"\xef\xbb\xbf";
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum State {
+    StartRecord,
+    StartField,
+    InField,
+    InQuotedField,
+    QuoteInQuotedField,
+}
+
+/// Check raw file contents for csvx format violations
+///
+/// Returns every violation found; an empty vector means the data is
+/// conformant.
+pub fn check_format(data: &[u8], filename: &str) -> Vec<ErrorAtLocation<ValidationError, Location>> {
+    let mut errs = Vec::new();
+    let err_at_line = |err: FormatError, line: usize| {
+        ValidationError::FormatError(err).at(Location::FileLine(filename.to_owned(), line))
+    };
+    let err_at_field = |err: FormatError, line: usize, field: usize| {
+        ValidationError::FormatError(err).at(Location::FileLineField(
+            filename.to_owned(),
+            line,
+            field,
+        ))
+    };
+
+    if data.starts_with(BOM) {
+        errs.push(err_at_line(FormatError::ByteOrderMark, 1));
+    }
+
+    match str::from_utf8(data) {
+        Ok(s) => {
+            for (idx, line) in s.split('\n').enumerate() {
+                if !is_nfc(line) {
+                    errs.push(err_at_line(FormatError::NotNfc, idx + 1));
+                }
+            }
+        }
+        Err(e) => {
+            let line = count_lines(&data[..e.valid_up_to()]);
+            errs.push(err_at_line(FormatError::InvalidUtf8, line));
+        }
+    }
+
+    let mut state = State::StartRecord;
+    let mut line = 1;
+    let mut field = 1;
+    let mut field_line = 1;
+    let mut needs_quotes = false;
+    let mut empty = true;
+
+    for (pos, &c) in data.iter().enumerate() {
+        let prev = if pos > 0 { Some(data[pos - 1]) } else { None };
+        let next = data.get(pos + 1).cloned();
+
+        match state {
+            State::InQuotedField => {
+                match c {
+                    b'"' => state = State::QuoteInQuotedField,
+                    b',' | b'\r' | b'\n' => {
+                        needs_quotes = true;
+                        empty = false;
+                    }
+                    _ => empty = false,
+                }
+            }
+            State::QuoteInQuotedField if c == b'"' => {
+                // escaped quote
+                needs_quotes = true;
+                empty = false;
+                state = State::InQuotedField;
+            }
+            _ => {
+                if state == State::QuoteInQuotedField {
+                    let ends_record = c == b'\r' || c == b'\n';
+
+                    // quotes are only needed if the field contains special
+                    // characters or if it is the sole, empty field of a
+                    // record (which would otherwise be an empty line)
+                    let quotes_required = needs_quotes || (empty && field == 1 && ends_record);
+                    if c != b',' && !ends_record {
+                        errs.push(err_at_field(FormatError::MalformedQuoting, line, field));
+                    } else if !quotes_required {
+                        errs.push(err_at_field(
+                            FormatError::UnnecessaryQuotes,
+                            field_line,
+                            field,
+                        ));
+                    }
+                    state = State::InField;
+                }
+
+                match c {
+                    b'\r' | b'\n' => {
+                        if c == b'\n' && prev != Some(b'\r') {
+                            errs.push(err_at_line(FormatError::BareLineFeed, line));
+                        }
+                        if c == b'\r' && next != Some(b'\n') {
+                            errs.push(err_at_line(FormatError::BareCarriageReturn, line));
+                        }
+
+                        // only report an empty line once per line break
+                        if state == State::StartRecord && (c == b'\r' || prev != Some(b'\r')) {
+                            errs.push(err_at_line(FormatError::EmptyLine, line));
+                        }
+                        state = State::StartRecord;
+                        field = 1;
+                    }
+                    b',' => {
+                        state = State::StartField;
+                        field += 1;
+                    }
+                    b'"' => {
+                        if state == State::StartRecord || state == State::StartField {
+                            state = State::InQuotedField;
+                            field_line = line;
+                            needs_quotes = false;
+                            empty = true;
+                        } else {
+                            errs.push(err_at_field(FormatError::MalformedQuoting, line, field));
+                            state = State::InField;
+                        }
+                    }
+                    _ => state = State::InField,
+                }
+            }
+        }
+
+        if c == b'\n' {
+            line += 1;
+        }
+    }
+
+    match state {
+        State::InQuotedField => {
+            errs.push(err_at_field(
+                FormatError::MalformedQuoting,
+                field_line,
+                field,
+            ))
+        }
+        // closing quote was the last byte of the file
+        State::QuoteInQuotedField if !(needs_quotes || (empty && field == 1)) => {
+            errs.push(err_at_field(
+                FormatError::UnnecessaryQuotes,
+                field_line,
+                field,
+            ))
+        }
+        _ => (),
+    }
+
+    if !data.is_empty() && !data.ends_with(b"\r\n") {
+        errs.push(err_at_line(FormatError::MissingFinalLineBreak, line));
+    }
+
+    errs
+}
+
+/// Returns the line number the end of `data` is on
+fn count_lines(data: &[u8]) -> usize {
+    data.iter().filter(|&&c| c == b'\n').count() + 1
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn format_errors(data: &[u8]) -> Vec<(FormatError, usize, Option<usize>)> {
+        check_format(data, "test.csv")
+            .into_iter()
+            .map(|e| {
+                let (line, field) = match *e.location() {
+                    Location::FileLine(_, line) => (line, None),
+                    Location::FileLineField(_, line, field) => (line, Some(field)),
+                    ref loc => panic!("unexpected location {:?}", loc),
+                };
+                match *e.error() {
+                    ValidationError::FormatError(ref fe) => (fe.clone(), line, field),
+                    ref e => panic!("unexpected error {:?}", e),
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn conformant_data_passes() {
+        assert_eq!(
+            format_errors(b"id,name\r\n1,\"Brian, the \"\"Gorilla\"\"\"\r\n2,\"multi\r\nline\"\r\n"),
+            vec![]
+        );
+        assert_eq!(format_errors(b"tag\r\n\"\"\r\nA\r\n"), vec![]);
+    }
+
+    #[test]
+    fn line_endings_are_checked() {
+        assert_eq!(
+            format_errors(b"id,name\r\n1,Brian\n2,Pinky\r\n\r\n3,Sam"),
+            vec![
+                (FormatError::BareLineFeed, 2, None),
+                (FormatError::EmptyLine, 4, None),
+                (FormatError::MissingFinalLineBreak, 5, None),
+            ]
+        );
+    }
+
+    #[test]
+    fn needless_quotes_and_bom_are_reported() {
+        assert_eq!(
+            format_errors(b"\xef\xbb\xbfid,name\r\n1,\"Brian\"\r\n\"2\",\"\"\r\n"),
+            vec![
+                (FormatError::ByteOrderMark, 1, None),
+                (FormatError::UnnecessaryQuotes, 2, Some(2)),
+                (FormatError::UnnecessaryQuotes, 3, Some(1)),
+                (FormatError::UnnecessaryQuotes, 3, Some(2)),
+            ]
+        );
+    }
+
+    #[test]
+    fn encoding_is_checked() {
+        assert_eq!(
+            format_errors(b"id\r\n\xff\r\n"),
+            vec![(FormatError::InvalidUtf8, 2, None)]
+        );
+        // `e` followed by a combining acute accent instead of `é`
+        assert_eq!(
+            format_errors("id\r\ncafe\u{301}\r\n".as_bytes()),
+            vec![(FormatError::NotNfc, 2, None)]
+        );
+    }
+}
@@ -1,32 +1,136 @@
 extern crate chrono;
 extern crate csv;
+extern crate flate2;
+extern crate hmac_sha256;
 #[macro_use]
 extern crate lazy_static;
+extern crate num_bigint;
+extern crate num_traits;
 extern crate regex;
 extern crate safe_unwrap;
+#[macro_use]
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+#[macro_use]
+extern crate serde_json;
 extern crate term_painter;
 extern crate term_size;
 extern crate textwrap;
 extern crate try_from;
-
+extern crate unicode_normalization;
+extern crate uuid;
+extern crate xz2;
+
+mod builder;
+mod de;
+mod decimal;
+mod diff;
 pub mod err;
+mod expr;
+mod format;
+mod mask;
+mod reader;
+mod refs;
 mod regexes;
+mod report;
+mod ser;
+mod snippet;
+mod writer;
 
-use chrono::{NaiveDate, NaiveDateTime, NaiveTime};
+use chrono::{DateTime, FixedOffset, NaiveDate, NaiveDateTime, NaiveTime, Offset, TimeZone,
+             Timelike};
 use err::{ColumnConstraintsError, ColumnTypeError, ErrorLoc, ErrorAtLocation, Location, ResultLoc,
           SchemaLoadError, ValidationError, ValueError};
-use std::{fmt, fs, path, slice};
-use std::io::Read;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use std::{fmt, fs, io, mem, path, slice};
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::io::{Read, Write};
 use safe_unwrap::SafeUnwrap;
-use regexes::{IDENT_UNDERSCORE_RE, ENUM_EXPR_RE, CONSTRAINT_RE, DECIMAL_RE, DATE_RE, DATETIME_RE,
-              FN_RE, TIME_RE};
+use reader::Records;
+use regexes::{ENUM_EXPR_RE, CONSTRAINT_RE, DECIMAL_RE, DATE_RE, DATETIME_RE,
+              DATETIME_TYPE_RE, DECIMAL_TYPE_RE, FN_RE, IDENT_UNDERSCORE_RE, KEY_RE, REFERENCES_RE,
+              TIME_RE};
 use try_from::TryFrom;
+use uuid::Uuid;
+use xz2::read::XzDecoder;
+use xz2::write::XzEncoder;
+
+pub use builder::CsvxSchemaBuilder;
+pub use decimal::Decimal;
+pub use diff::SchemaChange;
+pub use expr::{CmpOp, Expr, Operand};
+pub use mask::{Masker, PII_TAG};
+pub use reader::{CsvxReader, DeserializeRows};
+pub use refs::validate_references;
+pub use report::{to_junit, to_sarif, ErrorEntry, FileSummary, ReportEntry, FATAL_KIND};
+pub use snippet::{Excerpt, ExcerptLine, Source};
+pub use writer::CsvxWriter;
+
+/// Compression applied to a csvx file
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Compression {
+    Gzip,
+    Xz,
+}
+
+impl Compression {
+    /// Determine compression from the file extension (`.gzip` or `.xz`)
+    pub fn from_path<P: AsRef<path::Path>>(filename: P) -> Option<Compression> {
+        match filename.as_ref().extension().and_then(|ext| ext.to_str()) {
+            Some("gzip") => Some(Compression::Gzip),
+            Some("xz") => Some(Compression::Xz),
+            _ => None,
+        }
+    }
+
+    /// Wrap a reader, decompressing its contents on the fly
+    pub fn decode<'a, R: Read + 'a>(compression: Option<Compression>, rdr: R) -> Box<Read + 'a> {
+        match compression {
+            Some(Compression::Gzip) => Box::new(GzDecoder::new(rdr)),
+            Some(Compression::Xz) => Box::new(XzDecoder::new(rdr)),
+            None => Box::new(rdr),
+        }
+    }
+
+    /// Wrap a writer, compressing everything written to it
+    ///
+    /// The compressed stream is finished when the writer is dropped.
+    pub fn encode<'a, W: Write + 'a>(compression: Option<Compression>, wtr: W) -> Box<Write + 'a> {
+        match compression {
+            Some(Compression::Gzip) => {
+                Box::new(GzEncoder::new(wtr, flate2::Compression::default()))
+            }
+            Some(Compression::Xz) => Box::new(XzEncoder::new(wtr, 6)),
+            None => Box::new(wtr),
+        }
+    }
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Compression::Gzip => write!(f, "gzip"),
+            Compression::Xz => write!(f, "xz"),
+        }
+    }
+}
+
+/// Open a file for reading, decompressing it if its extension indicates a
+/// compressed file
+pub fn open_file<P: AsRef<path::Path>>(filename: P) -> io::Result<Box<Read>> {
+    let compression = Compression::from_path(&filename);
+    Ok(Compression::decode(compression, fs::File::open(filename)?))
+}
 
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct CsvxMetadata {
     pub table_name: String,
     pub date: NaiveDate,
     pub schema: String,
+    pub compression: Option<Compression>,
 }
 
 impl CsvxMetadata {
@@ -35,16 +139,38 @@ impl CsvxMetadata {
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Eq, PartialEq)]
 pub enum ColumnType {
     String,
     Bool,
     Integer,
     Enum(Vec<String>),
-    Decimal,
+
+    /// Decimal number, optionally with a precision (total number of digits)
+    /// and scale (exact number of digits after the decimal point)
+    Decimal(Option<(u32, u32)>),
     Date,
-    DateTime,
+
+    /// Date and time, with the given number of fractional second digits
+    DateTime(u32),
+
+    /// Date and time with a UTC offset, with the given number of fractional
+    /// second digits
+    DateTimeTz(u32),
     Time,
+
+    /// UUID in its canonical, lowercase and hyphenated form
+    Uuid,
+}
+
+impl ColumnType {
+    /// Whether values of both types can be compared to each other
+    ///
+    /// Types of the same base type are compatible, regardless of precision,
+    /// scale or `ENUM` variants.
+    pub fn is_compatible(&self, other: &ColumnType) -> bool {
+        mem::discriminant(self) == mem::discriminant(other)
+    }
 }
 
 impl fmt::Display for ColumnType {
@@ -54,18 +180,111 @@ impl fmt::Display for ColumnType {
             ColumnType::Bool => write!(f, "BOOL"),
             ColumnType::Integer => write!(f, "INTEGER"),
             ColumnType::Enum(ref variants) => write!(f, "ENUM({})", variants.join(",")),
-            ColumnType::Decimal => write!(f, "DECIMAL"),
+            ColumnType::Decimal(None) => write!(f, "DECIMAL"),
+            ColumnType::Decimal(Some((precision, scale))) => {
+                write!(f, "DECIMAL({},{})", precision, scale)
+            }
             ColumnType::Date => write!(f, "DATE"),
-            ColumnType::DateTime => write!(f, "DATETIME"),
+            ColumnType::DateTime(0) => write!(f, "DATETIME"),
+            ColumnType::DateTime(digits) => write!(f, "DATETIME({})", digits),
+            ColumnType::DateTimeTz(0) => write!(f, "DATETIMETZ"),
+            ColumnType::DateTimeTz(digits) => write!(f, "DATETIMETZ({})", digits),
             ColumnType::Time => write!(f, "TIME"),
+            ColumnType::Uuid => write!(f, "UUID"),
         }
     }
 }
 
+/// Column of another schema referenced by a `REFERENCES` constraint
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct ForeignKey {
+    /// Name of the referenced schema, e.g. `staff-1`
+    pub schema: String,
+
+    /// Id of the referenced column
+    pub column: String,
+}
+
+impl fmt::Display for ForeignKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}", self.schema, self.column)
+    }
+}
+
+/// A key spanning one or more columns of a table
+///
+/// Columns marked `PRIMARY` make up the primary key, columns marked
+/// `KEY(name)` the key `name`. The values of all columns of a key must be
+/// unique in combination.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TableKey {
+    /// Name of the key, `None` for the primary key
+    pub name: Option<String>,
+
+    /// Ids of the columns making up the key, in schema order
+    pub columns: Vec<String>,
+}
+
+impl fmt::Display for TableKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.name {
+            Some(ref name) => write!(f, "key `{}`", name)?,
+            None => write!(f, "primary key")?,
+        }
+        write!(f, " ({})", self.columns.join(", "))
+    }
+}
+
+/// Regular expression that `STRING` values must match in full
+#[derive(Clone, Debug)]
+pub struct Pattern {
+    src: String,
+    re: regex::Regex,
+}
+
+impl Pattern {
+    pub fn new(src: &str) -> Result<Pattern, regex::Error> {
+        Ok(Pattern {
+            src: src.to_owned(),
+            re: regex::Regex::new(&format!("^(?:{})$", src))?,
+        })
+    }
+
+    /// The regular expression as given, without anchors
+    pub fn as_str(&self) -> &str {
+        &self.src
+    }
+
+    pub fn is_match(&self, s: &str) -> bool {
+        self.re.is_match(s)
+    }
+}
+
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.src)
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct ColumnConstraints {
     pub nullable: bool,
     pub unique: bool,
+    pub primary: bool,
+    pub keys: Vec<String>,
+    pub references: Option<ForeignKey>,
+    pub checks: Vec<Expr>,
+
+    /// Inclusive lower bound of `INTEGER` and `DECIMAL` values
+    pub min: Option<Decimal>,
+
+    /// Inclusive upper bound of `INTEGER` and `DECIMAL` values
+    pub max: Option<Decimal>,
+
+    /// Maximum length of `STRING` values, in characters
+    pub max_len: Option<usize>,
+
+    pub pattern: Option<Pattern>,
 }
 
 impl Default for ColumnConstraints {
@@ -73,6 +292,14 @@ impl Default for ColumnConstraints {
         ColumnConstraints {
             nullable: false,
             unique: false,
+            primary: false,
+            keys: Vec::new(),
+            references: None,
+            checks: Vec::new(),
+            min: None,
+            max: None,
+            max_len: None,
+            pattern: None,
         }
     }
 }
@@ -81,15 +308,86 @@ impl fmt::Display for ColumnConstraints {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let mut parts = Vec::new();
         if self.nullable {
-            parts.push("NULLABLE");
+            parts.push("NULLABLE".to_owned());
         }
         if self.unique {
-            parts.push("UNIQUE");
+            parts.push("UNIQUE".to_owned());
+        }
+        if self.primary {
+            parts.push("PRIMARY".to_owned());
+        }
+        for key in &self.keys {
+            parts.push(format!("KEY({})", key));
+        }
+        if let Some(ref fk) = self.references {
+            parts.push(format!("REFERENCES({})", fk));
+        }
+        for expr in &self.checks {
+            parts.push(format!("CHECK({})", expr));
+        }
+        if let Some(ref min) = self.min {
+            parts.push(format!("MIN({})", min));
+        }
+        if let Some(ref max) = self.max {
+            parts.push(format!("MAX({})", max));
+        }
+        if let Some(max_len) = self.max_len {
+            parts.push(format!("MAXLEN({})", max_len));
+        }
+        if let Some(ref pattern) = self.pattern {
+            parts.push(format!("PATTERN('{}')", pattern.as_str().replace('\'', "''")));
         }
         write!(f, "{}", parts.join(","))
     }
 }
 
+/// Keywords of all constraints
+const KNOWN_CONSTRAINTS: &[&str] = &[
+    "CHECK",
+    "KEY",
+    "MAX",
+    "MAXLEN",
+    "MIN",
+    "NULLABLE",
+    "PATTERN",
+    "PRIMARY",
+    "REFERENCES",
+    "UNIQUE",
+];
+
+/// Split constraints at commas that are not enclosed in parentheses
+///
+/// Parentheses and commas inside single-quoted strings are ignored. Returns
+/// `None` if the parentheses are unbalanced.
+fn split_constraints(s: &str) -> Option<Vec<&str>> {
+    let mut fragments = Vec::new();
+    let mut depth = 0usize;
+    let mut start = 0;
+    let mut quoted = false;
+
+    for (pos, c) in s.char_indices() {
+        match c {
+            // an escaped quote (`''`) toggles twice
+            '\'' => quoted = !quoted,
+            _ if quoted => (),
+            '(' => depth += 1,
+            ')' => depth = depth.checked_sub(1)?,
+            ',' if depth == 0 => {
+                fragments.push(&s[start..pos]);
+                start = pos + 1;
+            }
+            _ => (),
+        }
+    }
+
+    if depth != 0 || quoted {
+        return None;
+    }
+
+    fragments.push(&s[start..]);
+    Some(fragments)
+}
+
 impl<S> TryFrom<S> for ColumnConstraints
 where
     S: AsRef<str>,
@@ -97,33 +395,75 @@ where
     type Err = ColumnConstraintsError;
 
     fn try_from(s: S) -> Result<ColumnConstraints, Self::Err> {
-        if !CONSTRAINT_RE.is_match(s.as_ref()) {
-            return Err(ColumnConstraintsError::MalformedConstraints(
-                s.as_ref().to_string(),
-            ));
-        }
-
         let mut ccs = ColumnConstraints::default();
 
         if s.as_ref() == "" {
             return Ok(ccs);
         }
 
-        for fragment in s.as_ref().split(',') {
-            match fragment.as_ref() {
-                "NULLABLE" => {
-                    ccs.nullable = true;
+        let fragments = split_constraints(s.as_ref()).ok_or_else(|| {
+            ColumnConstraintsError::MalformedConstraints(s.as_ref().to_string())
+        })?;
+
+        for fragment in fragments {
+            let malformed = || ColumnConstraintsError::MalformedConstraints(fragment.to_string());
+
+            if !CONSTRAINT_RE.is_match(fragment) {
+                return Err(malformed());
+            }
+
+            // `KEYWORD` or `KEYWORD(argument)`
+            let (keyword, arg) = match fragment.find('(') {
+                Some(pos) => (&fragment[..pos], Some(&fragment[pos + 1..fragment.len() - 1])),
+                None => (fragment, None),
+            };
+
+            match (keyword, arg) {
+                ("NULLABLE", None) => ccs.nullable = true,
+                ("UNIQUE", None) => ccs.unique = true,
+                ("PRIMARY", None) => ccs.primary = true,
+                ("KEY", Some(name)) if KEY_RE.is_match(fragment) => {
+                    if !ccs.keys.iter().any(|k| k == name) {
+                        ccs.keys.push(name.to_owned());
+                    }
                 }
-                "UNIQUE" => {
-                    ccs.unique = true;
+                ("REFERENCES", Some(_)) if REFERENCES_RE.is_match(fragment) => {
+                    let caps = REFERENCES_RE.captures(fragment).safe_unwrap(
+                        "match already exists",
+                    );
+                    ccs.references = Some(ForeignKey {
+                        schema: caps.get(1).safe_unwrap("known group").as_str().to_owned(),
+                        column: caps.get(2).safe_unwrap("known group").as_str().to_owned(),
+                    });
                 }
+                ("CHECK", Some(src)) => {
+                    ccs.checks.push(Expr::parse(src).map_err(|e| {
+                        ColumnConstraintsError::BadExpression(src.to_owned(), Box::new(e))
+                    })?);
+                }
+                ("MIN", Some(n)) => ccs.min = Some(n.parse().map_err(|_| malformed())?),
+                ("MAX", Some(n)) => ccs.max = Some(n.parse().map_err(|_| malformed())?),
+                ("MAXLEN", Some(n)) if n.bytes().all(|c| c.is_ascii_digit()) => {
+                    ccs.max_len = Some(n.parse().map_err(|_| malformed())?)
+                }
+                ("PATTERN", Some(quoted)) => {
+                    if quoted.len() < 2 || !quoted.starts_with('\'') || !quoted.ends_with('\'') {
+                        return Err(malformed());
+                    }
+                    let src = quoted[1..quoted.len() - 1].replace("''", "'");
+                    ccs.pattern = Some(Pattern::new(&src).map_err(|e| {
+                        ColumnConstraintsError::BadPattern(src.clone(), e.to_string())
+                    })?);
+                }
+                // misspelled arguments to a known constraint are malformed,
+                // not unknown
+                _ if KNOWN_CONSTRAINTS.contains(&keyword) => return Err(malformed()),
                 _ => {
                     return Err(ColumnConstraintsError::UnknownConstraint(
-                        s.as_ref().to_string(),
+                        fragment.to_string(),
                     ))
                 }
             }
-
         }
 
         Ok(ccs)
@@ -141,10 +481,10 @@ where
             "STRING" => Ok(ColumnType::String),
             "BOOL" => Ok(ColumnType::Bool),
             "INTEGER" => Ok(ColumnType::Integer),
-            "DECIMAL" => Ok(ColumnType::Decimal),
+            "DECIMAL" => Ok(ColumnType::Decimal(None)),
             "DATE" => Ok(ColumnType::Date),
-            "DATETIME" => Ok(ColumnType::DateTime),
             "TIME" => Ok(ColumnType::Time),
+            "UUID" => Ok(ColumnType::Uuid),
             _ if ENUM_EXPR_RE.is_match(s.as_ref()) => {
                 let variants: Vec<_> = ENUM_EXPR_RE
                     .captures(s.as_ref())
@@ -158,6 +498,35 @@ where
 
                 Ok(ColumnType::Enum(variants))
             }
+            _ if s.as_ref().starts_with("DATETIME") => {
+                let bad = || ColumnTypeError::BadDateTime(s.as_ref().to_owned());
+                let caps = DATETIME_TYPE_RE.captures(s.as_ref()).ok_or_else(&bad)?;
+                let digits = match caps.get(2) {
+                    Some(m) => m.as_str().parse().map_err(|_| bad())?,
+                    None => 0,
+                };
+
+                // `DATETIME(0)` would just be another way to say `DATETIME`
+                if caps.get(2).is_some() && !(1..=9).contains(&digits) {
+                    return Err(bad());
+                }
+                if caps.get(1).is_some() {
+                    Ok(ColumnType::DateTimeTz(digits))
+                } else {
+                    Ok(ColumnType::DateTime(digits))
+                }
+            }
+            _ if s.as_ref().starts_with("DECIMAL(") => {
+                let bad = || ColumnTypeError::BadDecimal(s.as_ref().to_owned());
+                let caps = DECIMAL_TYPE_RE.captures(s.as_ref()).ok_or_else(&bad)?;
+                let precision: u32 = caps[1].parse().map_err(|_| bad())?;
+                let scale: u32 = caps[2].parse().map_err(|_| bad())?;
+
+                if precision == 0 || scale > precision {
+                    return Err(bad());
+                }
+                Ok(ColumnType::Decimal(Some((precision, scale))))
+            }
             _ => {
                 if s.as_ref().starts_with("ENUM") {
                     return Err(ColumnTypeError::BadEnum(s.as_ref().to_owned()));
@@ -175,6 +544,80 @@ pub struct CsvxColumnType {
     pub ty: ColumnType,
     pub constraints: ColumnConstraints,
     pub description: String,
+
+    /// Value to assume if none is given, in its csvx form
+    pub default: Option<String>,
+
+    /// Example value for documentation, in its csvx form
+    pub example: Option<String>,
+
+    /// Units, tags and other information for documentation
+    pub metadata: ColumnMetadata,
+}
+
+/// Optional fields of a schema file, in the order they are written
+const OPTIONAL_FIELDS: &[&str] = &["default", "example", "unit", "tags", "deprecated", "since"];
+
+/// Metadata of a column that does not affect validation
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ColumnMetadata {
+    /// Unit of the column's values, e.g. `kg`
+    pub unit: Option<String>,
+
+    /// Tags, each an identifier with underscores, e.g. `pii`
+    pub tags: Vec<String>,
+
+    /// Whether the column should no longer be used
+    pub deprecated: bool,
+
+    /// Date the column was introduced
+    pub since: Option<NaiveDate>,
+}
+
+impl ColumnMetadata {
+    /// Parse metadata from the optional fields of a schema row
+    ///
+    /// `field` returns the value of a field if present and not empty. On
+    /// error, the name and value of the offending field are returned.
+    fn from_fields<F: Fn(&'static str) -> Option<String>>(
+        field: F,
+    ) -> Result<ColumnMetadata, (&'static str, String)> {
+        let tags = match field("tags") {
+            Some(tags) => {
+                let tags: Vec<_> = tags.split(',').map(str::to_owned).collect();
+                if let Some(tag) = tags.iter().find(|t| !IDENT_UNDERSCORE_RE.is_match(t)) {
+                    return Err(("tags", tag.clone()));
+                }
+                tags
+            }
+            None => Vec::new(),
+        };
+
+        let deprecated = match field("deprecated").as_deref() {
+            None | Some("FALSE") => false,
+            Some("TRUE") => true,
+            Some(other) => return Err(("deprecated", other.to_owned())),
+        };
+
+        let since = match field("since") {
+            Some(s) => {
+                let date = CsvxColumnType::new("since", ColumnType::Date)
+                    .validate_value(&s)
+                    .ok()
+                    .flatten()
+                    .and_then(Value::to_date);
+                Some(date.ok_or(("since", s))?)
+            }
+            None => None,
+        };
+
+        Ok(ColumnMetadata {
+            unit: field("unit"),
+            tags: tags,
+            deprecated: deprecated,
+            since: since,
+        })
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -183,17 +626,19 @@ pub enum Value {
     Bool(bool),
     Integer(i64),
     Enum(usize),
-    Decimal(String),
+    Decimal(Decimal),
     Date(NaiveDate),
     DateTime(NaiveDateTime),
+    DateTimeTz(DateTime<FixedOffset>),
     Time(NaiveTime),
+    Uuid(Uuid),
 }
 
 impl Value {
     pub fn to_string(self) -> Option<String> {
         match self {
             Value::String(s) => Some(s),
-            Value::Decimal(d) => Some(d),
+            Value::Decimal(d) => Some(d.to_string()),
             _ => None,
         }
     }
@@ -214,6 +659,14 @@ impl Value {
         }
     }
 
+    pub fn to_decimal(self) -> Option<Decimal> {
+        if let Value::Decimal(val) = self {
+            Some(val)
+        } else {
+            None
+        }
+    }
+
     pub fn to_date(self) -> Option<NaiveDate> {
         if let Value::Date(val) = self {
             Some(val)
@@ -230,6 +683,14 @@ impl Value {
         }
     }
 
+    pub fn to_datetime_tz(self) -> Option<DateTime<FixedOffset>> {
+        if let Value::DateTimeTz(val) = self {
+            Some(val)
+        } else {
+            None
+        }
+    }
+
     pub fn to_time(self) -> Option<NaiveTime> {
         if let Value::Time(val) = self {
             Some(val)
@@ -238,6 +699,14 @@ impl Value {
         }
     }
 
+    pub fn to_uuid(self) -> Option<Uuid> {
+        if let Value::Uuid(val) = self {
+            Some(val)
+        } else {
+            None
+        }
+    }
+
     pub fn to_usize(self) -> Option<usize> {
         if let Value::Enum(v) = self {
             Some(v)
@@ -248,9 +717,36 @@ impl Value {
 }
 
 impl CsvxColumnType {
-    pub fn validate_value<S: AsRef<str>>(&self, s: &S) -> Result<Option<Value>, ValueError> {
-        // FIXME: check UNIQUE
+    /// Create a column without constraints, description, default or example
+    pub fn new<S: Into<String>>(id: S, ty: ColumnType) -> CsvxColumnType {
+        CsvxColumnType {
+            id: id.into(),
+            ty: ty,
+            constraints: ColumnConstraints::default(),
+            description: String::new(),
+            default: None,
+            example: None,
+            metadata: ColumnMetadata::default(),
+        }
+    }
 
+    /// Render one of the `OPTIONAL_FIELDS` of a schema row
+    ///
+    /// Missing values are rendered as empty strings.
+    fn render_optional_field(&self, name: &str) -> String {
+        let meta = &self.metadata;
+        match name {
+            "default" => self.default.clone().unwrap_or_default(),
+            "example" => self.example.clone().unwrap_or_default(),
+            "unit" => meta.unit.clone().unwrap_or_default(),
+            "tags" => meta.tags.join(","),
+            "deprecated" => if meta.deprecated { "TRUE" } else { "" }.to_owned(),
+            "since" => meta.since.map(|d| d.format("%Y%m%d").to_string()).unwrap_or_default(),
+            _ => panic!("unknown optional field `{}`", name),
+        }
+    }
+
+    pub fn validate_value<S: AsRef<str>>(&self, s: &S) -> Result<Option<Value>, ValueError> {
         // null check
         if s.as_ref() == "" {
             if self.constraints.nullable {
@@ -260,7 +756,7 @@ impl CsvxColumnType {
             }
         }
 
-        match self.ty {
+        let value = match self.ty {
             ColumnType::String => Ok(Some(Value::String(s.as_ref().to_string()))),
             ColumnType::Bool => {
                 match s.as_ref() {
@@ -287,12 +783,26 @@ impl CsvxColumnType {
                     ))
                 }
             }
-            ColumnType::Decimal => {
-                if DECIMAL_RE.is_match(s.as_ref()) {
-                    Ok(Some(Value::Decimal(s.as_ref().to_owned())))
-                } else {
-                    Err(ValueError::InvalidDecimal(s.as_ref().to_owned()))
+            ColumnType::Decimal(spec) => {
+                if !DECIMAL_RE.is_match(s.as_ref()) {
+                    return Err(ValueError::InvalidDecimal(s.as_ref().to_owned()));
                 }
+
+                let d: Decimal = s.as_ref().parse()?;
+                if let Some((precision, scale)) = spec {
+                    if d.scale() != scale {
+                        return Err(ValueError::WrongScale(s.as_ref().to_owned(), scale));
+                    }
+                    // leading zeros do not count towards the precision
+                    if d.mantissa().to_string().len() > precision as usize {
+                        return Err(ValueError::PrecisionExceeded(
+                            s.as_ref().to_owned(),
+                            precision,
+                            scale,
+                        ));
+                    }
+                }
+                Ok(Some(Value::Decimal(d)))
             }
             ColumnType::Date => {
                 match DATE_RE.captures(s.as_ref()) {
@@ -305,22 +815,18 @@ impl CsvxColumnType {
                     None => Err(ValueError::InvalidDate(s.as_ref().to_owned())),
                 }
             }
-            ColumnType::DateTime => {
-                match DATETIME_RE.captures(s.as_ref()) {
-                    Some(ref c) => {
-                        let dt =
-                            NaiveDate::from_ymd_opt(cap(c, 1), cap(c, 2), cap(c, 3))
-                                .ok_or_else(|| ValueError::InvalidDate(s.as_ref().to_string()))?;
-                        Ok(Some(Value::DateTime(
-                            dt.and_hms_opt(cap(c, 4), cap(c, 5), cap(c, 6)).ok_or_else(
-                                || {
-                                    ValueError::InvalidTime(s.as_ref().to_string())
-                                },
-                            )?,
-                        )))
-                    }
-                    None => Err(ValueError::InvalidDateTime(s.as_ref().to_string())),
-                }
+            ColumnType::DateTime(digits) => {
+                parse_datetime(s.as_ref(), digits, false).map(|(dt, _)| Some(Value::DateTime(dt)))
+            }
+            ColumnType::DateTimeTz(digits) => {
+                parse_datetime(s.as_ref(), digits, true).map(|(dt, offset)| {
+                    let offset = offset.safe_unwrap("offset is required");
+                    Some(Value::DateTimeTz(
+                        offset.from_local_datetime(&dt).single().safe_unwrap(
+                            "fixed offsets are never ambiguous",
+                        ),
+                    ))
+                })
             }
             ColumnType::Time => {
                 match TIME_RE.captures(s.as_ref()) {
@@ -333,6 +839,130 @@ impl CsvxColumnType {
                     None => Err(ValueError::InvalidTime(s.as_ref().to_string())),
                 }
             }
+            ColumnType::Uuid => {
+                // only the canonical form is accepted, so that equal UUIDs
+                // are always equal strings
+                match Uuid::parse_str(s.as_ref()) {
+                    Ok(u) if u.to_hyphenated().to_string() == s.as_ref() => {
+                        Ok(Some(Value::Uuid(u)))
+                    }
+                    _ => Err(ValueError::InvalidUuid(s.as_ref().to_owned())),
+                }
+            }
+        }?;
+
+        if let Some(ref v) = value {
+            self.check_value(s.as_ref(), v)?;
+        }
+        Ok(value)
+    }
+
+    /// Check a parsed value against range, length and pattern constraints
+    fn check_value(&self, s: &str, value: &Value) -> Result<(), ValueError> {
+        let ccs = &self.constraints;
+
+        let number = match *value {
+            Value::Integer(i) => Some(Decimal::from(i)),
+            Value::Decimal(ref d) => Some(d.clone()),
+            _ => None,
+        };
+        if let Some(n) = number {
+            let below = ccs.min.as_ref().is_some_and(|min| n < *min);
+            let above = ccs.max.as_ref().is_some_and(|max| n > *max);
+            if below || above {
+                return Err(ValueError::OutOfRange(s.to_owned(), ccs.min.clone(), ccs.max.clone()));
+            }
+        }
+
+        if let Value::String(ref v) = *value {
+            if let Some(max_len) = ccs.max_len {
+                let len = v.chars().count();
+                if len > max_len {
+                    return Err(ValueError::TooLong(len, max_len));
+                }
+            }
+            if let Some(ref pattern) = ccs.pattern {
+                if !pattern.is_match(v) {
+                    return Err(ValueError::PatternMismatch(v.clone(), pattern.to_string()));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Render a value in its canonical csvx form
+    ///
+    /// The value must match the column's type; the rendered result is
+    /// checked using `validate_value` before being returned.
+    pub fn render_value(&self, value: &Option<Value>) -> Result<String, ValueError> {
+        let s = match (value, &self.ty) {
+            (None, _) => String::new(),
+            (Some(Value::String(s)), ColumnType::String) => s.clone(),
+            (Some(Value::Bool(b)), ColumnType::Bool) => {
+                if *b { "TRUE" } else { "FALSE" }.to_owned()
+            }
+            (Some(Value::Integer(i)), ColumnType::Integer) => i.to_string(),
+            (Some(Value::Enum(idx)), ColumnType::Enum(variants)) => {
+                variants.get(*idx).cloned().ok_or_else(|| {
+                    ValueError::InvalidEnum(idx.to_string(), variants.clone())
+                })?
+            }
+            (Some(Value::Decimal(d)), ColumnType::Decimal(spec)) => {
+                // pad to the declared scale, the result is validated below
+                match spec.and_then(|(_, scale)| d.rescale(scale)) {
+                    Some(rescaled) => rescaled.to_string(),
+                    None => d.to_string(),
+                }
+            }
+            (Some(Value::Date(d)), ColumnType::Date) => d.format("%Y%m%d").to_string(),
+            (Some(Value::DateTime(dt)), ColumnType::DateTime(digits)) => {
+                dt.format("%Y%m%d%H%M%S").to_string() + &render_fraction(dt.nanosecond(), *digits)?
+            }
+            (Some(Value::DateTimeTz(dt)), ColumnType::DateTimeTz(digits)) => {
+                let offset = dt.offset().fix().local_minus_utc();
+                let offset = if offset == 0 {
+                    "Z".to_owned()
+                } else {
+                    format!(
+                        "{}{:02}{:02}",
+                        if offset < 0 { '-' } else { '+' },
+                        offset.abs() / 3600,
+                        offset.abs() % 3600 / 60
+                    )
+                };
+                let fraction = render_fraction(dt.nanosecond(), *digits)?;
+                dt.format("%Y%m%d%H%M%S").to_string() + &fraction + &offset
+            }
+            (Some(Value::Time(t)), ColumnType::Time) => t.format("%H%M%S").to_string(),
+            (Some(Value::Uuid(u)), ColumnType::Uuid) => u.to_hyphenated().to_string(),
+            _ => return Err(ValueError::WrongType(self.ty.to_string())),
+        };
+
+        self.validate_value(&s)?;
+        Ok(s)
+    }
+
+    /// Render a value such that equal values give equal results
+    ///
+    /// Unlike `render_value`, the result does not depend on how the value is
+    /// written: `1.5` and `1.50` give the same key, as do datetimes denoting
+    /// the same point in time. Keys of columns of the same base type, e.g.
+    /// `DECIMAL` and `DECIMAL(10,2)`, can be compared to each other.
+    pub fn value_key(&self, value: &Value) -> Result<String, ValueError> {
+        let datetime_key = |dt: &NaiveDateTime| -> Result<String, ValueError> {
+            let fraction = render_fraction(dt.nanosecond(), 9)?;
+            Ok(dt.format("%Y%m%d%H%M%S").to_string() +
+                fraction.trim_end_matches('0').trim_end_matches('.'))
+        };
+
+        match (value, &self.ty) {
+            (Value::Decimal(d), ColumnType::Decimal(_)) => Ok(d.normalize().to_string()),
+            (Value::DateTime(dt), ColumnType::DateTime(_)) => datetime_key(dt),
+            (Value::DateTimeTz(dt), ColumnType::DateTimeTz(_)) => {
+                Ok(datetime_key(&dt.naive_utc())? + "Z")
+            }
+            _ => self.render_value(&Some(value.clone())),
         }
     }
 }
@@ -340,6 +970,7 @@ impl CsvxColumnType {
 #[derive(Clone, Debug)]
 pub struct CsvxSchema {
     columns: Vec<CsvxColumnType>,
+    checks: Vec<expr::Check>,
 }
 
 impl CsvxSchema {
@@ -351,13 +982,51 @@ impl CsvxSchema {
         self.columns.iter().position(|c| col == c.id)
     }
 
+    /// All keys declared by the columns' constraints
+    ///
+    /// The primary key, if any, comes first, followed by named keys in the
+    /// order they are first mentioned.
+    pub fn keys(&self) -> Vec<TableKey> {
+        let mut keys = Vec::new();
+
+        let primary: Vec<_> = self.columns
+            .iter()
+            .filter(|c| c.constraints.primary)
+            .map(|c| c.id.clone())
+            .collect();
+        if !primary.is_empty() {
+            keys.push(TableKey {
+                name: None,
+                columns: primary,
+            });
+        }
+
+        for col in &self.columns {
+            for name in &col.constraints.keys {
+                if keys.iter().any(|k| k.name.as_ref() == Some(name)) {
+                    continue;
+                }
+                keys.push(TableKey {
+                    name: Some(name.clone()),
+                    columns: self.columns
+                        .iter()
+                        .filter(|c| c.constraints.keys.contains(name))
+                        .map(|c| c.id.clone())
+                        .collect(),
+                });
+            }
+        }
+
+        keys
+    }
+
     pub fn from_file<P: AsRef<path::Path>>(
         filename: P,
     ) -> Result<CsvxSchema, ErrorAtLocation<SchemaLoadError, Location>> {
 
         // have a copy of the filename as a string ready for error locations
         let filename_s: String = filename.as_ref().to_string_lossy().into_owned();
-        let mut file = fs::File::open(filename).err_at(|| {
+        let mut file = open_file(filename).err_at(|| {
             Location::File(filename_s.clone())
         })?;
         let mut contents = String::new();
@@ -375,12 +1044,16 @@ impl CsvxSchema {
         // have a copy of the filename as a string ready for error locations
         let filename_s = filename.to_string();
 
-        let mut rdr = csv::Reader::from_string(src).has_headers(false);
+        let mut records = Records::new(src.as_bytes());
+        let csv_error = |e: ErrorAtLocation<csv::Error, _>| {
+            e.convert().map_location(|p: reader::Position| p.in_file(&filename_s))
+        };
 
-        let mut it = rdr.decode();
-        let header: Option<Result<(String, String, String, String), _>> = it.next();
+        let header = records.next_record();
 
-        let mut columns = Vec::new();
+        let mut builder = CsvxSchemaBuilder::new();
+        // ids of the columns added, along with the location of their constraints
+        let mut ids = Vec::new();
 
         match header {
             None => {
@@ -390,53 +1063,62 @@ impl CsvxSchema {
                 )))
             }
             Some(res) => {
-                let fields = res.err_at(|| Location::File(filename_s.clone()))?;
-                if fields.0 != "id" || fields.1 != "type" || fields.2 != "constraints" ||
-                    fields.3 != "description"
-                {
-
+                let fields = res.map_err(&csv_error)?.fields;
+
+                // required fields come first, optional ones may follow in
+                // any order, but only once
+                let bad_header = fields.len() < 4 ||
+                    fields[..4] != ["id", "type", "constraints", "description"] ||
+                    fields[4..].iter().enumerate().any(|(i, f)| {
+                        !OPTIONAL_FIELDS.contains(&f.as_str()) || fields[4..4 + i].contains(f)
+                    });
+                if bad_header {
                     return Err(SchemaLoadError::BadHeader.at(
                         Location::FileLine(filename_s, 1),
                     ));
                 }
-
-                for (recno, rec) in it.enumerate() {
-                    let (id, ty, constraints, desc) =
-                        rec.err_at(|| Location::FileLine(filename_s.clone(), 1))?;
-                    let lineno = recno + 2;
-
-                    // check identifier
-                    if !IDENT_UNDERSCORE_RE.is_match(&id.as_str()) {
-                        return Err(SchemaLoadError::BadIdentifier(id).at(
-                            Location::FileLineField(
-                                filename_s,
-                                lineno,
-                                1,
-                            ),
-                        ));
-                    }
+                let position = |name: &str| fields.iter().position(|f| f == name);
+                let default_idx = position("default");
+                let example_idx = position("example");
+                let tags_idx = position("tags");
+
+                while let Some(rec) = records.next_record() {
+                    let record = rec.map_err(&csv_error)?;
+                    let at_field = |col_idx| record.location(&filename_s, col_idx);
+                    let mut rec = record.fields.clone();
+
+                    // empty optional fields are the same as missing ones
+                    let optional_field = |name: &str| {
+                        position(name).map(|i| rec[i].clone()).filter(|v| !v.is_empty())
+                    };
+                    let default = optional_field("default");
+                    let example = optional_field("example");
+                    let metadata = ColumnMetadata::from_fields(optional_field).map_err(
+                        |(name, value)| {
+                            let idx = position(name).safe_unwrap("field is present");
+                            SchemaLoadError::BadMetadata(name.to_owned(), value)
+                                .at(at_field(idx + 1))
+                        },
+                    )?;
+                    rec.truncate(4);
+                    let desc = rec.pop().safe_unwrap("header has four fields");
+                    let constraints = rec.pop().safe_unwrap("header has four fields");
+                    let ty = rec.pop().safe_unwrap("header has four fields");
+                    let id = rec.pop().safe_unwrap("header has four fields");
 
                     // create type
                     let col_type = match ColumnType::try_from(ty.as_str()) {
                         Ok(v) => v,
                         Err(e) => {
-                            return Err(SchemaLoadError::BadType(e).at(Location::FileLineField(
-                                filename_s,
-                                lineno,
-                                1,
-                            )))
+                            return Err(SchemaLoadError::BadType(e).at(at_field(1)))
                         }
                     };
 
                     // create constraints
                     let col_constraints = match ColumnConstraints::try_from(constraints.as_str()) {
                         Ok(v) => v,
-                        // FIXME: location
                         Err(e) => {
-                            return Err(SchemaLoadError::BadConstraints(e).at(Location::FileLine(
-                                filename_s,
-                                lineno,
-                            )))
+                            return Err(SchemaLoadError::BadConstraints(e).at(at_field(3)))
                         }
                     };
 
@@ -445,75 +1127,249 @@ impl CsvxSchema {
                         ty: col_type,
                         constraints: col_constraints,
                         description: desc,
+                        default: default,
+                        example: example,
+                        metadata: metadata,
                     };
 
-                    columns.push(col)
+                    // checks the identifier and rejects duplicates
+                    ids.push((col.id.clone(), at_field(3)));
+                    builder.add_column(col).map_err(|e| {
+                        let idx = match e {
+                            SchemaLoadError::BadDefault(_, _) => default_idx,
+                            SchemaLoadError::BadExample(_, _) => example_idx,
+                            SchemaLoadError::UnmaskableColumn(_) => tags_idx,
+                            _ => None,
+                        };
+                        let field = idx.map_or(1, |i| i + 1);
+                        e.at(at_field(field))
+                    })?;
                 }
 
-                Ok(CsvxSchema { columns: columns })
+                builder.build().map_err(|e| {
+                    // locate check errors at the constraints of their column
+                    let loc = match e {
+                        SchemaLoadError::BadCheck(ref id, _) => {
+                            let col = ids.iter().find(|c| c.0 == *id);
+                            col.safe_unwrap("known column").1.clone()
+                        }
+                        _ => Location::File(filename_s.clone()),
+                    };
+                    e.at(loc)
+                })
             }
         }
     }
 
-    pub fn validate_file<P: AsRef<path::Path>>(
-        &self,
-        filename: P,
-    ) -> Result<(), Vec<ErrorAtLocation<ValidationError, Location>>> {
-        let filename_s = filename.as_ref().to_string_lossy().to_string();
-
-        let mut rdr = csv::Reader::from_file(filename)
-            .map_err(|e| vec![e.at(Location::File(filename_s.clone()))])?
-            .has_headers(true);
-
-        let headers = rdr.headers().map_err(|e| {
-            vec![e.at(Location::FileLine(filename_s.clone(), 1))]
-        })?;
+    /// Write the schema as a csvx schema file
+    pub fn write_to<W: io::Write>(&self, out: &mut W) -> io::Result<()> {
+        // optional fields are only written if used
+        let optional: Vec<_> = OPTIONAL_FIELDS
+            .iter()
+            .filter(|name| {
+                self.columns.iter().any(|c| !c.render_optional_field(name).is_empty())
+            })
+            .collect();
+
+        let mut header = vec!["id", "type", "constraints", "description"];
+        header.extend(optional.iter().cloned());
+        writer::write_record(out, &header)?;
+
+        for col in &self.columns {
+            let mut rec = vec![
+                col.id.clone(),
+                col.ty.to_string(),
+                col.constraints.to_string(),
+                col.description.clone(),
+            ];
+            rec.extend(optional.iter().map(|name| col.render_optional_field(name)));
+            writer::write_record(out, &rec)?;
+        }
+        Ok(())
+    }
 
+    /// Check a file's header row against the schema
+    fn check_headers(
+        &self,
+        headers: &[String],
+        filename: &str,
+    ) -> Vec<ErrorAtLocation<ValidationError, Location>> {
         if headers.len() != self.columns.len() {
-            return Err(vec![
+            return vec![
                 ValidationError::MissingHeaders.at(Location::FileLine(
-                    filename_s.clone(),
+                    filename.to_owned(),
                     1,
                 )),
-            ]);
+            ];
         }
 
         let mut errs = Vec::new();
-
         for (idx, (spec, actual)) in self.columns.iter().zip(headers.iter()).enumerate() {
             if spec.id.as_str() != actual {
                 errs.push(ValidationError::HeaderMismatch(actual.to_string()).at(
-                    Location::FileLineField(filename_s.clone(), 1, idx + 1),
+                    Location::FileLineField(filename.to_owned(), 1, idx + 1),
                 ));
             }
         }
+        errs
+    }
+
+    pub fn validate_file<P: AsRef<path::Path>>(
+        &self,
+        filename: P,
+    ) -> Result<(), Vec<ErrorAtLocation<ValidationError, Location>>> {
+        let filename_s = filename.as_ref().to_string_lossy().to_string();
+
+        let mut data = Vec::new();
+        open_file(filename)
+            .and_then(|mut file| file.read_to_end(&mut data))
+            .map_err(|e| {
+                vec![ValidationError::Io(e).at(Location::File(filename_s.clone()))]
+            })?;
+
+        // check the raw bytes first, the csv reader is not strict enough
+        let mut errs = format::check_format(&data, &filename_s);
+
+        // rows of the wrong length are reported below, instead of aborting
+        let mut records = Records::new(&data[..]).flexible(true);
+        let csv_error = |e: ErrorAtLocation<csv::Error, _>| {
+            e.convert().map_location(|p: reader::Position| p.in_file(&filename_s))
+        };
+
+        let headers = match records.next_record() {
+            Some(Ok(record)) => record.fields,
+            Some(Err(e)) => {
+                errs.push(csv_error(e));
+                return Err(errs);
+            }
+            None => Vec::new(),
+        };
 
         // bail if headers are incorrect
-        if errs.len() != 0 {
+        let header_errs = self.check_headers(&headers, &filename_s);
+        if !header_errs.is_empty() {
+            errs.extend(header_errs);
             return Err(errs);
         }
 
-        for (rowid, row) in rdr.records().enumerate() {
-            let lineno = rowid + 2;
+        // for every `UNIQUE` column, remember the line each value was first
+        // seen on, by its key
+        let mut unique_values: Vec<Option<HashMap<String, usize>>> = self.columns
+            .iter()
+            .map(|col| if col.constraints.unique {
+                Some(HashMap::new())
+            } else {
+                None
+            })
+            .collect();
+
+        // same for every key, remembering combinations of values instead
+        let mut keys: Vec<_> = self.keys()
+            .into_iter()
+            .map(|key| {
+                let idxs: Vec<_> = key.columns
+                    .iter()
+                    .map(|id| self.col_idx(id).safe_unwrap("key columns exist"))
+                    .collect();
+                (key, idxs, HashMap::new())
+            })
+            .collect();
 
+        while let Some(row) = records.next_record() {
             // bail early if we cannot read the fields, this is probably a
             // major csv issue
-            let fields = row.map_err(
-                |e| vec![e.at(Location::FileLine(filename_s.clone(), 1))],
-            )?;
+            let record = match row {
+                Ok(record) => record,
+                Err(e) => {
+                    errs.push(csv_error(e));
+                    return Err(errs);
+                }
+            };
+            let fields = &record.fields;
+            let lineno = record.line();
+            let at_field = |col_idx| record.location(&filename_s, col_idx);
+
+            if fields.len() != self.columns.len() {
+                errs.push(
+                    ValidationError::FieldCount(self.columns.len(), fields.len()).at(at_field(0)),
+                );
+                continue;
+            }
+
+            // keys of the columns holding a valid, non-NULL value
+            let mut value_keys = vec![None; self.columns.len()];
+            let mut values = Vec::with_capacity(self.columns.len());
 
             for (idx, (col, value)) in self.columns.iter().zip(fields.iter()).enumerate() {
-                if let Err(e) = col.validate_value(value) {
-                    let col_idx = idx + 1;
+                let col_idx = idx + 1;
 
-                    errs.push(ValidationError::ValueError(e).at(Location::FileLineField(
-                        filename_s.clone(),
-                        lineno,
-                        col_idx,
-                    )));
-                    continue;
+                match col.validate_value(value) {
+                    Err(e) => {
+                        errs.push(ValidationError::ValueError(e).at(at_field(col_idx)));
+                        continue;
+                    }
+                    // NULL values never collide with each other
+                    Ok(None) => values.push(None),
+                    Ok(Some(v)) => {
+                        let value_key =
+                            col.value_key(&v).safe_unwrap("valid values can be rendered");
+                        values.push(Some(v));
+
+                        if let Some(ref mut seen) = unique_values[idx] {
+                            match seen.entry(value_key.clone()) {
+                                Entry::Occupied(first) => {
+                                    errs.push(
+                                        ValidationError::DuplicateValue(
+                                            value.clone(),
+                                            Location::FileLineField(
+                                                filename_s.clone(),
+                                                *first.get(),
+                                                col_idx,
+                                            ),
+                                        ).at(at_field(col_idx)),
+                                    );
+                                }
+                                Entry::Vacant(slot) => {
+                                    slot.insert(record.positions[idx].line);
+                                }
+                            }
+                        }
+                        value_keys[idx] = Some(value_key);
+                    }
                 }
             }
+
+            // like `UNIQUE`, keys with a NULL (or invalid) part never collide
+            for &mut (ref key, ref idxs, ref mut seen) in &mut keys {
+                let parts: Option<Vec<_>> =
+                    idxs.iter().map(|&idx| value_keys[idx].clone()).collect();
+                let parts = match parts {
+                    Some(parts) => parts,
+                    None => continue,
+                };
+
+                match seen.entry(parts) {
+                    Entry::Occupied(first) => {
+                        errs.push(
+                            ValidationError::DuplicateKey(
+                                Box::new(key.clone()),
+                                idxs.iter().map(|&idx| fields[idx].clone()).collect(),
+                                Location::FileLine(filename_s.clone(), *first.get()),
+                            ).at(at_field(0)),
+                        );
+                    }
+                    Entry::Vacant(slot) => {
+                        slot.insert(lineno);
+                    }
+                }
+            }
+
+            // checks are only meaningful if all values are valid
+            if values.len() == self.columns.len() {
+                errs.extend(
+                    self.check_row(&values).into_iter().map(|e| e.map_location(at_field)),
+                );
+            }
         }
 
         if errs.len() != 0 {
@@ -527,8 +1383,12 @@ impl CsvxSchema {
         &self,
         fields: &T,
     ) -> Result<Vec<Option<Value>>, ErrorAtLocation<ValidationError, usize>> {
-        let mut rv = Vec::with_capacity(self.columns.len());
         let fields = fields.as_ref();
+        if fields.len() != self.columns.len() {
+            return Err(ValidationError::FieldCount(self.columns.len(), fields.len()).at(0));
+        }
+
+        let mut rv = Vec::with_capacity(self.columns.len());
         for (idx, (col, value)) in self.columns.iter().zip(fields.iter()).enumerate() {
             match col.validate_value(value) {
                 Err(e) => {
@@ -539,9 +1399,26 @@ impl CsvxSchema {
                 Ok(v) => rv.push(v),
             }
         }
+
+        if let Some(e) = self.check_row(&rv).into_iter().next() {
+            return Err(e);
+        }
         Ok(rv)
     }
 
+    /// Evaluate all `CHECK` constraints against a row of values
+    ///
+    /// Errors are located at the column declaring the failed check.
+    pub fn check_row(&self, values: &[Option<Value>]) -> Vec<ErrorAtLocation<ValidationError, usize>> {
+        self.checks
+            .iter()
+            .filter(|check| check.compiled.eval(values) == Some(false))
+            .map(|check| {
+                ValidationError::CheckFailed(check.expr.clone()).at(check.column + 1)
+            })
+            .collect()
+    }
+
     pub fn read_field<T: AsRef<[String]>>(
         &self,
         fields: &T,
@@ -567,6 +1444,74 @@ impl CsvxSchema {
 }
 
 
+/// Parse a `DATETIME` or `DATETIMETZ` value
+///
+/// Returns the local date and time, along with the offset if `tz` is set.
+fn parse_datetime(
+    s: &str,
+    digits: u32,
+    tz: bool,
+) -> Result<(NaiveDateTime, Option<FixedOffset>), ValueError> {
+    let invalid = || if tz {
+        ValueError::InvalidDateTimeTz(s.to_owned())
+    } else {
+        ValueError::InvalidDateTime(s.to_owned())
+    };
+
+    let c = DATETIME_RE.captures(s).ok_or_else(&invalid)?;
+    if c.get(8).is_some() != tz {
+        return Err(invalid());
+    }
+
+    let fraction = c.get(7).map_or("", |m| m.as_str());
+    if fraction.len() != digits as usize {
+        return Err(ValueError::WrongFraction(s.to_owned(), digits));
+    }
+    // pad to nanoseconds
+    let nanos = if fraction.is_empty() {
+        0
+    } else {
+        cap::<u32>(&c, 7) * 10u32.pow(9 - digits)
+    };
+
+    let dt = NaiveDate::from_ymd_opt(cap(&c, 1), cap(&c, 2), cap(&c, 3))
+        .ok_or_else(|| ValueError::InvalidDate(s.to_owned()))?
+        .and_hms_nano_opt(cap(&c, 4), cap(&c, 5), cap(&c, 6), nanos)
+        .ok_or_else(|| ValueError::InvalidTime(s.to_owned()))?;
+
+    let offset = match c.get(8).map(|m| m.as_str()) {
+        None => None,
+        Some("Z") => Some(FixedOffset::east_opt(0).safe_unwrap("zero offset is valid")),
+        Some(o) => {
+            let hours: i32 = o[1..3].parse().map_err(|_| invalid())?;
+            let minutes: i32 = o[3..5].parse().map_err(|_| invalid())?;
+            if hours > 23 || minutes > 59 {
+                return Err(invalid());
+            }
+            let secs = (hours * 60 + minutes) * 60;
+            Some(
+                FixedOffset::east_opt(if o.starts_with('-') { -secs } else { secs })
+                    .ok_or_else(&invalid)?,
+            )
+        }
+    };
+
+    Ok((dt, offset))
+}
+
+/// Render the fractional part of a second, including the decimal point
+///
+/// Fails if digits that are not zero would have to be dropped.
+fn render_fraction(nanos: u32, digits: u32) -> Result<String, ValueError> {
+    let all = format!("{:09}", nanos);
+    let (kept, dropped) = all.split_at(digits as usize);
+
+    if dropped.bytes().any(|c| c != b'0') {
+        return Err(ValueError::WrongFraction(format!("0.{}", all), digits));
+    }
+    Ok(if digits == 0 { String::new() } else { format!(".{}", kept) })
+}
+
 #[inline]
 fn cap<T>(c: &regex::Captures, idx: usize) -> T
 where
@@ -589,6 +1534,10 @@ pub fn parse_filename<S: AsRef<str>>(filename: S) -> Option<CsvxMetadata> {
             let month = cap(&caps, 3);
             let day = cap(&caps, 4);
             let schema = caps.get(5).safe_unwrap("known group").as_str().to_string();
+            let compression = caps.get(6).map(|m| match m.as_str() {
+                "gzip" => Compression::Gzip,
+                _ => Compression::Xz,
+            });
 
             Some(CsvxMetadata {
                 table_name: table_name,
@@ -597,6 +1546,7 @@ pub fn parse_filename<S: AsRef<str>>(filename: S) -> Option<CsvxMetadata> {
                     None => return None,
                 },
                 schema: schema,
+                compression: compression,
             })
         }
         None => None,
@@ -609,6 +1559,28 @@ mod test {
     use super::*;
 
     use chrono::NaiveDate;
+    use std::env;
+    use std::io::Write;
+
+    const ANIMALS_SCHEMA: &str = "id,type,constraints,description\r\n\
+        id,INTEGER,UNIQUE,Internal zoo id\r\n\
+        name,STRING,,Name of the animal\r\n\
+        caretaker,STRING,NULLABLE,Designated caretaker\r\n";
+
+    fn validate_data(
+        schema: &CsvxSchema,
+        name: &str,
+        data: &str,
+    ) -> Result<(), Vec<ErrorAtLocation<ValidationError, Location>>> {
+        let path = env::temp_dir().join(name);
+        fs::File::create(&path)
+            .unwrap()
+            .write_all(data.as_bytes())
+            .unwrap();
+        let res = schema.validate_file(&path);
+        fs::remove_file(&path).unwrap();
+        res
+    }
 
     #[test]
     fn filename_parsing_rejects_invalid() {
@@ -626,8 +1598,660 @@ mod test {
                 table_name: "zoo-nyc".to_owned(),
                 date: NaiveDate::from_ymd(2017, 04, 01),
                 schema: "animals-2".to_owned(),
+                compression: None,
+            }
+        );
+        assert_eq!(
+            parse_filename("zoo-nyc_20170401_animals-2.csv.xz").unwrap(),
+            CsvxMetadata {
+                table_name: "zoo-nyc".to_owned(),
+                date: NaiveDate::from_ymd(2017, 4, 1),
+                schema: "animals-2".to_owned(),
+                compression: Some(Compression::Xz),
+            }
+        );
+        assert_eq!(
+            parse_filename("zoo-nyc_20170401_animals-2.csv.gzip")
+                .unwrap()
+                .compression,
+            Some(Compression::Gzip)
+        );
+        assert_eq!(parse_filename("zoo-nyc_20170401_animals-2.csv.bz2"), None);
+        assert_eq!(parse_filename("zoo-nyc_20170401_animals-2xcsv"), None);
+    }
+
+    #[test]
+    fn unique_columns_reject_duplicates() {
+        let schema = CsvxSchema::from_string(ANIMALS_SCHEMA, "schema.csv").unwrap();
+        let errs = validate_data(
+            &schema,
+            "zoo-unique_20170401_animals-2.csv",
+            "id,name,caretaker\r\n\
+             1,Brian,Sam\r\n\
+             2,Pinky,\r\n\
+             1,Brain,\r\n",
+        ).unwrap_err();
+
+        assert_eq!(errs.len(), 1);
+        match *errs[0].location() {
+            Location::FileLineField(_, 4, 1) => (),
+            ref loc => panic!("unexpected location {:?}", loc),
+        }
+        match *errs[0].error() {
+            ValidationError::DuplicateValue(ref v, Location::FileLineField(_, 2, 1)) => {
+                assert_eq!(v, "1")
+            }
+            ref e => panic!("unexpected error {:?}", e),
+        }
+    }
+
+    #[test]
+    fn ragged_rows_are_reported() {
+        let schema = CsvxSchema::from_string(ANIMALS_SCHEMA, "schema.csv").unwrap();
+        let errs = validate_data(
+            &schema,
+            "zoo-ragged_20170401_animals-2.csv",
+            "id,name,caretaker\r\n\
+             1,Brian\r\n\
+             2,Pinky,,\r\n\
+             x,Brain,\r\n",
+        ).unwrap_err();
+
+        assert_eq!(errs.len(), 3);
+        for (err, &(line, expected, actual)) in errs.iter().zip(&[(2, 3, 2), (3, 3, 4)]) {
+            match (err.location(), err.error()) {
+                (&Location::FileLine(_, l), &ValidationError::FieldCount(e, a)) => {
+                    assert_eq!((l, e, a), (line, expected, actual))
+                }
+                res => panic!("unexpected error {:?}", res),
+            }
+        }
+
+        // rows of the correct length are still checked
+        match *errs[2].location() {
+            Location::FileLineField(_, 4, 1) => (),
+            ref loc => panic!("unexpected location {:?}", loc),
+        }
+
+        let short = vec!["1".to_owned(), "Brian".to_owned()];
+        match schema.parse_row(&short) {
+            Err(ref e) if *e.location() == 0 => match *e.error() {
+                ValidationError::FieldCount(3, 2) => (),
+                ref e => panic!("unexpected error {:?}", e),
+            },
+            res => panic!("unexpected result {:?}", res),
+        }
+    }
+
+    #[test]
+    fn multi_line_fields_keep_line_numbers() {
+        let err = CsvxSchema::from_string(
+            "id,type,constraints,description\r\n\
+             id,INTEGER,UNIQUE,\"Internal zoo id,\r\nassigned on arrival\"\r\n\
+             name,STRING,,Name of the animal\r\n\
+             caretaker,STRING,NULABLE,Designated caretaker\r\n",
+            "schema.csv",
+        ).unwrap_err();
+        match *err.location() {
+            Location::FileLineField(_, 5, 3) => (),
+            ref loc => panic!("unexpected location {:?}", loc),
+        }
+
+        let schema = CsvxSchema::from_string(ANIMALS_SCHEMA, "schema.csv").unwrap();
+        let errs = validate_data(
+            &schema,
+            "zoo-multiline_20170401_animals-2.csv",
+            "id,name,caretaker\r\n\
+             1,\"Brian\r\nthe\r\nGorilla\",Sam\r\n\
+             x,Pinky,\r\n\
+             1,Tweety,\"Big\r\nBird\"\r\n\
+             4,Rex\r\n",
+        ).unwrap_err();
+
+        assert_eq!(errs.len(), 3);
+        match (errs[0].location(), errs[1].location(), errs[2].location()) {
+            (
+                &Location::FileLineField(_, 5, 1),
+                &Location::FileLineField(_, 6, 1),
+                &Location::FileLine(_, 8),
+            ) => (),
+            locs => panic!("unexpected locations {:?}", locs),
+        }
+        match *errs[1].error() {
+            ValidationError::DuplicateValue(_, Location::FileLineField(_, 2, 1)) => (),
+            ref e => panic!("unexpected error {:?}", e),
+        }
+    }
+
+    #[test]
+    fn unique_columns_ignore_nulls() {
+        let schema = CsvxSchema::from_string(
+            "id,type,constraints,description\r\n\
+             tag,STRING,\"NULLABLE,UNIQUE\",Optional tag\r\n",
+            "schema.csv",
+        ).unwrap();
+
+        assert!(
+            validate_data(
+                &schema,
+                "zoo-nulls_20170401_tags-1.csv",
+                "tag\r\n\"\"\r\nA\r\n\"\"\r\nB\r\n",
+            ).is_ok()
+        );
+    }
+
+    #[test]
+    fn unique_values_are_compared_by_value() {
+        let schema = CsvxSchema::from_string(
+            "id,type,constraints,description\r\n\
+             cost,DECIMAL,UNIQUE,\r\n\
+             fed,DATETIMETZ,UNIQUE,\r\n\
+             enclosure,INTEGER,PRIMARY,\r\n\
+             weight,DECIMAL,PRIMARY,\r\n",
+            "schema.csv",
+        ).unwrap();
+
+        let errs = validate_data(
+            &schema,
+            "zoo-values_20170401_feedings-1.csv",
+            "cost,fed,enclosure,weight\r\n\
+             1.5,20151231230158Z,1,10\r\n\
+             1.50,20160101000158+0100,1,10.0\r\n",
+        ).unwrap_err();
+
+        let kinds: Vec<_> = errs.iter()
+            .map(|e| match *e.error() {
+                ValidationError::DuplicateValue(ref v, _) => v.clone(),
+                ValidationError::DuplicateKey(_, ref values, _) => values.join(","),
+                ref e => panic!("unexpected error {:?}", e),
+            })
+            .collect();
+        assert_eq!(kinds, vec!["1.50", "20160101000158+0100", "1,10.0"]);
+
+        let cols: Vec<_> = schema.iter_columns().collect();
+        let key = |col: &CsvxColumnType, s| {
+            col.value_key(&col.validate_value(&s).unwrap().unwrap()).unwrap()
+        };
+        assert_eq!(key(cols[0], "0.250"), "0.25");
+        assert_eq!(key(cols[0], "3.000"), "3");
+        assert_eq!(key(cols[1], "20160101000158-0130"), "20160101013158Z");
+        let precise = CsvxColumnType::new("fed", ColumnType::DateTimeTz(3));
+        assert_eq!(key(&precise, "20160101000158.500+0100"), "20151231230158.5Z");
+    }
+
+    #[test]
+    fn constraints_are_parsed() {
+        let ccs = ColumnConstraints::try_from("NULLABLE,REFERENCES(staff-1.id)").unwrap();
+        assert!(ccs.nullable);
+        assert_eq!(
+            ccs.references,
+            Some(ForeignKey {
+                schema: "staff-1".to_owned(),
+                column: "id".to_owned(),
+            })
+        );
+        assert_eq!(ccs.to_string(), "NULLABLE,REFERENCES(staff-1.id)");
+
+        for bad in &["REFERENCES(staff)", "REFERENCES(staff.id", "NULLABLE,", "nullable"] {
+            match ColumnConstraints::try_from(*bad) {
+                Err(ColumnConstraintsError::MalformedConstraints(_)) => (),
+                res => panic!("unexpected result for {:?}: {:?}", bad, res),
+            }
+        }
+        match ColumnConstraints::try_from("UNIQUE,INDEXED") {
+            Err(ColumnConstraintsError::UnknownConstraint(ref s)) => assert_eq!(s, "INDEXED"),
+            res => panic!("unexpected result {:?}", res),
+        }
+
+        let ccs = ColumnConstraints::try_from("KEY(visit),PRIMARY,KEY(visit),KEY(shift)")
+            .unwrap();
+        assert!(ccs.primary);
+        assert_eq!(ccs.keys, vec!["visit".to_owned(), "shift".to_owned()]);
+        assert_eq!(ccs.to_string(), "PRIMARY,KEY(visit),KEY(shift)");
+        assert!(ColumnConstraints::try_from("KEY(Visit)").is_err());
+    }
+
+    #[test]
+    fn composite_keys_reject_duplicates() {
+        let schema = CsvxSchema::from_string(
+            "id,type,constraints,description\r\n\
+             enclosure,INTEGER,PRIMARY,Enclosure number\r\n\
+             date,DATE,\"PRIMARY,KEY(visit)\",Day of the feeding\r\n\
+             keeper,STRING,\"NULLABLE,KEY(visit)\",Keeper on duty\r\n",
+            "schema.csv",
+        ).unwrap();
+
+        let keys = schema.keys();
+        assert_eq!(keys.len(), 2);
+        assert_eq!(keys[0].to_string(), "primary key (enclosure, date)");
+        assert_eq!(keys[1].to_string(), "key `visit` (date, keeper)");
+
+        let errs = validate_data(
+            &schema,
+            "zoo-keys_20170401_feedings-1.csv",
+            "enclosure,date,keeper\r\n\
+             1,20170401,Sam\r\n\
+             2,20170401,\r\n\
+             1,20170402,\r\n\
+             2,20170401,Sam\r\n",
+        ).unwrap_err();
+
+        assert_eq!(errs.len(), 2);
+        match (errs[0].location(), errs[0].error()) {
+            (Location::FileLine(_, 5),
+             ValidationError::DuplicateKey(key, values, Location::FileLine(_, 3))) => {
+                assert_eq!(key.name, None);
+                assert_eq!(values, &vec!["2".to_owned(), "20170401".to_owned()]);
             }
+            (loc, e) => panic!("unexpected error {:?} at {:?}", e, loc),
+        }
+        match (errs[1].location(), errs[1].error()) {
+            (Location::FileLine(_, 5),
+             ValidationError::DuplicateKey(key, _, Location::FileLine(_, 2))) => {
+                assert_eq!(key.name, Some("visit".to_owned()))
+            }
+            (loc, e) => panic!("unexpected error {:?} at {:?}", e, loc),
+        }
+
+        // primary keys cannot be incomplete
+        let err = CsvxSchema::from_string(
+            "id,type,constraints,description\r\n\
+             id,INTEGER,\"PRIMARY,NULLABLE\",\r\n",
+            "schema.csv",
+        ).unwrap_err();
+        match *err.error() {
+            SchemaLoadError::NullablePrimaryKey(_) => (),
+            ref e => panic!("unexpected error {:?}", e),
+        }
+    }
+
+    #[test]
+    fn check_constraints_are_enforced() {
+        let schema = CsvxSchema::from_string(
+            "id,type,constraints,description\r\n\
+             start_date,DATE,,\r\n\
+             end_date,DATE,\"NULLABLE,CHECK(end_date >= start_date)\",\r\n\
+             name,STRING,\"CHECK(name != 'N/A, (unknown)')\",\r\n",
+            "schema.csv",
+        ).unwrap();
+
+        let col = schema.iter_columns().nth(2).unwrap();
+        assert_eq!(col.constraints.to_string(), "CHECK(name != 'N/A, (unknown)')");
+
+        let errs = validate_data(
+            &schema,
+            "zoo-checks_20170401_stays-1.csv",
+            "start_date,end_date,name\r\n\
+             20170101,20170102,Brian\r\n\
+             20170101,,Pinky\r\n\
+             20170101,20161231,\"N/A, (unknown)\"\r\n",
+        ).unwrap_err();
+
+        let found: Vec<_> = errs.iter().map(|e| e.to_string()).collect();
+        assert_eq!(found.len(), 2, "{:?}", found);
+        match (errs[0].location(), errs[0].error()) {
+            (Location::FileLineField(_, 4, 2), ValidationError::CheckFailed(expr)) => {
+                assert_eq!(expr.to_string(), "end_date >= start_date")
+            }
+            (loc, e) => panic!("unexpected error {:?} at {:?}", e, loc),
+        }
+        match *errs[1].location() {
+            Location::FileLineField(_, 4, 3) => (),
+            ref loc => panic!("unexpected location {:?}", loc),
+        }
+
+        let err = CsvxSchema::from_string(
+            "id,type,constraints,description\r\n\
+             weight,INTEGER,,\r\n\
+             legs,INTEGER,CHECK(legs <= wieght),\r\n",
+            "schema.csv",
+        ).unwrap_err();
+        match (err.location(), err.error()) {
+            (Location::FileLineField(_, 3, 3), SchemaLoadError::BadCheck(id, _)) => {
+                assert_eq!(id, "legs")
+            }
+            (loc, e) => panic!("unexpected error {:?} at {:?}", e, loc),
+        }
+    }
+
+    #[test]
+    fn values_are_checked_against_limits() {
+        let schema = CsvxSchema::from_string(
+            "id,type,constraints,description\r\n\
+             weight,INTEGER,\"MIN(0),MAX(500000)\",Weight in g\r\n\
+             food_cost,DECIMAL,MAX(9999.99),\r\n\
+             tag,STRING,\"NULLABLE,MAXLEN(6),PATTERN('[A-Z]{2}-\\d+|n''a')\",\r\n",
+            "schema.csv",
+        ).unwrap();
+        let cols: Vec<_> = schema.iter_columns().collect();
+
+        assert_eq!(cols[0].constraints.to_string(), "MIN(0),MAX(500000)");
+        assert_eq!(
+            cols[2].constraints.to_string(),
+            "NULLABLE,MAXLEN(6),PATTERN('[A-Z]{2}-\\d+|n''a')"
         );
+
+        assert!(cols[0].validate_value(&"0").is_ok());
+        assert!(cols[0].validate_value(&"500000").is_ok());
+        match cols[0].validate_value(&"-5") {
+            Err(ValueError::OutOfRange(ref s, Some(_), Some(_))) => assert_eq!(s, "-5"),
+            res => panic!("unexpected result {:?}", res),
+        }
+        assert!(cols[1].validate_value(&"9999.990").is_ok());
+        assert!(cols[1].validate_value(&"10000").is_err());
+
+        assert!(cols[2].validate_value(&"AB-123").is_ok());
+        assert!(cols[2].validate_value(&"n'a").is_ok());
+        assert!(cols[2].validate_value(&"").is_ok());
+        match cols[2].validate_value(&"AB-1234") {
+            Err(ValueError::TooLong(7, 6)) => (),
+            res => panic!("unexpected result {:?}", res),
+        }
+        // patterns must match the whole value
+        match cols[2].validate_value(&"xAB-1") {
+            Err(ValueError::PatternMismatch(_, _)) => (),
+            res => panic!("unexpected result {:?}", res),
+        }
+
+        let err = CsvxSchema::from_string(
+            "id,type,constraints,description\r\n\
+             name,STRING,MIN(1),\r\n",
+            "schema.csv",
+        ).unwrap_err();
+        match *err.error() {
+            SchemaLoadError::InapplicableConstraint(ref id, ref c) => {
+                assert_eq!((id.as_str(), c.as_str()), ("name", "MIN(1)"))
+            }
+            ref e => panic!("unexpected error {:?}", e),
+        }
+
+        for bad in &["MIN(abc)", "MAXLEN(-1)", "PATTERN([a-z])", "MAX"] {
+            match ColumnConstraints::try_from(*bad) {
+                Err(ColumnConstraintsError::MalformedConstraints(_)) => (),
+                res => panic!("unexpected result for {:?}: {:?}", bad, res),
+            }
+        }
+        match ColumnConstraints::try_from("PATTERN('[a-z')") {
+            Err(ColumnConstraintsError::BadPattern(ref src, _)) => assert_eq!(src, "[a-z"),
+            res => panic!("unexpected result {:?}", res),
+        }
     }
 
+    #[test]
+    fn decimal_values_are_exact() {
+        let col = CsvxColumnType {
+            id: "yearly_food_cost".to_owned(),
+            ty: ColumnType::Decimal(None),
+            constraints: ColumnConstraints::default(),
+            description: String::new(),
+            default: None,
+            example: None,
+            metadata: ColumnMetadata::default(),
+        };
+
+        let total: Decimal = ["0.10", "0.20", "1234567890123456789.70"]
+            .iter()
+            .map(|s| col.validate_value(s).unwrap().unwrap().to_decimal().unwrap())
+            .sum();
+        assert_eq!(total, "1234567890123456790".parse().unwrap());
+        assert_eq!(col.render_value(&Some(Value::Decimal(total))).unwrap(), "1234567890123456790.00");
+
+        assert!(col.validate_value(&"-1.5").is_err());
+    }
+
+    #[test]
+    fn decimal_precision_and_scale_are_enforced() {
+        let ty = ColumnType::try_from("DECIMAL(6,2)").unwrap();
+        assert_eq!(ty, ColumnType::Decimal(Some((6, 2))));
+        assert_eq!(ty.to_string(), "DECIMAL(6,2)");
+
+        for bad in &["DECIMAL(0,0)", "DECIMAL(2,3)", "DECIMAL(5)", "DECIMAL(5, 2)"] {
+            match ColumnType::try_from(*bad) {
+                Err(ColumnTypeError::BadDecimal(_)) => (),
+                res => panic!("unexpected result for {:?}: {:?}", bad, res),
+            }
+        }
+
+        let col = CsvxColumnType::new("price", ty);
+        for ok in &["0.00", "5000.00", "9999.99", "0012.50"] {
+            assert!(col.validate_value(ok).is_ok(), "{:?} should be valid", ok);
+        }
+        match col.validate_value(&"5000") {
+            Err(ValueError::WrongScale(_, 2)) => (),
+            res => panic!("unexpected result {:?}", res),
+        }
+        assert!(col.validate_value(&"5000.001").is_err());
+        match col.validate_value(&"10000.00") {
+            Err(ValueError::PrecisionExceeded(_, 6, 2)) => (),
+            res => panic!("unexpected result {:?}", res),
+        }
+
+        // values are padded to the declared scale when rendered
+        let five: Decimal = "5".parse().unwrap();
+        assert_eq!(col.render_value(&Some(Value::Decimal(five))).unwrap(), "5.00");
+        let cents: Decimal = "0.125".parse().unwrap();
+        assert!(col.render_value(&Some(Value::Decimal(cents))).is_err());
+    }
+
+    #[test]
+    fn datetimes_with_fractions_and_offsets() {
+        assert_eq!(ColumnType::try_from("DATETIME").unwrap(), ColumnType::DateTime(0));
+        assert_eq!(ColumnType::try_from("DATETIMETZ(3)").unwrap(), ColumnType::DateTimeTz(3));
+        assert_eq!(ColumnType::DateTime(6).to_string(), "DATETIME(6)");
+        for bad in &["DATETIME(0)", "DATETIME(10)", "DATETIMEZ", "DATETIMETZ()"] {
+            match ColumnType::try_from(*bad) {
+                Err(ColumnTypeError::BadDateTime(_)) => (),
+                res => panic!("unexpected result for {:?}: {:?}", bad, res),
+            }
+        }
+
+        let col = CsvxColumnType::new("seen", ColumnType::DateTime(3));
+        let seen = col.validate_value(&"20151231230158.250").unwrap();
+        assert_eq!(
+            seen.clone().unwrap().to_datetime().unwrap(),
+            NaiveDate::from_ymd_opt(2015, 12, 31)
+                .unwrap()
+                .and_hms_milli_opt(23, 1, 58, 250)
+                .unwrap()
+        );
+        assert_eq!(col.render_value(&seen).unwrap(), "20151231230158.250");
+        for bad in &["20151231230158", "20151231230158.25", "20151231230158.2500"] {
+            match col.validate_value(bad) {
+                Err(ValueError::WrongFraction(_, 3)) => (),
+                res => panic!("unexpected result for {:?}: {:?}", bad, res),
+            }
+        }
+        match col.validate_value(&"20151231230158.250Z") {
+            Err(ValueError::InvalidDateTime(_)) => (),
+            res => panic!("unexpected result {:?}", res),
+        }
+
+        let col = CsvxColumnType::new("seen", ColumnType::DateTimeTz(0));
+        let utc = col.validate_value(&"20151231230158Z").unwrap();
+        let cet = col.validate_value(&"20160101000158+0100").unwrap();
+        assert_eq!(
+            utc.clone().unwrap().to_datetime_tz(),
+            cet.clone().unwrap().to_datetime_tz()
+        );
+        assert_eq!(col.render_value(&utc).unwrap(), "20151231230158Z");
+        assert_eq!(col.render_value(&cet).unwrap(), "20160101000158+0100");
+        for bad in &["20151231230158", "20151231230158+2400", "20151231230158-0060"] {
+            match col.validate_value(bad) {
+                Err(ValueError::InvalidDateTimeTz(_)) => (),
+                res => panic!("unexpected result for {:?}: {:?}", bad, res),
+            }
+        }
+    }
+
+    #[test]
+    fn defaults_and_examples_are_validated() {
+        let schema = CsvxSchema::from_string(
+            "id,type,constraints,description,example,default\r\n\
+             id,INTEGER,UNIQUE,Internal zoo id,17,\r\n\
+             legs,INTEGER,MAX(8),Number of legs,2,4\r\n",
+            "schema.csv",
+        ).unwrap();
+        let cols: Vec<_> = schema.iter_columns().collect();
+        assert_eq!(cols[0].default, None);
+        assert_eq!(cols[0].example, Some("17".to_owned()));
+        assert_eq!(cols[1].default, Some("4".to_owned()));
+
+        let mut out = Vec::new();
+        schema.write_to(&mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "id,type,constraints,description,default,example\r\n\
+             id,INTEGER,UNIQUE,Internal zoo id,,17\r\n\
+             legs,INTEGER,MAX(8),Number of legs,4,2\r\n"
+        );
+
+        let err = CsvxSchema::from_string(
+            "id,type,constraints,description,default,example\r\n\
+             legs,INTEGER,MAX(8),Number of legs,4,many\r\n",
+            "schema.csv",
+        ).unwrap_err();
+        match (err.error(), err.location()) {
+            (SchemaLoadError::BadExample(id, _), Location::FileLineField(_, 2, 6)) => {
+                assert_eq!(id, "legs")
+            }
+            res => panic!("unexpected error {:?}", res),
+        }
+
+        for header in &[
+            "id,type,constraints,description,default,default",
+            "id,type,constraints,description,units",
+            "default,id,type,constraints,description",
+        ] {
+            let err = CsvxSchema::from_string(&format!("{}\r\n", header), "schema.csv")
+                .unwrap_err();
+            match *err.error() {
+                SchemaLoadError::BadHeader => (),
+                ref e => panic!("unexpected error for {:?}: {:?}", header, e),
+            }
+        }
+    }
+
+    #[test]
+    fn metadata_is_parsed_and_written() {
+        let src = "id,type,constraints,description,since,tags,unit,deprecated\r\n\
+                   weight,INTEGER,,Weight,20170401,,g,\r\n\
+                   keeper_email,STRING,NULLABLE,Email,,\"pii,contact\",,TRUE\r\n";
+        let schema = CsvxSchema::from_string(src, "schema.csv").unwrap();
+        let cols: Vec<_> = schema.iter_columns().collect();
+        assert_eq!(
+            cols[0].metadata,
+            ColumnMetadata {
+                unit: Some("g".to_owned()),
+                tags: Vec::new(),
+                deprecated: false,
+                since: NaiveDate::from_ymd_opt(2017, 4, 1),
+            }
+        );
+        assert_eq!(cols[1].metadata.tags, vec!["pii", "contact"]);
+        assert!(cols[1].metadata.deprecated);
+
+        let mut out = Vec::new();
+        schema.write_to(&mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "id,type,constraints,description,unit,tags,deprecated,since\r\n\
+             weight,INTEGER,,Weight,g,,,20170401\r\n\
+             keeper_email,STRING,NULLABLE,Email,,\"pii,contact\",TRUE,\r\n"
+        );
+
+        for &(row, field) in &[
+            ("email,STRING,,,,PII,,", 6),
+            ("email,STRING,,,,,,YES", 8),
+            ("email,STRING,,,20170231,,,", 5),
+        ] {
+            let err = CsvxSchema::from_string(
+                &format!("id,type,constraints,description,since,tags,unit,deprecated\r\n{}\r\n", row),
+                "schema.csv",
+            ).unwrap_err();
+            match (err.error(), err.location()) {
+                (SchemaLoadError::BadMetadata(_, _), &Location::FileLineField(_, 2, f)) => {
+                    assert_eq!(f, field)
+                }
+                res => panic!("unexpected error for {:?}: {:?}", row, res),
+            }
+        }
+    }
+
+    #[test]
+    fn only_canonical_uuids_are_valid() {
+        let col = CsvxColumnType::new("id", ColumnType::try_from("UUID").unwrap());
+        let id = col.validate_value(&"936da01f-9abd-4d9d-80c7-02af85c822a8").unwrap();
+        assert_eq!(
+            id.clone().unwrap().to_uuid().unwrap(),
+            Uuid::parse_str("936DA01F9ABD4D9D80C702AF85C822A8").unwrap()
+        );
+        assert_eq!(col.render_value(&id).unwrap(), "936da01f-9abd-4d9d-80c7-02af85c822a8");
+
+        for bad in &[
+            "936DA01F-9ABD-4D9D-80C7-02AF85C822A8",
+            "936da01f9abd4d9d80c702af85c822a8",
+            "{936da01f-9abd-4d9d-80c7-02af85c822a8}",
+            "urn:uuid:936da01f-9abd-4d9d-80c7-02af85c822a8",
+            "936da01f-9abd-4d9d-80c7-02af85c822a",
+            "Brian",
+        ] {
+            match col.validate_value(bad) {
+                Err(ValueError::InvalidUuid(_)) => (),
+                res => panic!("unexpected result for {:?}: {:?}", bad, res),
+            }
+        }
+    }
+
+    #[test]
+    fn compressed_files_are_decompressed() {
+        use flate2::write::GzEncoder;
+        use xz2::write::XzEncoder;
+
+        let schema = CsvxSchema::from_string(ANIMALS_SCHEMA, "schema.csv").unwrap();
+        let data = "id,name,caretaker\r\n1,Brian,Sam\r\nx,Pinky,\r\n";
+
+        let mut gz = GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gz.write_all(data.as_bytes()).unwrap();
+        let gz_path = env::temp_dir().join("zoo-gz_20170401_animals-2.csv.gzip");
+        fs::File::create(&gz_path)
+            .unwrap()
+            .write_all(&gz.finish().unwrap())
+            .unwrap();
+
+        let mut xz = XzEncoder::new(Vec::new(), 6);
+        xz.write_all(data.as_bytes()).unwrap();
+        let xz_path = env::temp_dir().join("zoo-xz_20170401_animals-2.csv.xz");
+        fs::File::create(&xz_path)
+            .unwrap()
+            .write_all(&xz.finish().unwrap())
+            .unwrap();
+
+        for path in &[gz_path, xz_path] {
+            let errs = schema.validate_file(path).unwrap_err();
+            fs::remove_file(path).unwrap();
+
+            assert_eq!(errs.len(), 1);
+            match *errs[0].error() {
+                ValidationError::ValueError(ValueError::InvalidInt(ref v)) => assert_eq!(v, "x"),
+                ref e => panic!("unexpected error {:?}", e),
+            }
+        }
+    }
+
+    #[test]
+    fn format_errors_do_not_hide_value_errors() {
+        let schema = CsvxSchema::from_string(ANIMALS_SCHEMA, "schema.csv").unwrap();
+        let errs = validate_data(
+            &schema,
+            "zoo-lf_20170401_animals-2.csv",
+            "id,name,caretaker\n1,Brian,Sam\n1,Pinky,\n",
+        ).unwrap_err();
+
+        // three bare line feeds, a missing final line break and the duplicate
+        assert_eq!(errs.len(), 5);
+        match *errs[4].error() {
+            ValidationError::DuplicateValue(_, _) => (),
+            ref e => panic!("unexpected error {:?}", e),
+        }
+    }
 }
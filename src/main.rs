@@ -1,16 +1,128 @@
 extern crate clap;
 extern crate csvx;
 extern crate safe_unwrap;
+extern crate serde_json;
 extern crate term_painter;
 
 
 use clap::{App, Arg, SubCommand};
 use safe_unwrap::SafeUnwrap;
-use std::{io, path, process};
+use std::{env, fmt, io, path, process};
+use std::collections::HashMap;
 use term_painter::{Attr, Color, ToStyle};
 
-use csvx::ColumnType;
-use csvx::err::{CheckError, ErrorLoc, ErrorAtLocation, HelpPrinter, Location};
+use csvx::{ColumnType, CsvxSchema, ErrorEntry, FileSummary, ReportEntry, Source};
+use csvx::err::{CheckError, ErrorLoc, ErrorAtLocation, Helpful, HelpPrinter, Location,
+                SchemaLoadError, ValidationError};
+
+/// Output format of `check`
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Format {
+    Human,
+    Json,
+    Ndjson,
+    Junit,
+    Sarif,
+}
+
+/// Results of `check`, either printed for humans or collected into a report.
+///
+/// NDJSON reports are printed one entry per line as soon as it is known, all
+/// other reports once complete.
+struct Output {
+    format: Format,
+    entries: Vec<ReportEntry>,
+}
+
+impl Output {
+    fn new(format: Format) -> Output {
+        Output {
+            format: format,
+            entries: Vec::new(),
+        }
+    }
+
+    fn push(&mut self, entry: ReportEntry) {
+        match self.format {
+            Format::Human => (),
+            Format::Ndjson => {
+                println!(
+                    "{}",
+                    serde_json::to_string(&entry).safe_unwrap("report entries serialize")
+                )
+            }
+            _ => self.entries.push(entry),
+        }
+    }
+
+    /// Report a file that passed (`schema` is `None`) or failed validation
+    fn checked(
+        &mut self,
+        file: &str,
+        schema: Option<&CsvxSchema>,
+        errs: Vec<ErrorAtLocation<ValidationError, Location>>,
+    ) {
+        if self.format == Format::Human {
+            if errs.is_empty() {
+                println!("{} {}", Color::Green.paint(Attr::Bold.paint("✓")), file);
+            } else {
+                println!("{} {}", Color::Red.paint(Attr::Bold.paint("✗")), file);
+                print_errors(errs, schema);
+            }
+            return;
+        }
+
+        let source = if errs.is_empty() {
+            None
+        } else {
+            Source::from_file(file).ok()
+        };
+        let summary = FileSummary::new(file, errs.len());
+        for e in &errs {
+            self.push(ReportEntry::Error(ErrorEntry::new(e, schema, source.as_ref())));
+        }
+        self.push(ReportEntry::Summary(summary));
+    }
+
+    fn schema_loaded(&mut self, file: &str) {
+        match self.format {
+            Format::Human => {
+                println!(
+                    "{} {}",
+                    Color::Green.paint(Attr::Bold.paint("✓")),
+                    Attr::Bold.paint(file)
+                )
+            }
+            // only input files are test cases or artifacts
+            Format::Junit | Format::Sarif => (),
+            Format::Json | Format::Ndjson => {
+                self.push(ReportEntry::Summary(FileSummary::new(file, 0)))
+            }
+        }
+    }
+
+    fn fatal<E: fmt::Display + Helpful>(&mut self, e: &ErrorAtLocation<E, Location>) {
+        match self.format {
+            Format::Human => print_error(e),
+            _ => self.push(ReportEntry::Error(ErrorEntry::fatal(e))),
+        }
+    }
+
+    fn finish(self) {
+        let json = match self.format {
+            Format::Human | Format::Ndjson => return,
+            Format::Junit => {
+                print!("{}", csvx::to_junit(&self.entries));
+                return;
+            }
+            Format::Json => {
+                serde_json::to_value(&self.entries).safe_unwrap("report entries serialize")
+            }
+            Format::Sarif => csvx::to_sarif(&self.entries),
+        };
+        println!("{}", serde_json::to_string_pretty(&json).safe_unwrap("JSON serializes"));
+    }
+}
 
 /// Check input files against schema.
 ///
@@ -19,6 +131,7 @@ use csvx::err::{CheckError, ErrorLoc, ErrorAtLocation, HelpPrinter, Location};
 fn cmd_check<P: AsRef<path::Path>, Q: AsRef<path::Path>>(
     schema_path: P,
     input_files: Vec<Q>,
+    out: &mut Output,
 ) -> Result<bool, ErrorAtLocation<CheckError, Location>> {
 
     // ensure schema_path evaluates to a real utf8 path
@@ -56,11 +169,7 @@ fn cmd_check<P: AsRef<path::Path>, Q: AsRef<path::Path>>(
     )?;
 
     // schema validated correctly, reward user with a checkmark
-    println!(
-        "{} {}",
-        Color::Green.paint(Attr::Bold.paint("✓")),
-        Attr::Bold.paint(schema_path_s)
-    );
+    out.schema_loaded(&schema_path_s);
 
     let mut all_good = true;
     for input_file in input_files {
@@ -94,20 +203,12 @@ fn cmd_check<P: AsRef<path::Path>, Q: AsRef<path::Path>>(
             );
         }
 
+        let input_s = input_file.as_ref().to_string_lossy();
         match schema.validate_file(&input_file) {
-            Ok(()) => println!("{} {}",
-                 Color::Green.paint(Attr::Bold.paint("✓")),
-                 input_file.as_ref().to_string_lossy()),
+            Ok(()) => out.checked(&input_s, None, Vec::new()),
             Err(errs) => {
                 all_good = false;
-                println!(
-                    "{} {}",
-                    Color::Red.paint(Attr::Bold.paint("✗")),
-                    input_file.as_ref().to_string_lossy()
-                );
-                for e in errs {
-                    e.print_help();
-                }
+                out.checked(&input_s, Some(&schema), errs);
             }
         }
     }
@@ -115,6 +216,37 @@ fn cmd_check<P: AsRef<path::Path>, Q: AsRef<path::Path>>(
     Ok(all_good)
 }
 
+/// Print an error, along with an excerpt of the file it is located in.
+fn print_error<E: fmt::Display + Helpful>(e: &ErrorAtLocation<E, Location>) {
+    let source = e.location().file().and_then(|file| Source::from_file(file).ok());
+    e.print_help_with(source.as_ref());
+}
+
+/// Print errors, along with excerpts of the files they are located in.
+///
+/// Every file is read only once. Fields are labeled with the column ids of
+/// `schema` if given, otherwise with the header of their file.
+fn print_errors<E: fmt::Display + Helpful>(
+    errs: Vec<ErrorAtLocation<E, Location>>,
+    schema: Option<&CsvxSchema>,
+) {
+    let mut sources = HashMap::new();
+    for e in errs {
+        let source = e.location().file().and_then(|file| {
+            sources
+                .entry(file.to_owned())
+                .or_insert_with(|| {
+                    Source::from_file(file).ok().map(|source| match schema {
+                        Some(schema) => source.with_schema(schema),
+                        None => source,
+                    })
+                })
+                .as_ref()
+        });
+        e.print_help_with(source);
+    }
+}
+
 fn underline(s: &str, c: char) -> String {
     s.chars().map(|_| c).collect()
 }
@@ -171,6 +303,24 @@ fn cmd_pretty<P: AsRef<path::Path>>(schema_path: P) {
         if cons.len() > 0 {
             print!("* `{}`\n", cons);
         }
+        if let Some(ref default) = col.default {
+            println!("* default: `{}`", default);
+        }
+        if let Some(ref example) = col.example {
+            println!("* example: `{}`", example);
+        }
+        if let Some(ref unit) = col.metadata.unit {
+            println!("* unit: {}", unit);
+        }
+        if !col.metadata.tags.is_empty() {
+            println!("* tags: {}", col.metadata.tags.join(", "));
+        }
+        if let Some(since) = col.metadata.since {
+            println!("* since: {}", since);
+        }
+        if col.metadata.deprecated {
+            println!("* **deprecated**");
+        }
         print!("\n{}\n\n\n", col.description);
     }
 }
@@ -206,19 +356,167 @@ fn cmd_gen<P: AsRef<path::Path>>(schema_path: P) {
             ColumnType::Bool => "bool",
             ColumnType::Integer => "i64",
             ColumnType::Enum(_) => "FIXME",
-            ColumnType::Decimal => "String",
+            ColumnType::Decimal(_) => "Decimal",
             ColumnType::Date => "NaiveDate",
-            ColumnType::DateTime => "NaiveDateTime",
+            ColumnType::DateTime(_) => "NaiveDateTime",
+            ColumnType::DateTimeTz(_) => "DateTime<FixedOffset>",
             ColumnType::Time => "NaiveTime",
+            ColumnType::Uuid => "Uuid",
         }.to_owned();
         if col.constraints.nullable {
             ty_s = format!("Option<{}>", ty_s);
         }
-        println!("    {}: {},", col.id, ty_s);
+
+        let mut notes = Vec::new();
+        if let ColumnType::Decimal(Some(_)) |
+            ColumnType::DateTime(1..=9) |
+            ColumnType::DateTimeTz(1..=9) = col.ty
+        {
+            notes.push(col.ty.to_string());
+        }
+        if let Some(ref default) = col.default {
+            notes.push(format!("default: {}", default));
+        }
+        if let Some(ref example) = col.example {
+            notes.push(format!("e.g. {}", example));
+        }
+
+        if notes.is_empty() {
+            println!("    {}: {},", col.id, ty_s);
+        } else {
+            println!("    {}: {}, // {}", col.id, ty_s, notes.join(", "));
+        }
     }
     println!("}}");
 }
 
+/// Parse the csvx metadata contained in a path's filename.
+fn table_meta<P: AsRef<path::Path>>(
+    path: P,
+) -> Result<csvx::CsvxMetadata, ErrorAtLocation<CheckError, Location>> {
+    let path_s = path.as_ref().to_string_lossy().to_string();
+    let filename = path.as_ref()
+        .file_name()
+        .ok_or_else(|| CheckError::SchemaNotAFile.at(Location::File(path_s.clone())))?
+        .to_string_lossy()
+        .to_string();
+
+    csvx::parse_filename(&filename).ok_or_else(|| {
+        CheckError::InvalidCsvxFilename(filename).at(Location::File(path_s))
+    })
+}
+
+/// Check references between input files.
+///
+/// Like `cmd_check()`, returns `Ok(false)` if any reference is broken.
+fn cmd_refs<P: AsRef<path::Path>, Q: AsRef<path::Path>>(
+    schema_paths: Vec<P>,
+    input_files: Vec<Q>,
+) -> Result<bool, ErrorAtLocation<CheckError, Location>> {
+    let mut schemas = HashMap::new();
+    for schema_path in schema_paths {
+        let meta = table_meta(&schema_path)?;
+        if !meta.is_schema() {
+            return Err(CheckError::NotASchema.at(Location::File(
+                schema_path.as_ref().to_string_lossy().to_string(),
+            )));
+        }
+
+        let schema = csvx::CsvxSchema::from_file(&schema_path).map_err(
+            |e| e.convert(),
+        )?;
+        schemas.insert(meta.table_name, schema);
+    }
+
+    let mut files = Vec::new();
+    for input_file in input_files {
+        let meta = table_meta(&input_file)?;
+        files.push((meta.schema, input_file));
+    }
+
+    match csvx::validate_references(&schemas, &files) {
+        Ok(()) => {
+            println!(
+                "{} {}",
+                Color::Green.paint(Attr::Bold.paint("✓")),
+                Attr::Bold.paint("all references resolved")
+            );
+            Ok(true)
+        }
+        Err(errs) => {
+            print_errors(errs, None);
+            Ok(false)
+        }
+    }
+}
+
+/// Mask columns tagged `pii` in an input file.
+///
+/// Like `cmd_check()`, returns `Ok(false)` if the input could not be masked
+/// or the masked output does not validate.
+fn cmd_mask<P: AsRef<path::Path>, Q: AsRef<path::Path>, R: AsRef<path::Path>>(
+    schema_path: P,
+    input_file: Q,
+    output_file: R,
+    key: Option<&str>,
+) -> Result<bool, ErrorAtLocation<CheckError, Location>> {
+    let key = match key {
+        Some(key) => key.to_owned(),
+        None => {
+            env::var("CSVX_MASK_KEY").map_err(|_| {
+                CheckError::MissingMaskKey.at(Location::Unspecified)
+            })?
+        }
+    };
+
+    let schema = csvx::CsvxSchema::from_file(&schema_path).map_err(
+        |e| e.convert(),
+    )?;
+
+    match schema.mask_file(&csvx::Masker::new(key), &input_file, &output_file) {
+        Ok(()) => {
+            println!(
+                "{} {}",
+                Color::Green.paint(Attr::Bold.paint("✓")),
+                output_file.as_ref().to_string_lossy()
+            );
+            Ok(true)
+        }
+        Err(errs) => {
+            println!(
+                "{} {}",
+                Color::Red.paint(Attr::Bold.paint("✗")),
+                output_file.as_ref().to_string_lossy()
+            );
+            print_errors(errs, Some(&schema));
+            Ok(false)
+        }
+    }
+}
+
+/// Compare two versions of a schema.
+///
+/// Prints every change; returns `Ok(false)` if any of them is breaking.
+fn cmd_schema_diff<P: AsRef<path::Path>, Q: AsRef<path::Path>>(
+    old_path: P,
+    new_path: Q,
+) -> Result<bool, ErrorAtLocation<SchemaLoadError, Location>> {
+    let old = csvx::CsvxSchema::from_file(old_path)?;
+    let new = csvx::CsvxSchema::from_file(new_path)?;
+
+    let mut compatible = true;
+    for change in old.diff(&new) {
+        if change.is_breaking() {
+            compatible = false;
+            println!("{} {}", Color::Red.paint(Attr::Bold.paint("breaking  ")), change);
+        } else {
+            println!("{} {}", Color::Green.paint(Attr::Bold.paint("compatible")), change);
+        }
+    }
+
+    Ok(compatible)
+}
+
 fn main() {
     let app = App::new("csvx")
         .version("5.4.0")
@@ -226,6 +524,15 @@ fn main() {
         .subcommand(
             SubCommand::with_name("check")
                 .about("Check csvx files for conformance")
+                .arg(
+                    Arg::with_name("format")
+                        .long("format")
+                        .short("f")
+                        .help("Output format, all but `human` are meant for machines")
+                        .possible_values(&["human", "json", "ndjson", "junit", "sarif"])
+                        .default_value("human")
+                        .takes_value(true),
+                )
                 .arg(
                     Arg::with_name("schema_path")
                         .help("Schema file to check against")
@@ -258,12 +565,89 @@ fn main() {
                         .required(true)
                         .takes_value(true),
                 ),
+        )
+        .subcommand(
+            SubCommand::with_name("refs")
+                .about("Check references between csvx files")
+                .arg(
+                    Arg::with_name("schema")
+                        .help("Schema used by input files or referenced by them")
+                        .short("s")
+                        .long("schema")
+                        .required(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("input_files")
+                        .help("Input files to check")
+                        .multiple(true)
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("mask")
+                .about("Mask columns tagged `pii`, e.g. before sharing a file")
+                .arg(
+                    Arg::with_name("key")
+                        .long("key")
+                        .short("k")
+                        .help("Secret key, defaults to $CSVX_MASK_KEY")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("schema_path")
+                        .help("Schema of the input file")
+                        .required(true)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("input_file")
+                        .help("File to mask")
+                        .required(true)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("output_file")
+                        .help("Path to write the masked file to")
+                        .required(true)
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("schema")
+                .about("Schema utilities")
+                .subcommand(
+                    SubCommand::with_name("diff")
+                        .about("List changes between two schema versions")
+                        .arg(
+                            Arg::with_name("old_schema")
+                                .help("Previous version of the schema")
+                                .required(true)
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("new_schema")
+                                .help("New version of the schema")
+                                .required(true)
+                                .takes_value(true),
+                        ),
+                ),
         );
 
     let m = app.clone().get_matches();
 
     match m.subcommand {
         Some(ref cmd) if cmd.name == "check" => {
+            let format = match cmd.matches.value_of("format") {
+                Some("json") => Format::Json,
+                Some("ndjson") => Format::Ndjson,
+                Some("junit") => Format::Junit,
+                Some("sarif") => Format::Sarif,
+                _ => Format::Human,
+            };
+            let mut out = Output::new(format);
             let res = cmd_check(
                 cmd.matches.value_of("schema_path").safe_unwrap(
                     "required argument",
@@ -272,18 +656,21 @@ fn main() {
                     .values_of("input_files")
                     .map(|v| v.collect())
                     .unwrap_or_else(|| Vec::new()),
+                &mut out,
             );
 
             match res {
                 Err(e) => {
                     // display fatal error:
-                    e.print_help();
+                    out.fatal(&e);
+                    out.finish();
                     process::exit(1);
                 }
                 Ok(result) => {
                     // the errors have already been displayed by `cmd_check()`
                     // we use exit status `2` for validation but non-fatal
                     // errors
+                    out.finish();
                     process::exit(if result { 0 } else { 2 });
                 }
             }
@@ -298,6 +685,74 @@ fn main() {
                 "required argument",
             ));
         }
+        Some(ref cmd) if cmd.name == "refs" => {
+            let res = cmd_refs(
+                cmd.matches
+                    .values_of("schema")
+                    .safe_unwrap("required argument")
+                    .collect(),
+                cmd.matches
+                    .values_of("input_files")
+                    .map(|v| v.collect())
+                    .unwrap_or_else(|| Vec::new()),
+            );
+
+            match res {
+                Err(e) => {
+                    print_error(&e);
+                    process::exit(1);
+                }
+                Ok(result) => process::exit(if result { 0 } else { 2 }),
+            }
+        }
+        Some(ref cmd) if cmd.name == "mask" => {
+            let res = cmd_mask(
+                cmd.matches.value_of("schema_path").safe_unwrap(
+                    "required argument",
+                ),
+                cmd.matches.value_of("input_file").safe_unwrap(
+                    "required argument",
+                ),
+                cmd.matches.value_of("output_file").safe_unwrap(
+                    "required argument",
+                ),
+                cmd.matches.value_of("key"),
+            );
+
+            match res {
+                Err(e) => {
+                    print_error(&e);
+                    process::exit(1);
+                }
+                Ok(result) => process::exit(if result { 0 } else { 2 }),
+            }
+        }
+        Some(ref cmd) if cmd.name == "schema" => {
+            match cmd.matches.subcommand {
+                Some(ref sub) if sub.name == "diff" => {
+                    let res = cmd_schema_diff(
+                        sub.matches.value_of("old_schema").safe_unwrap(
+                            "required argument",
+                        ),
+                        sub.matches.value_of("new_schema").safe_unwrap(
+                            "required argument",
+                        ),
+                    );
+
+                    match res {
+                        Err(e) => {
+                            print_error(&e);
+                            process::exit(1);
+                        }
+                        // breaking changes are reported like validation errors
+                        Ok(compatible) => process::exit(if compatible { 0 } else { 2 }),
+                    }
+                }
+                _ => {
+                    println!("{}", cmd.matches.usage());
+                }
+            }
+        }
         _ => {
             app.write_help(&mut io::stdout()).unwrap();
             println!();
@@ -0,0 +1,501 @@
+//! Anonymization of columns holding personally identifiable information
+
+use chrono::{Duration, NaiveTime};
+use err::{ErrorAtLocation, ErrorLoc, Location, ResultLoc, ValidationError, ValueError};
+use hmac_sha256::HMAC;
+use num_bigint::BigInt;
+use num_traits::{pow, ToPrimitive, Zero};
+use safe_unwrap::SafeUnwrap;
+use std::{cmp, fs, io, path};
+use super::{ColumnType, Compression, CsvxColumnType, CsvxReader, CsvxSchema, CsvxWriter,
+            Decimal, Value};
+use uuid::{Builder, Variant, Version};
+
+/// Tag marking columns that need to be masked
+pub const PII_TAG: &str = "pii";
+
+/// Largest mantissa of masked decimals
+const MAX_DECIMAL: i128 = 999_999_999_999_999_999;
+
+/// Replaces values with stand-ins of the same type
+///
+/// Replacements are derived from a keyed hash of the value and the column's
+/// base type, then fitted into the column's `MIN`, `MAX`, `MAXLEN` and
+/// precision: equal values masked with the same key are replaced by equal
+/// values in columns of the same type and limits, regardless of file. This
+/// keeps `UNIQUE` columns unique (barring collisions, which are more likely
+/// the tighter the limits) and `REFERENCES` resolvable, as long as
+/// referencing columns are tagged as well. Dates and times with a date are
+/// shifted by the same, key-dependent number of days instead, preserving
+/// their order.
+pub struct Masker {
+    key: Vec<u8>,
+}
+
+impl Masker {
+    pub fn new<K: AsRef<[u8]>>(key: K) -> Masker {
+        Masker { key: key.as_ref().to_vec() }
+    }
+
+    /// Mask a single value, regardless of the column's tags
+    ///
+    /// Replacements that would exceed the column's limits are drawn from
+    /// within them instead. Fails with `ValueError::Unmaskable` if the
+    /// replacement still is not a valid value, e.g. if it does not match a
+    /// `PATTERN`.
+    pub fn mask_value(
+        &self,
+        col: &CsvxColumnType,
+        value: &Option<Value>,
+    ) -> Result<Option<Value>, ValueError> {
+        let value = match *value {
+            Some(ref value) => value,
+            None => return Ok(None),
+        };
+        // the key does not depend on precision or scale, unlike the rendering
+        let h = HMAC::mac(col.value_key(value)?, &self.key);
+
+        let masked = match (&col.ty, value) {
+            (ColumnType::String, _) => {
+                let mut s: String = h[..8].iter().map(|b| format!("{:02x}", b)).collect();
+                // a prefix of the hash is still a hash
+                s.truncate(col.constraints.max_len.unwrap_or(s.len()));
+                Value::String(s)
+            }
+            (ColumnType::Bool, _) => Value::Bool(h[0] & 1 == 1),
+            // the sign is kept, columns of negative numbers stay valid
+            (ColumnType::Integer, Value::Integer(i)) => {
+                let (lo, hi) = if *i < 0 {
+                    (i64::MIN as i128, -1)
+                } else {
+                    (0, i64::MAX as i128)
+                };
+                let (min, max) = limits(col, 0, lo, hi);
+                Value::Integer(fit(&h, pick(&h, lo, hi), min, max) as i64)
+            }
+            (ColumnType::Enum(variants), _) => {
+                Value::Enum(pick(&h, 0, variants.len() as i128 - 1) as usize)
+            }
+            (ColumnType::Decimal(precision), Value::Decimal(d)) => {
+                // up to 18 digits, with as many fractional digits as the
+                // column requires or the value has
+                let (scale, hi) = match *precision {
+                    Some((p, s)) if p < 18 => (s, 10i128.pow(p) - 1),
+                    Some((_, s)) => (s, MAX_DECIMAL),
+                    None => (d.normalize().scale(), MAX_DECIMAL),
+                };
+                let (min, max) = limits(col, scale, 0, hi);
+                let mantissa = fit(&h, pick(&h, 0, MAX_DECIMAL), min, max);
+                Value::Decimal(Decimal::new(BigInt::from(mantissa), scale))
+            }
+            (ColumnType::Date, Value::Date(d)) => {
+                Value::Date(d.checked_add_signed(self.shift()).ok_or_else(|| {
+                    ValueError::InvalidDate(d.to_string())
+                })?)
+            }
+            (ColumnType::DateTime(_), Value::DateTime(dt)) => {
+                Value::DateTime(dt.checked_add_signed(self.shift()).ok_or_else(|| {
+                    ValueError::InvalidDateTime(dt.to_string())
+                })?)
+            }
+            (ColumnType::DateTimeTz(_), Value::DateTimeTz(dt)) => {
+                Value::DateTimeTz(dt.checked_add_signed(self.shift()).ok_or_else(|| {
+                    ValueError::InvalidDateTimeTz(dt.to_string())
+                })?)
+            }
+            (ColumnType::Time, _) => {
+                let secs = pick(&h, 0, 86_399) as u32;
+                Value::Time(
+                    NaiveTime::from_num_seconds_from_midnight_opt(secs, 0)
+                        .safe_unwrap("seconds are within a day"),
+                )
+            }
+            (ColumnType::Uuid, _) => {
+                let mut bytes = [0; 16];
+                bytes.copy_from_slice(&h[..16]);
+                Value::Uuid(
+                    Builder::from_bytes(bytes)
+                        .set_variant(Variant::RFC4122)
+                        .set_version(Version::Random)
+                        .build(),
+                )
+            }
+            _ => return Err(ValueError::WrongType(col.ty.to_string())),
+        };
+
+        let masked = Some(masked);
+        col.render_value(&masked).map_err(|e| ValueError::Unmaskable(Box::new(e)))?;
+        Ok(masked)
+    }
+
+    /// Mask all values of columns tagged `pii`
+    ///
+    /// Errors are located at the 1-based index of the offending field.
+    pub fn mask_row(
+        &self,
+        schema: &CsvxSchema,
+        row: &mut [Option<Value>],
+    ) -> Result<(), ErrorAtLocation<ValueError, usize>> {
+        for (idx, (col, value)) in schema.iter_columns().zip(row.iter_mut()).enumerate() {
+            if col.metadata.tags.iter().any(|t| t == PII_TAG) {
+                *value = self.mask_value(col, value).err_at(|| idx + 1)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Number of days dates are shifted by, between one and 365 either way
+    fn shift(&self) -> Duration {
+        let h = HMAC::mac("date shift", &self.key);
+        let days = pick(&h, 1, 365) as i64;
+        Duration::days(if h[16] & 1 == 1 { -days } else { days })
+    }
+}
+
+impl CsvxSchema {
+    /// Mask all `pii` columns of `input`, writing the result to `output`
+    ///
+    /// Rows that cannot be read or masked are reported and left out. The
+    /// output is compressed according to its extension, like inputs are
+    /// decompressed, and validated once written; any errors are located in
+    /// `output`. `output` must not be the same file as `input`.
+    pub fn mask_file<P: AsRef<path::Path>, Q: AsRef<path::Path>>(
+        &self,
+        masker: &Masker,
+        input: P,
+        output: Q,
+    ) -> Result<(), Vec<ErrorAtLocation<ValidationError, Location>>> {
+        let output_s = output.as_ref().to_string_lossy().to_string();
+        let io_error = |e| vec![ValidationError::Io(e).at(Location::File(output_s.clone()))];
+
+        let mut reader = CsvxReader::from_file(self, &input)?;
+
+        // creating the output would truncate the input otherwise
+        let same_file = match (canonicalize(input.as_ref()), canonicalize(output.as_ref())) {
+            (Ok(input), Ok(output)) => input == output,
+            _ => false,
+        };
+        if same_file {
+            return Err(io_error(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "output is the same file as the input",
+            )));
+        }
+
+        let out = fs::File::create(&output).map_err(&io_error)?;
+        let out = Compression::encode(Compression::from_path(&output), out);
+        let mut writer = CsvxWriter::new(self, out).map_err(|e| {
+            vec![e.at(Location::File(output_s.clone()))]
+        })?;
+
+        let mut errs = Vec::new();
+        while let Some(row) = reader.next() {
+            let mut values = match row {
+                Ok(values) => values,
+                Err(e) => {
+                    errs.push(e);
+                    continue;
+                }
+            };
+            if let Err(e) = masker.mask_row(self, &mut values) {
+                errs.push(e.convert().map_location(|idx| reader.location(idx)));
+                continue;
+            }
+            if let Err(e) = writer.write_row(&values) {
+                errs.push(e.map_location(|idx| reader.location(idx)));
+            }
+        }
+        drop(writer);
+
+        if !errs.is_empty() {
+            return Err(errs);
+        }
+        self.validate_file(output)
+    }
+}
+
+/// Absolute path of a file, which need not exist as long as its directory
+/// does
+fn canonicalize(path: &path::Path) -> io::Result<path::PathBuf> {
+    if let Ok(path) = fs::canonicalize(path) {
+        return Ok(path);
+    }
+
+    let dir = match path.parent() {
+        Some(dir) if dir != path::Path::new("") => fs::canonicalize(dir)?,
+        _ => fs::canonicalize(".")?,
+    };
+    match path.file_name() {
+        Some(name) => Ok(dir.join(name)),
+        None => Err(io::Error::new(io::ErrorKind::InvalidInput, "not a file")),
+    }
+}
+
+/// Mantissas at `scale` within the column's `MIN` and `MAX`, as well as
+/// within `lo..=hi`
+fn limits(col: &CsvxColumnType, scale: u32, lo: i128, hi: i128) -> (i128, i128) {
+    let (lo, hi) = (BigInt::from(lo), BigInt::from(hi));
+    let clamp = |m: BigInt| cmp::min(cmp::max(m, lo.clone()), hi.clone());
+
+    let min = match col.constraints.min {
+        Some(ref min) => clamp(-floor_mantissa(&-min.clone(), scale)),
+        None => lo.clone(),
+    };
+    let max = match col.constraints.max {
+        Some(ref max) => clamp(floor_mantissa(max, scale)),
+        None => hi.clone(),
+    };
+    (
+        min.to_i128().safe_unwrap("clamped to i128"),
+        max.to_i128().safe_unwrap("clamped to i128"),
+    )
+}
+
+/// Largest mantissa at `scale` not greater than `d`
+fn floor_mantissa(d: &Decimal, scale: u32) -> BigInt {
+    if d.scale() <= scale {
+        return d.mantissa() * pow(BigInt::from(10), (scale - d.scale()) as usize);
+    }
+
+    let divisor = pow(BigInt::from(10), (d.scale() - scale) as usize);
+    let quotient = d.mantissa() / &divisor;
+    // division rounds towards zero
+    if d.is_negative() && !(d.mantissa() % &divisor).is_zero() {
+        quotient - 1
+    } else {
+        quotient
+    }
+}
+
+/// Keep `candidate` if within `lo..=hi`, otherwise pick another number from it
+fn fit(h: &[u8; 32], candidate: i128, lo: i128, hi: i128) -> i128 {
+    if lo <= candidate && candidate <= hi {
+        candidate
+    } else {
+        pick(h, lo, hi)
+    }
+}
+
+/// Pick a number from `lo..=hi` based on a hash
+fn pick(h: &[u8; 32], lo: i128, hi: i128) -> i128 {
+    if hi <= lo {
+        return lo;
+    }
+
+    let mut bytes = [0; 16];
+    bytes.copy_from_slice(&h[..16]);
+    lo + (u128::from_be_bytes(bytes) % ((hi - lo) as u128 + 1)) as i128
+}
+
+#[cfg(test)]
+mod test {
+    use std::{env, fs};
+    use std::io::Write;
+    use std::collections::HashMap;
+    use super::*;
+    use err::SchemaLoadError;
+    use {validate_references, Pattern};
+
+    const STAFF_SCHEMA: &str = "id,type,constraints,description,tags\r\n\
+                                id,INTEGER,UNIQUE,Staff id,\r\n\
+                                name,STRING,MAXLEN(40),Name,pii\r\n\
+                                email,STRING,\"UNIQUE,NULLABLE\",Email,pii\r\n\
+                                born,DATE,,Date of birth,pii\r\n\
+                                role,\"ENUM(KEEPER,VET,GUIDE)\",,Role,pii\r\n\
+                                badge,UUID,,Badge,pii\r\n";
+
+    #[test]
+    fn masked_values_are_consistent_and_valid() {
+        let schema = CsvxSchema::from_string(STAFF_SCHEMA, "schema.csv").unwrap();
+        let masker = Masker::new("secret");
+        let cols: Vec<_> = schema.iter_columns().collect();
+
+        let values = [
+            "17",
+            "Brian Brown",
+            "brian@zoo.org",
+            "19700101",
+            "VET",
+            "936da01f-9abd-4d9d-80c7-02af85c822a8",
+        ];
+        for (col, value) in cols.iter().zip(&values) {
+            let orig = col.validate_value(value).unwrap();
+            let masked = masker.mask_value(col, &orig).unwrap();
+            let rendered = col.render_value(&masked).unwrap();
+            assert!(col.validate_value(&rendered).is_ok());
+
+            // enums may well end up with the same variant
+            if !matches!(col.ty, ColumnType::Enum(_)) {
+                assert_ne!(&rendered, value);
+            }
+
+            // the same key gives the same result, another key a different one
+            let again = masker.mask_value(col, &orig).unwrap();
+            assert_eq!(col.render_value(&again).unwrap(), rendered);
+            let other = Masker::new("other").mask_value(col, &orig).unwrap();
+            if col.ty == ColumnType::String {
+                assert_ne!(col.render_value(&other).unwrap(), rendered);
+            }
+        }
+
+        // nulls stay null
+        assert!(masker.mask_value(cols[2], &None).unwrap().is_none());
+
+        // patterns are not respected, violating them is an error
+        let mut col = CsvxColumnType::new("code", ColumnType::String);
+        col.constraints.pattern = Some(Pattern::new("[A-Z]+").unwrap());
+        let value = col.validate_value(&"ABC").unwrap();
+        match masker.mask_value(&col, &value) {
+            Err(ValueError::Unmaskable(ref e)) => match **e {
+                ValueError::PatternMismatch(_, _) => (),
+                ref e => panic!("unexpected error {:?}", e),
+            },
+            res => panic!("unexpected result {:?}", res),
+        }
+
+        // strings are shortened to fit
+        col.constraints.pattern = None;
+        col.constraints.max_len = Some(3);
+        let masked = masker.mask_value(&col, &value).unwrap();
+        assert_eq!(col.render_value(&masked).unwrap().len(), 3);
+
+        // negative integers stay negative
+        let mut col = CsvxColumnType::new("depth", ColumnType::Integer);
+        col.constraints.max = Some("-1".parse().unwrap());
+        for value in &["-1", "-20", "-300"] {
+            let masked = masker.mask_value(&col, &col.validate_value(value).unwrap()).unwrap();
+            assert!(masked.unwrap().to_i64().unwrap() < 0);
+        }
+
+        // replacements are drawn from within limits
+        let mut col = CsvxColumnType::new("legs", ColumnType::Integer);
+        col.constraints.min = Some("1.5".parse().unwrap());
+        col.constraints.max = Some("8".parse().unwrap());
+        for value in &["2", "4", "8"] {
+            let masked = masker.mask_value(&col, &col.validate_value(value).unwrap()).unwrap();
+            assert!((2..=8).contains(&masked.unwrap().to_i64().unwrap()));
+        }
+
+        let mut col = CsvxColumnType::new("fee", ColumnType::Decimal(Some((4, 2))));
+        col.constraints.min = Some("0.125".parse().unwrap());
+        for value in &["0.13", "12.50", "99.99"] {
+            let masked = masker.mask_value(&col, &col.validate_value(value).unwrap()).unwrap();
+            let rendered = col.render_value(&masked).unwrap();
+            assert!(col.validate_value(&rendered).is_ok());
+            assert_ne!(&rendered, value);
+        }
+
+        // loose limits do not affect replacements
+        let mut limited = CsvxColumnType::new("caretaker", ColumnType::Integer);
+        limited.constraints.min = Some("1".parse().unwrap());
+        let value = limited.validate_value(&"17").unwrap();
+        assert_eq!(
+            limited.render_value(&masker.mask_value(&limited, &value).unwrap()).unwrap(),
+            cols[0].render_value(&masker.mask_value(cols[0], &value).unwrap()).unwrap()
+        );
+    }
+
+    #[test]
+    fn masked_files_validate() {
+        let schema = CsvxSchema::from_string(STAFF_SCHEMA, "schema.csv").unwrap();
+        let input = env::temp_dir().join("mask_20170401_staff-1.csv");
+        let output = env::temp_dir().join("mask-out_20170401_staff-1.csv");
+        fs::File::create(&input)
+            .unwrap()
+            .write_all(
+                b"id,name,email,born,role,badge\r\n\
+                  1,Sam,sam@zoo.org,19800229,KEEPER,936da01f-9abd-4d9d-80c7-02af85c822a8\r\n\
+                  2,Alex,,19910612,VET,2f2d1f5e-4bbe-4a3f-9d3c-0f7ab1b9a8e1\r\n",
+            )
+            .unwrap();
+
+        schema.mask_file(&Masker::new("secret"), &input, &output).unwrap();
+        let masked = fs::read_to_string(&output).unwrap();
+        assert!(masked.starts_with("id,name,email,born,role,badge\r\n1,"));
+        assert!(!masked.contains("Sam") && !masked.contains("19800229"));
+        assert!(masked.contains("\r\n2,") && masked.contains(",,"));
+
+        // compressed like inputs are decompressed
+        let compressed = env::temp_dir().join("mask-out_20170401_staff-1.csv.xz");
+        schema.mask_file(&Masker::new("secret"), &input, &compressed).unwrap();
+        assert_eq!(&fs::read(&compressed).unwrap()[..6], b"\xfd7zXZ\x00");
+
+        // the input is left untouched
+        let same = input.parent().unwrap().join(".").join(input.file_name().unwrap());
+        let errs = schema.mask_file(&Masker::new("secret"), &input, &same).unwrap_err();
+        match *errs[0].error() {
+            ValidationError::Io(ref e) => assert_eq!(e.kind(), io::ErrorKind::InvalidInput),
+            ref e => panic!("unexpected error {:?}", e),
+        }
+        assert!(fs::read_to_string(&input).unwrap().contains("Sam"));
+
+        for path in &[input, output, compressed] {
+            fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn pii_patterns_are_rejected() {
+        let err = CsvxSchema::from_string(
+            "id,type,constraints,description,tags\r\n\
+             id,INTEGER,UNIQUE,Staff id,\r\n\
+             email,STRING,PATTERN('.+@.+'),Email,pii\r\n",
+            "schema.csv",
+        ).unwrap_err();
+        match *err.error() {
+            SchemaLoadError::UnmaskableColumn(ref id) => assert_eq!(id, "email"),
+            ref e => panic!("unexpected error {:?}", e),
+        }
+        match *err.location() {
+            Location::FileLineField(_, 3, 5) => (),
+            ref loc => panic!("unexpected location {:?}", loc),
+        }
+    }
+
+    #[test]
+    fn masked_references_resolve() {
+        let mut schemas = HashMap::new();
+        schemas.insert(
+            "staff-1".to_owned(),
+            CsvxSchema::from_string(
+                "id,type,constraints,description,tags\r\n\
+                 id,INTEGER,UNIQUE,Staff id,pii\r\n\
+                 name,STRING,,Name,pii\r\n",
+                "staff.csv",
+            ).unwrap(),
+        );
+        schemas.insert(
+            "animals-3".to_owned(),
+            CsvxSchema::from_string(
+                "id,type,constraints,description,tags\r\n\
+                 id,INTEGER,UNIQUE,Internal zoo id,\r\n\
+                 caretaker,INTEGER,\"NULLABLE,MIN(1),REFERENCES(staff-1.id)\",Caretaker,pii\r\n",
+                "animals.csv",
+            ).unwrap(),
+        );
+
+        let dir = env::temp_dir();
+        let files = [
+            ("staff-1", "id,name\r\n1,Sam\r\n2,Alex\r\n"),
+            ("animals-3", "id,caretaker\r\n1,2\r\n2,\r\n3,1\r\n"),
+        ];
+        let mut masked = Vec::new();
+        for &(schema_name, data) in &files {
+            let input = dir.join(format!("mask-refs_20170401_{}.csv", schema_name));
+            let output = dir.join(format!("mask-refs-out_20170401_{}.csv", schema_name));
+            fs::File::create(&input).unwrap().write_all(data.as_bytes()).unwrap();
+
+            schemas[schema_name].mask_file(&Masker::new("secret"), &input, &output).unwrap();
+            fs::remove_file(&input).unwrap();
+            masked.push((schema_name, output));
+        }
+
+        assert!(validate_references(&schemas, &masked).is_ok());
+        let animals = fs::read_to_string(&masked[1].1).unwrap();
+        assert!(!animals.contains("\r\n1,2\r\n"));
+
+        for (_, output) in &masked {
+            fs::remove_file(output).unwrap();
+        }
+    }
+}
@@ -0,0 +1,445 @@
+use csv;
+use err::{ErrorAtLocation, ErrorLoc, Location, ValidationError};
+use serde::de::DeserializeOwned;
+use std::{io, path};
+use std::cell::RefCell;
+use std::marker::PhantomData;
+use std::rc::Rc;
+use super::{open_file, CsvxSchema, Value};
+
+/// Streaming reader for csvx data
+///
+/// Checks the header against a schema on creation, afterwards yields the
+/// typed values of one row at a time.
+pub struct CsvxReader<'a, R> {
+    schema: &'a CsvxSchema,
+    records: Records<R>,
+    filename: String,
+    record: Record,
+    done: bool,
+}
+
+impl<'a> CsvxReader<'a, Box<io::Read>> {
+    /// Open a (possibly compressed) file for reading
+    pub fn from_file<P: AsRef<path::Path>>(
+        schema: &'a CsvxSchema,
+        filename: P,
+    ) -> Result<Self, Vec<ErrorAtLocation<ValidationError, Location>>> {
+        let filename_s = filename.as_ref().to_string_lossy().to_string();
+        let file = open_file(filename).map_err(|e| {
+            vec![ValidationError::Io(e).at(Location::File(filename_s.clone()))]
+        })?;
+
+        CsvxReader::new(schema, file, &filename_s)
+    }
+}
+
+impl<'a, R: io::Read> CsvxReader<'a, R> {
+    /// Create a new reader
+    ///
+    /// `filename` is only used to construct error locations and may be any
+    /// descriptive name for the source.
+    pub fn new(
+        schema: &'a CsvxSchema,
+        rdr: R,
+        filename: &str,
+    ) -> Result<CsvxReader<'a, R>, Vec<ErrorAtLocation<ValidationError, Location>>> {
+        let mut records = Records::new(rdr).flexible(true);
+
+        let record = match records.next_record() {
+            Some(Ok(record)) => record,
+            Some(Err(e)) => return Err(vec![e.convert().map_location(|p| p.in_file(filename))]),
+            None => Record::default(),
+        };
+
+        let errs = schema.check_headers(&record.fields, filename);
+        if !errs.is_empty() {
+            return Err(errs);
+        }
+
+        Ok(CsvxReader {
+            schema: schema,
+            records: records,
+            filename: filename.to_owned(),
+            record: record,
+            done: false,
+        })
+    }
+
+    /// Line number the row returned last starts on
+    pub fn lineno(&self) -> usize {
+        self.record.line()
+    }
+
+    /// Location of a field of the row returned last
+    ///
+    /// `col_idx` is the 1-based index of the field, or `0` for the row as a
+    /// whole.
+    pub fn location(&self, col_idx: usize) -> Location {
+        self.record.location(&self.filename, col_idx)
+    }
+
+    /// Turn the reader into an iterator over rows deserialized into `T`
+    pub fn deserialize<T: DeserializeOwned>(self) -> DeserializeRows<'a, R, T> {
+        DeserializeRows {
+            reader: self,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<'a, R: io::Read> Iterator for CsvxReader<'a, R> {
+    type Item = Result<Vec<Option<Value>>, ErrorAtLocation<ValidationError, Location>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        match self.records.next_record() {
+            None => {
+                self.done = true;
+                None
+            }
+            Some(Err(e)) => {
+                // the csv data is broken, there is no point in continuing
+                self.done = true;
+                Some(Err(e.convert().map_location(|p| p.in_file(&self.filename))))
+            }
+            Some(Ok(record)) => {
+                self.record = record;
+                let parsed = self.schema.parse_row(&self.record.fields);
+                Some(parsed.map_err(|e| e.map_location(|col_idx| self.location(col_idx))))
+            }
+        }
+    }
+}
+
+/// Iterator over deserialized rows, see `CsvxReader::deserialize`
+pub struct DeserializeRows<'a, R, T> {
+    reader: CsvxReader<'a, R>,
+    _marker: PhantomData<T>,
+}
+
+impl<'a, R: io::Read, T: DeserializeOwned> Iterator for DeserializeRows<'a, R, T> {
+    type Item = Result<T, ErrorAtLocation<ValidationError, Location>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let values = match self.reader.next()? {
+            Ok(values) => values,
+            Err(e) => return Some(Err(e)),
+        };
+
+        let reader = &self.reader;
+        Some(reader.schema.deserialize_values(values).map_err(|e| {
+            e.map_location(|col_idx| reader.location(col_idx))
+        }))
+    }
+}
+
+/// Line and column in a file, both 1-based
+///
+/// Columns count characters, not bytes.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Position {
+    pub fn in_file(&self, filename: &str) -> Location {
+        Location::FileLineColumn(filename.to_owned(), self.line, self.column)
+    }
+}
+
+/// A raw csv record, along with the position each of its fields starts at
+#[derive(Clone, Debug, Default)]
+pub struct Record {
+    pub fields: Vec<String>,
+    pub positions: Vec<Position>,
+}
+
+impl Record {
+    /// Line the record starts on
+    pub fn line(&self) -> usize {
+        self.positions.first().map_or(1, |p| p.line)
+    }
+
+    /// Locate an error of the record, given the 1-based index of the field
+    /// or `0` for the record as a whole
+    pub fn location(&self, filename: &str, col_idx: usize) -> Location {
+        match col_idx.checked_sub(1).and_then(|idx| self.positions.get(idx)) {
+            Some(pos) => Location::FileLineField(filename.to_owned(), pos.line, col_idx),
+            None => Location::FileLine(filename.to_owned(), self.line()),
+        }
+    }
+}
+
+/// Bytes read so far, from the start of a line onwards
+#[derive(Debug, Default)]
+struct Seen {
+    /// Offset of `bytes[0]`
+    start: u64,
+    /// Number of lines before `start`
+    lines: usize,
+    bytes: Vec<u8>,
+}
+
+impl Seen {
+    /// Drop all bytes before the line containing `offset`
+    fn forget_before(&mut self, offset: u64) {
+        let end = (offset - self.start) as usize;
+        if let Some(nl) = self.bytes[..end].iter().rposition(|&b| b == b'\n') {
+            self.lines += self.bytes[..nl].iter().filter(|&&b| b == b'\n').count() + 1;
+            self.bytes.drain(..nl + 1);
+            self.start += nl as u64 + 1;
+        }
+    }
+
+    /// Skip line breaks, like the csv reader does before a record
+    fn skip_line_breaks(&self, mut offset: u64) -> u64 {
+        while let Some(&b'\r') | Some(&b'\n') = self.bytes.get((offset - self.start) as usize) {
+            offset += 1;
+        }
+        offset
+    }
+
+    /// Positions of the given offsets, which must be in ascending order
+    fn positions(&self, offsets: &[u64]) -> Vec<Position> {
+        let mut pos = Position {
+            line: self.lines + 1,
+            column: 1,
+        };
+        let mut cur = 0;
+
+        offsets
+            .iter()
+            .map(|&offset| {
+                let end = (offset - self.start) as usize;
+                for &b in &self.bytes[cur..end] {
+                    if b == b'\n' {
+                        pos.line += 1;
+                        pos.column = 1;
+                    } else if b & 0xC0 != 0x80 {
+                        // UTF-8 continuation bytes do not start a character
+                        pos.column += 1;
+                    }
+                }
+                cur = end;
+                pos
+            })
+            .collect()
+    }
+}
+
+/// Passes on bytes read, keeping a copy in `seen`
+struct Tracked<R> {
+    inner: R,
+    seen: Rc<RefCell<Seen>>,
+}
+
+impl<R: io::Read> io::Read for Tracked<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.seen.borrow_mut().bytes.extend_from_slice(&buf[..n]);
+        Ok(n)
+    }
+}
+
+/// Reads raw csv records, keeping track of where they are located
+///
+/// Fields containing line breaks make the line numbers of records differ
+/// from their record numbers, so positions are computed from the bytes read
+/// instead. Only the bytes of the current record are kept around.
+pub struct Records<R> {
+    rdr: csv::Reader<Tracked<R>>,
+    seen: Rc<RefCell<Seen>>,
+}
+
+impl<R: io::Read> Records<R> {
+    pub fn new(rdr: R) -> Records<R> {
+        let seen = Rc::new(RefCell::new(Seen::default()));
+        let tracked = Tracked {
+            inner: rdr,
+            seen: seen.clone(),
+        };
+
+        Records {
+            rdr: csv::Reader::from_reader(tracked).has_headers(false),
+            seen: seen,
+        }
+    }
+
+    /// Allow records to have differing numbers of fields
+    pub fn flexible(mut self, yes: bool) -> Records<R> {
+        self.rdr = self.rdr.flexible(yes);
+        self
+    }
+
+    /// Read the next record
+    ///
+    /// Errors are located at the start of the field that could not be read.
+    pub fn next_record(&mut self) -> Option<Result<Record, ErrorAtLocation<csv::Error, Position>>> {
+        self.seen.borrow_mut().forget_before(self.rdr.byte_offset());
+
+        let mut fields = Vec::new();
+        let mut offsets = Vec::new();
+        loop {
+            let offset = self.rdr.byte_offset();
+            match self.rdr.next_str() {
+                csv::NextField::Data(field) => {
+                    fields.push(field.to_owned());
+                    offsets.push(offset);
+                }
+                csv::NextField::EndOfRecord => break,
+                csv::NextField::EndOfCsv => return None,
+                csv::NextField::Error(e) => {
+                    let seen = self.seen.borrow();
+                    let offset = if offsets.is_empty() {
+                        seen.skip_line_breaks(offset)
+                    } else {
+                        offset
+                    };
+                    return Some(Err(e.at(seen.positions(&[offset])[0])));
+                }
+            }
+        }
+
+        let seen = self.seen.borrow();
+        if let Some(first) = offsets.first_mut() {
+            *first = seen.skip_line_breaks(*first);
+        }
+        Some(Ok(Record {
+            positions: seen.positions(&offsets),
+            fields: fields,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use err::ValueError;
+
+    fn zoo_schema() -> CsvxSchema {
+        CsvxSchema::from_string(
+            "id,type,constraints,description\r\n\
+             id,INTEGER,UNIQUE,Internal zoo id\r\n\
+             name,STRING,,Name of the animal\r\n\
+             caretaker,STRING,NULLABLE,Designated caretaker\r\n",
+            "schema.csv",
+        ).unwrap()
+    }
+
+    #[test]
+    fn yields_typed_rows() {
+        let schema = zoo_schema();
+        let data = "id,name,caretaker\r\n1,Brian,Sam\r\nx,Pinky,\r\n3,Tweety,\r\n";
+        let rows: Vec<_> = CsvxReader::new(&schema, data.as_bytes(), "<test>")
+            .unwrap()
+            .collect();
+
+        assert_eq!(rows.len(), 3);
+
+        let first = rows[0].as_ref().unwrap();
+        assert_eq!(first[0].clone().unwrap().to_i64(), Some(1));
+        assert_eq!(first[1].clone().unwrap().to_string(), Some("Brian".to_owned()));
+
+        let err = rows[1].as_ref().unwrap_err();
+        match *err.location() {
+            Location::FileLineField(ref f, 3, 1) => assert_eq!(f, "<test>"),
+            ref loc => panic!("unexpected location {:?}", loc),
+        }
+        match *err.error() {
+            ValidationError::ValueError(ValueError::InvalidInt(_)) => (),
+            ref e => panic!("unexpected error {:?}", e),
+        }
+
+        let third = rows[2].as_ref().unwrap();
+        assert!(third[2].is_none());
+    }
+
+    #[test]
+    fn rejects_bad_headers() {
+        let schema = zoo_schema();
+
+        let errs = CsvxReader::new(&schema, "id,nom,caretaker\r\n".as_bytes(), "<test>")
+            .err()
+            .unwrap();
+        assert_eq!(errs.len(), 1);
+        match *errs[0].location() {
+            Location::FileLineField(_, 1, 2) => (),
+            ref loc => panic!("unexpected location {:?}", loc),
+        }
+
+        let errs = CsvxReader::new(&schema, "".as_bytes(), "<test>").err().unwrap();
+        match *errs[0].error() {
+            ValidationError::MissingHeaders => (),
+            ref e => panic!("unexpected error {:?}", e),
+        }
+    }
+
+    #[test]
+    fn deserializes_rows() {
+        #[derive(Debug, Deserialize, PartialEq)]
+        struct Animal {
+            id: i64,
+            name: String,
+            caretaker: Option<String>,
+        }
+
+        let schema = zoo_schema();
+        let data = "id,name,caretaker\r\n1,Brian,Sam\r\n-1,Pinky,\r\n";
+        let rows: Vec<Result<Animal, _>> = CsvxReader::new(&schema, data.as_bytes(), "<test>")
+            .unwrap()
+            .deserialize()
+            .collect();
+
+        assert_eq!(
+            *rows[0].as_ref().unwrap(),
+            Animal {
+                id: 1,
+                name: "Brian".to_owned(),
+                caretaker: Some("Sam".to_owned()),
+            }
+        );
+        assert!(rows[1].as_ref().unwrap().caretaker.is_none());
+
+        #[derive(Debug, Deserialize)]
+        struct Unsigned {
+            #[allow(dead_code)]
+            id: u64,
+        }
+
+        let rows: Vec<Result<Unsigned, _>> = CsvxReader::new(&schema, data.as_bytes(), "<test>")
+            .unwrap()
+            .deserialize()
+            .collect();
+        assert!(rows[0].is_ok());
+        match *rows[1].as_ref().unwrap_err().location() {
+            Location::FileLineField(_, 3, 1) => (),
+            ref loc => panic!("unexpected location {:?}", loc),
+        }
+    }
+
+    #[test]
+    fn records_know_their_positions() {
+        let data = b"a,\"b\r\nc\",d\r\n\r\n\xc3\xa4\xc3\xb6,e\r\nf,\xff\r\n";
+        let mut records = Records::new(&data[..]).flexible(true);
+
+        let first = records.next_record().unwrap().unwrap();
+        assert_eq!(first.fields, vec!["a", "b\r\nc", "d"]);
+        let starts: Vec<_> = first.positions.iter().map(|p| (p.line, p.column)).collect();
+        assert_eq!(starts, vec![(1, 1), (1, 3), (2, 4)]);
+
+        // blank lines are skipped, columns count characters
+        let second = records.next_record().unwrap().unwrap();
+        assert_eq!(second.line(), 4);
+        assert_eq!(second.positions[1], Position { line: 4, column: 4 });
+
+        let err = records.next_record().unwrap().unwrap_err();
+        assert_eq!(*err.location(), Position { line: 5, column: 3 });
+        let err = err.map_location(|p| p.in_file("<test>"));
+        assert_eq!(err.location().to_string(), "<test>:5:3");
+    }
+}
@@ -0,0 +1,325 @@
+//! Checks of `REFERENCES` constraints across files
+
+use err::{ErrorAtLocation, ErrorLoc, Location, ValidationError};
+use safe_unwrap::SafeUnwrap;
+use std::collections::{HashMap, HashSet};
+use std::path;
+use super::{CsvxColumnType, CsvxReader, CsvxSchema, ForeignKey};
+
+/// Value of a referencing column, waiting to be resolved
+struct Reference<'a> {
+    key: &'a ForeignKey,
+    value: String,
+    /// Key of the value, see `CsvxColumnType::value_key`
+    value_key: String,
+    location: Location,
+}
+
+/// Check that all `REFERENCES` constraints are satisfied
+///
+/// `schemas` maps schema names to schemas, `files` pairs every data file with
+/// the name of its schema. A reference is satisfied if its value appears in
+/// the referenced column of any file of the referenced schema.
+///
+/// Every file is read once; rows that cannot be parsed are reported as
+/// well, values of rows that fail to parse are ignored.
+pub fn validate_references<S: AsRef<str>, P: AsRef<path::Path>>(
+    schemas: &HashMap<String, CsvxSchema>,
+    files: &[(S, P)],
+) -> Result<(), Vec<ErrorAtLocation<ValidationError, Location>>> {
+    let mut errs = Vec::new();
+
+    // all foreign keys that can be resolved, along with the values found
+    let mut targets: HashMap<&ForeignKey, HashSet<String>> = HashMap::new();
+    for (schema_name, _) in files {
+        if let Some(schema) = schemas.get(schema_name.as_ref()) {
+            for col in schema.iter_columns() {
+                if let Some(ref fk) = col.constraints.references {
+                    if resolve(schemas, col, fk).is_ok() {
+                        targets.insert(fk, HashSet::new());
+                    }
+                }
+            }
+        }
+    }
+
+    let mut pending = Vec::new();
+    for (schema_name, file) in files {
+        let filename = file.as_ref().to_string_lossy().to_string();
+
+        let schema = match schemas.get(schema_name.as_ref()) {
+            Some(schema) => schema,
+            None => {
+                errs.push(
+                    ValidationError::UnknownSchema(schema_name.as_ref().to_owned())
+                        .at(Location::File(filename)),
+                );
+                continue;
+            }
+        };
+
+        // columns of this file that are referenced elsewhere
+        let referenced: Vec<_> = targets
+            .keys()
+            .filter(|fk| fk.schema == schema_name.as_ref())
+            .map(|fk| {
+                (schema.col_idx(&fk.column).safe_unwrap("resolvable key"), *fk)
+            })
+            .collect();
+
+        // columns of this file referencing others
+        let mut referencing = Vec::new();
+        for (idx, col) in schema.iter_columns().enumerate() {
+            if let Some(ref fk) = col.constraints.references {
+                match resolve(schemas, col, fk) {
+                    Ok(()) => referencing.push((idx, fk)),
+                    Err(e) => {
+                        errs.push(e.at(Location::FileLineField(filename.clone(), 1, idx + 1)))
+                    }
+                }
+            }
+        }
+
+        let mut reader = match CsvxReader::from_file(schema, file) {
+            Ok(reader) => reader,
+            Err(e) => {
+                errs.extend(e);
+                continue;
+            }
+        };
+
+        while let Some(row) = reader.next() {
+            let values = match row {
+                Ok(values) => values,
+                Err(e) => {
+                    errs.push(e);
+                    continue;
+                }
+            };
+
+            // values are compared by key, `1.50` satisfies a reference to `1.5`
+            let value_keys: Vec<_> = schema
+                .iter_columns()
+                .zip(values.iter())
+                .map(|(col, value)| value.as_ref().and_then(|v| col.value_key(v).ok()))
+                .collect();
+
+            for &(idx, fk) in &referenced {
+                if let Some(ref s) = value_keys[idx] {
+                    targets
+                        .get_mut(fk)
+                        .safe_unwrap("known target")
+                        .insert(s.clone());
+                }
+            }
+
+            for &(idx, fk) in &referencing {
+                if let Some(ref s) = value_keys[idx] {
+                    let value = schema.columns[idx].render_value(&values[idx]);
+                    pending.push(Reference {
+                        key: fk,
+                        value: value.unwrap_or_else(|_| s.clone()),
+                        value_key: s.clone(),
+                        location: reader.location(idx + 1),
+                    });
+                }
+            }
+        }
+    }
+
+    for reference in pending {
+        if !targets[reference.key].contains(&reference.value_key) {
+            errs.push(
+                ValidationError::DanglingReference(reference.value, reference.key.clone())
+                    .at(reference.location),
+            );
+        }
+    }
+
+    if errs.is_empty() { Ok(()) } else { Err(errs) }
+}
+
+/// Check that the column referenced by `col` exists and is of a compatible type
+fn resolve(
+    schemas: &HashMap<String, CsvxSchema>,
+    col: &CsvxColumnType,
+    fk: &ForeignKey,
+) -> Result<(), ValidationError> {
+    let target = schemas
+        .get(&fk.schema)
+        .and_then(|s| s.iter_columns().find(|c| c.id == fk.column))
+        .ok_or_else(|| ValidationError::UnresolvedReference(fk.clone()))?;
+
+    if !col.ty.is_compatible(&target.ty) {
+        return Err(ValidationError::IncompatibleReference(
+            fk.clone(),
+            col.ty.clone(),
+            target.ty.clone(),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::{env, fs};
+    use std::io::Write;
+    use super::*;
+    use ColumnType;
+
+    fn write_file(name: &str, data: &str) -> path::PathBuf {
+        let path = env::temp_dir().join(name);
+        fs::File::create(&path)
+            .unwrap()
+            .write_all(data.as_bytes())
+            .unwrap();
+        path
+    }
+
+    fn zoo_schemas() -> HashMap<String, CsvxSchema> {
+        let mut schemas = HashMap::new();
+        schemas.insert(
+            "staff-1".to_owned(),
+            CsvxSchema::from_string(
+                "id,type,constraints,description\r\n\
+                 id,INTEGER,UNIQUE,Staff id\r\n\
+                 name,STRING,,Name\r\n",
+                "staff.csv",
+            ).unwrap(),
+        );
+        schemas.insert(
+            "animals-3".to_owned(),
+            CsvxSchema::from_string(
+                "id,type,constraints,description\r\n\
+                 id,INTEGER,UNIQUE,Internal zoo id\r\n\
+                 caretaker,INTEGER,\"NULLABLE,REFERENCES(staff-1.id)\",Caretaker\r\n",
+                "animals.csv",
+            ).unwrap(),
+        );
+        schemas
+    }
+
+    #[test]
+    fn dangling_references_are_reported() {
+        let schemas = zoo_schemas();
+        let staff_a = write_file("refs-a_20170401_staff-1.csv", "id,name\r\n1,Sam\r\n");
+        let staff_b = write_file("refs-b_20170401_staff-1.csv", "id,name\r\n2,Alex\r\n");
+        let animals = write_file(
+            "refs_20170401_animals-3.csv",
+            "id,caretaker\r\n1,1\r\n2,\r\n3,2\r\n4,3\r\n",
+        );
+
+        let res = validate_references(
+            &schemas,
+            &[
+                ("animals-3", &animals),
+                ("staff-1", &staff_a),
+                ("staff-1", &staff_b),
+            ],
+        );
+        let errs = res.unwrap_err();
+        assert_eq!(errs.len(), 1);
+        match *errs[0].location() {
+            Location::FileLineField(_, 5, 2) => (),
+            ref loc => panic!("unexpected location {:?}", loc),
+        }
+        match *errs[0].error() {
+            ValidationError::DanglingReference(ref value, ref fk) => {
+                assert_eq!(value, "3");
+                assert_eq!(fk.to_string(), "staff-1.id");
+            }
+            ref e => panic!("unexpected error {:?}", e),
+        }
+
+        // without the staff tables, the reference cannot be resolved
+        let mut without_staff = zoo_schemas();
+        without_staff.remove("staff-1");
+        let errs = validate_references(&without_staff, &[("animals-3", &animals)]).unwrap_err();
+        match *errs[0].error() {
+            ValidationError::UnresolvedReference(_) => (),
+            ref e => panic!("unexpected error {:?}", e),
+        }
+
+        for path in &[staff_a, staff_b, animals] {
+            fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn references_compare_values() {
+        let mut schemas = HashMap::new();
+        schemas.insert(
+            "rates-1".to_owned(),
+            CsvxSchema::from_string(
+                "id,type,constraints,description\r\n\
+                 rate,DECIMAL,UNIQUE,Feeding rate\r\n",
+                "rates.csv",
+            ).unwrap(),
+        );
+        schemas.insert(
+            "feedings-1".to_owned(),
+            CsvxSchema::from_string(
+                "id,type,constraints,description\r\n\
+                 rate,\"DECIMAL(4,2)\",REFERENCES(rates-1.rate),Rate applied\r\n",
+                "feedings.csv",
+            ).unwrap(),
+        );
+        let rates = write_file("refs_20170401_rates-1.csv", "rate\r\n1.5\r\n2\r\n");
+        let feedings = write_file(
+            "refs_20170401_feedings-1.csv",
+            "rate\r\n1.50\r\n2.00\r\n2.50\r\n",
+        );
+
+        let errs = validate_references(
+            &schemas,
+            &[("rates-1", &rates), ("feedings-1", &feedings)],
+        ).unwrap_err();
+        assert_eq!(errs.len(), 1);
+        match *errs[0].error() {
+            ValidationError::DanglingReference(ref value, _) => assert_eq!(value, "2.50"),
+            ref e => panic!("unexpected error {:?}", e),
+        }
+
+        for path in &[rates, feedings] {
+            fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn incompatible_references_are_rejected() {
+        let mut schemas = zoo_schemas();
+        schemas.insert(
+            "animals-3".to_owned(),
+            CsvxSchema::from_string(
+                "id,type,constraints,description\r\n\
+                 id,INTEGER,UNIQUE,Internal zoo id\r\n\
+                 caretaker,INTEGER,\"NULLABLE,REFERENCES(staff-1.name)\",Caretaker\r\n",
+                "animals.csv",
+            ).unwrap(),
+        );
+        let staff = write_file("refs-ty_20170401_staff-1.csv", "id,name\r\n1,1\r\n");
+        let animals = write_file("refs-ty_20170401_animals-3.csv", "id,caretaker\r\n1,1\r\n");
+
+        let errs = validate_references(
+            &schemas,
+            &[("animals-3", &animals), ("staff-1", &staff)],
+        ).unwrap_err();
+        assert_eq!(errs.len(), 1);
+        match *errs[0].location() {
+            Location::FileLineField(_, 1, 2) => (),
+            ref loc => panic!("unexpected location {:?}", loc),
+        }
+        match *errs[0].error() {
+            ValidationError::IncompatibleReference(ref fk, ref ty, ref target_ty) => {
+                assert_eq!(fk.to_string(), "staff-1.name");
+                assert_eq!(*ty, ColumnType::Integer);
+                assert_eq!(*target_ty, ColumnType::String);
+            }
+            ref e => panic!("unexpected error {:?}", e),
+        }
+
+        for path in &[staff, animals] {
+            fs::remove_file(path).unwrap();
+        }
+    }
+}
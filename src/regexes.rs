@@ -15,8 +15,30 @@ lazy_static! {
 }
 
 lazy_static! {
+    // `DECIMAL(precision,scale)`
+    pub static ref DECIMAL_TYPE_RE: Regex = Regex::new(
+        r"^DECIMAL\((\d+),(\d+)\)$"
+    ).safe_unwrap("built-in Regex is broken. Please file a bug");
+}
+
+lazy_static! {
+    // a single constraint, optionally followed by arguments in parentheses
     pub static ref CONSTRAINT_RE: Regex = Regex::new(
-        r"^(:?[A-Z]+,?)*$"
+        r"^[A-Z]+(?:\(.*\))?$"
+    ).safe_unwrap("built-in Regex is broken. Please file a bug");
+}
+
+lazy_static! {
+    // `KEY(name)`
+    pub static ref KEY_RE: Regex = Regex::new(
+        r"^KEY\(([a-z][a-z0-9_]*)\)$"
+    ).safe_unwrap("built-in Regex is broken. Please file a bug");
+}
+
+lazy_static! {
+    // `REFERENCES(schema.column)`
+    pub static ref REFERENCES_RE: Regex = Regex::new(
+        r"^REFERENCES\(([a-z][a-z0-9-]*)\.([a-z][a-z0-9_]*)\)$"
     ).safe_unwrap("built-in Regex is broken. Please file a bug");
 }
 
@@ -34,7 +56,15 @@ lazy_static! {
 
 lazy_static! {
     pub static ref DATETIME_RE: Regex = Regex::new(
-        r"^(\d{4})(\d{2})(\d{2})(\d{2})(\d{2})(\d{2})$"
+        r"^(\d{4})(\d{2})(\d{2})(\d{2})(\d{2})(\d{2})(?:\.(\d+))?(Z|[+-]\d{4})?$"
+    ).safe_unwrap("built-in Regex is broken. Please file a bug");
+}
+
+lazy_static! {
+    // `DATETIME`, `DATETIMETZ`, optionally followed by the number of
+    // fractional digits in parentheses
+    pub static ref DATETIME_TYPE_RE: Regex = Regex::new(
+        r"^DATETIME(TZ)?(?:\((\d+)\))?$"
     ).safe_unwrap("built-in Regex is broken. Please file a bug");
 }
 
@@ -45,8 +75,9 @@ lazy_static! {
 }
 
 lazy_static! {
-    // `tablename_date_schema-schemaversion_csvxversion.csvx`
+    // `tablename_date_schema-schemaversion.csv`, optionally followed by a
+    // compression suffix
     pub static ref FN_RE: Regex = Regex::new(
-        r"^([a-z][a-z0-9-]*)_(\d{4})(\d{2})(\d{2})_([a-z][a-z0-9-]*).csv$"
+        r"^([a-z][a-z0-9-]*)_(\d{4})(\d{2})(\d{2})_([a-z][a-z0-9-]*)\.csv(?:\.(gzip|xz))?$"
     ).expect("built-in Regex is broken. Please file a bug");
 }
@@ -0,0 +1,377 @@
+//! Machine-readable reports of validation results
+//!
+//! A report is a sequence of entries: the errors found in a file, followed by
+//! a summary of the file. Entries serialize to JSON objects with a `type` of
+//! either `error` or `summary`, and can be rendered as JUnit XML or SARIF for
+//! CI systems and code review tools.
+
+use err::{ErrorAtLocation, Helpful, Location, ValidationError};
+use serde_json::Value as Json;
+use std::fmt;
+use std::fmt::Write;
+use super::{CsvxSchema, Source};
+
+/// Version of SARIF logs written by `to_sarif`
+const SARIF_VERSION: &str = "2.1.0";
+const SARIF_SCHEMA: &str = "https://json.schemastore.org/sarif-2.1.0.json";
+
+/// Kind of errors that prevent checking any further, e.g. an invalid schema
+pub const FATAL_KIND: &str = "fatal";
+
+/// Entry of a report
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ReportEntry {
+    Error(ErrorEntry),
+    Summary(FileSummary),
+}
+
+/// A single error
+///
+/// Location fields are `null` if the error is not located that precisely.
+#[derive(Clone, Debug, Serialize)]
+pub struct ErrorEntry {
+    pub file: Option<String>,
+    pub line: Option<usize>,
+    /// 1-based index of the field
+    pub field: Option<usize>,
+    /// Id of the column the field belongs to
+    pub column: Option<String>,
+    /// Machine-readable kind of error, see `ValidationError::kind`
+    pub kind: String,
+    /// The offending value, as found in the file
+    pub value: Option<String>,
+    pub message: String,
+    pub help: String,
+}
+
+/// Outcome of checking a single file
+#[derive(Clone, Debug, Serialize)]
+pub struct FileSummary {
+    pub file: String,
+    pub valid: bool,
+    pub errors: usize,
+}
+
+impl ErrorEntry {
+    /// Describe a validation error
+    ///
+    /// The column is looked up in `schema`, the offending value in `source`.
+    pub fn new(
+        err: &ErrorAtLocation<ValidationError, Location>,
+        schema: Option<&CsvxSchema>,
+        source: Option<&Source>,
+    ) -> ErrorEntry {
+        let mut entry = ErrorEntry::from_error(err, err.error().kind());
+
+        if let (Some(line), Some(field)) = (entry.line, entry.field) {
+            entry.column = schema
+                .and_then(|s| s.iter_columns().nth(field - 1))
+                .map(|col| col.id.clone());
+            entry.value = source
+                .filter(|s| entry.file.as_deref() == Some(s.filename()))
+                .and_then(|s| s.field(line, field));
+        }
+        entry
+    }
+
+    /// Describe a fatal error
+    pub fn fatal<E: fmt::Display + Helpful>(err: &ErrorAtLocation<E, Location>) -> ErrorEntry {
+        ErrorEntry::from_error(err, FATAL_KIND)
+    }
+
+    fn from_error<E: fmt::Display + Helpful>(
+        err: &ErrorAtLocation<E, Location>,
+        kind: &str,
+    ) -> ErrorEntry {
+        let (line, field) = match *err.location() {
+            Location::FileLineColumn(_, line, _) | Location::FileLine(_, line) => {
+                (Some(line), None)
+            }
+            Location::FileLineField(_, line, field) => (Some(line), Some(field)),
+            Location::File(_) | Location::Unspecified => (None, None),
+        };
+
+        ErrorEntry {
+            file: err.location().file().map(str::to_owned),
+            line: line,
+            field: field,
+            column: None,
+            kind: kind.to_owned(),
+            value: None,
+            message: err.error().to_string(),
+            help: err.error().help(),
+        }
+    }
+}
+
+impl FileSummary {
+    pub fn new(file: &str, errors: usize) -> FileSummary {
+        FileSummary {
+            file: file.to_owned(),
+            valid: errors == 0,
+            errors: errors,
+        }
+    }
+}
+
+impl ErrorEntry {
+    /// Location in the style of `Location`, e.g. `zoo.csv:3[field 2]`
+    fn location(&self) -> String {
+        let mut loc = self.file.clone().unwrap_or_default();
+        if let Some(line) = self.line {
+            let _ = write!(loc, ":{}", line);
+        }
+        if let Some(field) = self.field {
+            let _ = write!(loc, "[field {}]", field);
+        }
+        loc
+    }
+}
+
+/// Render a report as JUnit XML
+///
+/// Every summarized file becomes a test case, failing with all errors found
+/// in it. Fatal errors, which are not followed by a summary, become test
+/// cases with an error.
+pub fn to_junit(entries: &[ReportEntry]) -> String {
+    let mut cases = Vec::new();
+    let mut errors: Vec<&ErrorEntry> = Vec::new();
+    for entry in entries {
+        match *entry {
+            ReportEntry::Error(ref e) => errors.push(e),
+            ReportEntry::Summary(ref summary) => {
+                cases.push((summary.file.clone(), errors, false));
+                errors = Vec::new();
+            }
+        }
+    }
+    for e in errors {
+        let name = e.file.clone().unwrap_or_else(|| "csvx".to_owned());
+        cases.push((name, vec![e], true));
+    }
+
+    let failures = cases.iter().filter(|c| !c.1.is_empty() && !c.2).count();
+    let fatal = cases.iter().filter(|c| c.2).count();
+
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    let _ = writeln!(
+        xml,
+        "<testsuites name=\"csvx\" tests=\"{0}\" failures=\"{1}\" errors=\"{2}\">\n  \
+         <testsuite name=\"csvx check\" tests=\"{0}\" failures=\"{1}\" errors=\"{2}\">",
+        cases.len(),
+        failures,
+        fatal
+    );
+    for (name, errors, is_fatal) in cases {
+        let _ = write!(xml, "    <testcase classname=\"csvx\" name=\"{}\"", xml_escape(&name));
+        if errors.is_empty() {
+            xml.push_str("/>\n");
+            continue;
+        }
+        xml.push_str(">\n");
+
+        // a failure may consist of several kinds of errors
+        let (tag, kind, message) = if is_fatal {
+            ("error", FATAL_KIND, errors[0].message.clone())
+        } else {
+            ("failure", "validation", format!("{} error(s)", errors.len()))
+        };
+        let details: Vec<_> = errors
+            .iter()
+            .map(|e| match e.column {
+                Some(ref column) => format!("{} ({}): {}", e.location(), column, e.message),
+                None => format!("{}: {}", e.location(), e.message),
+            })
+            .collect();
+        let _ = writeln!(
+            xml,
+            "      <{0} type=\"{1}\" message=\"{2}\">{3}</{0}>",
+            tag,
+            kind,
+            xml_escape(&message),
+            xml_escape(&details.join("\n"))
+        );
+        xml.push_str("    </testcase>\n");
+    }
+    xml.push_str("  </testsuite>\n</testsuites>\n");
+    xml
+}
+
+/// Render a report as a SARIF log
+///
+/// Every error becomes a result, with its kind as rule id. Summaries are left
+/// out, as SARIF only lists problems.
+pub fn to_sarif(entries: &[ReportEntry]) -> Json {
+    let mut rules: Vec<Json> = Vec::new();
+    let mut rule_ids: Vec<&str> = Vec::new();
+    let mut results = Vec::new();
+
+    for entry in entries {
+        let e = match *entry {
+            ReportEntry::Error(ref e) => e,
+            ReportEntry::Summary(_) => continue,
+        };
+
+        let rule_index = match rule_ids.iter().position(|&id| id == e.kind) {
+            Some(idx) => idx,
+            None => {
+                rule_ids.push(&e.kind);
+                rules.push(json!({
+                    "id": e.kind,
+                    "help": { "text": e.help },
+                }));
+                rule_ids.len() - 1
+            }
+        };
+
+        let mut result = json!({
+            "ruleId": e.kind,
+            "ruleIndex": rule_index,
+            "level": "error",
+            "message": { "text": e.message },
+            "properties": {
+                "field": e.field,
+                "column": e.column,
+                "value": e.value,
+            },
+        });
+        if let Some(ref file) = e.file {
+            let mut location = json!({ "artifactLocation": { "uri": uri(file) } });
+            if let Some(line) = e.line {
+                location["region"] = json!({ "startLine": line });
+            }
+            result["locations"] = json!([{ "physicalLocation": location }]);
+        }
+        results.push(result);
+    }
+
+    json!({
+        "$schema": SARIF_SCHEMA,
+        "version": SARIF_VERSION,
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": "csvx",
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "rules": rules,
+                },
+            },
+            "results": results,
+        }],
+    })
+}
+
+/// Escape text for use in XML attributes and elements
+///
+/// Control characters cannot be represented in XML 1.0 and are replaced.
+fn xml_escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            '\t' | '\n' | '\r' => escaped.push(c),
+            c if c.is_control() => escaped.push('\u{fffd}'),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Turn a path into a relative URI reference
+fn uri(path: &str) -> String {
+    let mut uri = String::with_capacity(path.len());
+    for b in path.replace('\\', "/").bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                uri.push(b as char)
+            }
+            b => {
+                let _ = write!(uri, "%{:02X}", b);
+            }
+        }
+    }
+    uri
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json;
+    use super::*;
+
+    #[test]
+    fn entries_describe_errors() {
+        let schema = CsvxSchema::from_string(
+            "id,type,constraints,description\r\n\
+             id,INTEGER,UNIQUE,Internal zoo id\r\n\
+             name,STRING,MAXLEN(5),Name of the animal\r\n",
+            "schema.csv",
+        ).unwrap();
+        let source = Source::new("zoo.csv", b"id,name\r\n1,Brian\r\n2,\"Pinky\r\nPie\"\r\n");
+        let err = schema.parse_row(&vec!["2".to_owned(), "Pinky\r\nPie".to_owned()])
+            .unwrap_err()
+            .map_location(|idx| Location::FileLineField("zoo.csv".to_owned(), 3, idx));
+
+        let entry = ErrorEntry::new(&err, Some(&schema), Some(&source));
+        assert_eq!(entry.column, Some("name".to_owned()));
+        assert_eq!(entry.value, Some("Pinky\r\nPie".to_owned()));
+        assert_eq!(entry.kind, "too_long");
+
+        let json = serde_json::to_value(ReportEntry::Error(entry)).unwrap();
+        assert_eq!(json["type"], "error");
+        assert_eq!(json["line"], 3);
+        assert_eq!(json["field"], 2);
+
+        // no value is looked up in other files
+        let err = err.map_location(|_| Location::FileLineField("other.csv".to_owned(), 3, 2));
+        assert!(ErrorEntry::new(&err, None, Some(&source)).value.is_none());
+
+        let json = serde_json::to_value(ReportEntry::Summary(FileSummary::new("zoo.csv", 1)))
+            .unwrap();
+        assert_eq!(json["type"], "summary");
+        assert_eq!(json["valid"], false);
+    }
+
+    #[test]
+    fn junit_and_sarif_reports() {
+        let entry = |file: &str, line, kind: &str, message: &str| {
+            ReportEntry::Error(ErrorEntry {
+                file: Some(file.to_owned()),
+                line: Some(line),
+                field: Some(2),
+                column: Some("name".to_owned()),
+                kind: kind.to_owned(),
+                value: Some("<Brian>".to_owned()),
+                message: message.to_owned(),
+                help: "Help & advice".to_owned(),
+            })
+        };
+        let entries = vec![
+            entry("zoo a.csv", 3, "too_long", "value is \"too\" long"),
+            entry("zoo a.csv", 5, "invalid_int", "not an <INTEGER>"),
+            ReportEntry::Summary(FileSummary::new("zoo a.csv", 2)),
+            ReportEntry::Summary(FileSummary::new("zoo b.csv", 0)),
+        ];
+
+        let junit = to_junit(&entries);
+        assert!(junit.contains("tests=\"2\" failures=\"1\" errors=\"0\""));
+        assert!(junit.contains("<testcase classname=\"csvx\" name=\"zoo b.csv\"/>"));
+        assert!(junit.contains("<failure type=\"validation\" message=\"2 error(s)\">"));
+        assert!(junit.contains("zoo a.csv:3[field 2] (name): value is &quot;too&quot; long\n"));
+        assert!(junit.contains("not an &lt;INTEGER&gt;</failure>"));
+
+        let sarif = to_sarif(&entries);
+        let results = &sarif["runs"][0]["results"];
+        assert_eq!(results.as_array().unwrap().len(), 2);
+        assert_eq!(results[1]["ruleId"], "invalid_int");
+        assert_eq!(results[1]["ruleIndex"], 1);
+        let location = &results[1]["locations"][0]["physicalLocation"];
+        assert_eq!(location["artifactLocation"]["uri"], "zoo%20a.csv");
+        assert_eq!(location["region"]["startLine"], 5);
+        assert_eq!(sarif["runs"][0]["tool"]["driver"]["rules"][0]["help"]["text"], "Help & advice");
+    }
+}
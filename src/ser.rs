@@ -0,0 +1,619 @@
+//! Serialization of user-defined types into rows
+//!
+//! The counterpart to `de`: structs are matched to columns by field name,
+//! tuples and sequences by position. The resulting values still need to be
+//! rendered, which `CsvxWriter::serialize` takes care of.
+
+use chrono::{DateTime, FixedOffset, NaiveDate, NaiveDateTime, NaiveTime};
+use err::{ErrorAtLocation, ErrorLoc, ValidationError, ValueError};
+use serde::ser::{self, Impossible, Serialize};
+use std::cell::Cell;
+use std::fmt::Display;
+use super::{ColumnType, CsvxColumnType, CsvxSchema, Value};
+use uuid::Uuid;
+
+impl CsvxSchema {
+    /// Serialize `record` into the values of a single row
+    ///
+    /// Errors are located at the 1-based index of the offending field, or at
+    /// `0` if the record as a whole does not fit the schema. Columns not
+    /// present in `record` are left empty.
+    pub fn serialize_row<T: Serialize>(
+        &self,
+        record: &T,
+    ) -> Result<Vec<Option<Value>>, ErrorAtLocation<ValidationError, usize>> {
+        let current = Cell::new(0);
+        let rv = record.serialize(RowSerializer {
+            schema: self,
+            row: vec![None; self.columns.len()],
+            pos: 0,
+            current: &current,
+        });
+
+        rv.map_err(|e| e.at(current.get()))
+    }
+}
+
+fn unsupported(what: &str) -> ValidationError {
+    ValidationError::Serde(format!("cannot serialize {} as a row", what))
+}
+
+/// Serializer for a whole row
+struct RowSerializer<'a> {
+    schema: &'a CsvxSchema,
+    row: Vec<Option<Value>>,
+    pos: usize,
+    current: &'a Cell<usize>,
+}
+
+impl<'a> RowSerializer<'a> {
+    fn set<T: ?Sized + Serialize>(&mut self, idx: usize, value: &T) -> Result<(), ValidationError> {
+        self.current.set(idx + 1);
+        self.row[idx] = value.serialize(ValueSerializer { col: &self.schema.columns[idx] })?;
+        Ok(())
+    }
+
+    fn push<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), ValidationError> {
+        if self.pos >= self.row.len() {
+            self.current.set(0);
+            return Err(ValidationError::SchemaMismatch);
+        }
+
+        let idx = self.pos;
+        self.pos += 1;
+        self.set(idx, value)
+    }
+
+    fn finish(self) -> Result<Vec<Option<Value>>, ValidationError> {
+        self.current.set(0);
+        Ok(self.row)
+    }
+}
+
+impl<'a> ser::Serializer for RowSerializer<'a> {
+    type Ok = Vec<Option<Value>>;
+    type Error = ValidationError;
+
+    type SerializeSeq = Self;
+    type SerializeTuple = Self;
+    type SerializeTupleStruct = Self;
+    type SerializeTupleVariant = Impossible<Self::Ok, Self::Error>;
+    type SerializeMap = Impossible<Self::Ok, Self::Error>;
+    type SerializeStruct = Self;
+    type SerializeStructVariant = Impossible<Self::Ok, Self::Error>;
+
+    fn serialize_bool(self, _v: bool) -> Result<Self::Ok, Self::Error> {
+        Err(unsupported("a bool"))
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<Self::Ok, Self::Error> {
+        self.serialize_i64(v as i64)
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<Self::Ok, Self::Error> {
+        self.serialize_i64(v as i64)
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<Self::Ok, Self::Error> {
+        self.serialize_i64(v as i64)
+    }
+
+    fn serialize_i64(self, _v: i64) -> Result<Self::Ok, Self::Error> {
+        Err(unsupported("an integer"))
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<Self::Ok, Self::Error> {
+        self.serialize_i64(v as i64)
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<Self::Ok, Self::Error> {
+        self.serialize_i64(v as i64)
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<Self::Ok, Self::Error> {
+        self.serialize_i64(v as i64)
+    }
+
+    fn serialize_u64(self, _v: u64) -> Result<Self::Ok, Self::Error> {
+        Err(unsupported("an integer"))
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<Self::Ok, Self::Error> {
+        self.serialize_f64(v as f64)
+    }
+
+    fn serialize_f64(self, _v: f64) -> Result<Self::Ok, Self::Error> {
+        Err(unsupported("a float"))
+    }
+
+    fn serialize_char(self, _v: char) -> Result<Self::Ok, Self::Error> {
+        Err(unsupported("a char"))
+    }
+
+    fn serialize_str(self, _v: &str) -> Result<Self::Ok, Self::Error> {
+        Err(unsupported("a string"))
+    }
+
+    fn serialize_bytes(self, _v: &[u8]) -> Result<Self::Ok, Self::Error> {
+        Err(unsupported("bytes"))
+    }
+
+    fn serialize_none(self) -> Result<Self::Ok, Self::Error> {
+        Err(unsupported("`None`"))
+    }
+
+    fn serialize_some<T: ?Sized + Serialize>(self, value: &T) -> Result<Self::Ok, Self::Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<Self::Ok, Self::Error> {
+        Err(unsupported("`()`"))
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Self::Ok, Self::Error> {
+        Err(unsupported("a unit struct"))
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+    ) -> Result<Self::Ok, Self::Error> {
+        Err(unsupported("an enum"))
+    }
+
+    fn serialize_newtype_struct<T: ?Sized + Serialize>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<Self::Ok, Self::Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: ?Sized + Serialize>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<Self::Ok, Self::Error> {
+        Err(unsupported("an enum"))
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq, Self::Error> {
+        Ok(self)
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple, Self::Error> {
+        Ok(self)
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleStruct, Self::Error> {
+        Ok(self)
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant, Self::Error> {
+        Err(unsupported("an enum"))
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, Self::Error> {
+        Err(unsupported("a map"))
+    }
+
+    fn serialize_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStruct, Self::Error> {
+        Ok(self)
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant, Self::Error> {
+        Err(unsupported("an enum"))
+    }
+}
+
+impl<'a> ser::SerializeSeq for RowSerializer<'a> {
+    type Ok = Vec<Option<Value>>;
+    type Error = ValidationError;
+
+    fn serialize_element<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), Self::Error> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Self::Ok, Self::Error> {
+        self.finish()
+    }
+}
+
+impl<'a> ser::SerializeTuple for RowSerializer<'a> {
+    type Ok = Vec<Option<Value>>;
+    type Error = ValidationError;
+
+    fn serialize_element<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), Self::Error> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Self::Ok, Self::Error> {
+        self.finish()
+    }
+}
+
+impl<'a> ser::SerializeTupleStruct for RowSerializer<'a> {
+    type Ok = Vec<Option<Value>>;
+    type Error = ValidationError;
+
+    fn serialize_field<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), Self::Error> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Self::Ok, Self::Error> {
+        self.finish()
+    }
+}
+
+impl<'a> ser::SerializeStruct for RowSerializer<'a> {
+    type Ok = Vec<Option<Value>>;
+    type Error = ValidationError;
+
+    fn serialize_field<T: ?Sized + Serialize>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), Self::Error> {
+        match self.schema.col_idx(key) {
+            Some(idx) => self.set(idx, value),
+            None => {
+                self.current.set(0);
+                Err(ValidationError::Serde(format!("no column named `{}`", key)))
+            }
+        }
+    }
+
+    fn end(self) -> Result<Self::Ok, Self::Error> {
+        self.finish()
+    }
+}
+
+/// Serializer for a single value
+///
+/// Conversions are driven by the column type; chrono types are recognized
+/// by the string representation they serialize to.
+struct ValueSerializer<'a> {
+    col: &'a CsvxColumnType,
+}
+
+impl<'a> ValueSerializer<'a> {
+    fn wrong_type(&self) -> ValidationError {
+        ValidationError::ValueError(ValueError::WrongType(self.col.ty.to_string()))
+    }
+
+    fn integer<I: Display>(&self, v: I) -> Result<Option<Value>, ValidationError> {
+        let s = v.to_string();
+        match self.col.ty {
+            ColumnType::Integer => {
+                let i = s.parse().map_err(|_| ValueError::InvalidInt(s))?;
+                Ok(Some(Value::Integer(i)))
+            }
+            ColumnType::Decimal(_) => Ok(Some(Value::Decimal(s.parse()?))),
+            _ => Err(self.wrong_type()),
+        }
+    }
+
+    fn variant(&self, name: &str) -> Result<Option<Value>, ValidationError> {
+        match self.col.ty {
+            ColumnType::Enum(ref variants) => {
+                match variants.iter().position(|v| v == name) {
+                    Some(idx) => Ok(Some(Value::Enum(idx))),
+                    None => Err(
+                        ValueError::InvalidEnum(name.to_owned(), variants.clone()).into(),
+                    ),
+                }
+            }
+            ColumnType::String => Ok(Some(Value::String(name.to_owned()))),
+            _ => Err(self.wrong_type()),
+        }
+    }
+}
+
+impl<'a> ser::Serializer for ValueSerializer<'a> {
+    type Ok = Option<Value>;
+    type Error = ValidationError;
+
+    type SerializeSeq = Impossible<Self::Ok, Self::Error>;
+    type SerializeTuple = Impossible<Self::Ok, Self::Error>;
+    type SerializeTupleStruct = Impossible<Self::Ok, Self::Error>;
+    type SerializeTupleVariant = Impossible<Self::Ok, Self::Error>;
+    type SerializeMap = Impossible<Self::Ok, Self::Error>;
+    type SerializeStruct = Impossible<Self::Ok, Self::Error>;
+    type SerializeStructVariant = Impossible<Self::Ok, Self::Error>;
+
+    fn serialize_bool(self, v: bool) -> Result<Self::Ok, Self::Error> {
+        match self.col.ty {
+            ColumnType::Bool => Ok(Some(Value::Bool(v))),
+            _ => Err(self.wrong_type()),
+        }
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<Self::Ok, Self::Error> {
+        self.integer(v)
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<Self::Ok, Self::Error> {
+        self.integer(v)
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<Self::Ok, Self::Error> {
+        self.integer(v)
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<Self::Ok, Self::Error> {
+        self.integer(v)
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<Self::Ok, Self::Error> {
+        self.integer(v)
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<Self::Ok, Self::Error> {
+        self.integer(v)
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<Self::Ok, Self::Error> {
+        self.integer(v)
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<Self::Ok, Self::Error> {
+        self.integer(v)
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<Self::Ok, Self::Error> {
+        self.serialize_f64(v as f64)
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<Self::Ok, Self::Error> {
+        match self.col.ty {
+            // `Display` for floats never uses exponential notation;
+            // infinities and NaN are rejected when the value is rendered
+            ColumnType::Decimal(_) => Ok(Some(Value::Decimal(v.to_string().parse()?))),
+            _ => Err(self.wrong_type()),
+        }
+    }
+
+    fn serialize_char(self, v: char) -> Result<Self::Ok, Self::Error> {
+        self.serialize_str(v.encode_utf8(&mut [0; 4]))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<Self::Ok, Self::Error> {
+        match self.col.ty {
+            ColumnType::String => Ok(Some(Value::String(v.to_owned()))),
+            ColumnType::Decimal(_) => Ok(Some(Value::Decimal(v.parse()?))),
+            ColumnType::Enum(_) => self.variant(v),
+            ColumnType::Date => {
+                let d = v.parse::<NaiveDate>()
+                    .map_err(|_| ValueError::InvalidDate(v.to_owned()))?;
+                Ok(Some(Value::Date(d)))
+            }
+            ColumnType::DateTime(_) => {
+                let dt = v.parse::<NaiveDateTime>()
+                    .map_err(|_| ValueError::InvalidDateTime(v.to_owned()))?;
+                Ok(Some(Value::DateTime(dt)))
+            }
+            ColumnType::DateTimeTz(_) => {
+                let dt = v.parse::<DateTime<FixedOffset>>()
+                    .map_err(|_| ValueError::InvalidDateTimeTz(v.to_owned()))?;
+                Ok(Some(Value::DateTimeTz(dt)))
+            }
+            ColumnType::Time => {
+                let t = v.parse::<NaiveTime>()
+                    .map_err(|_| ValueError::InvalidTime(v.to_owned()))?;
+                Ok(Some(Value::Time(t)))
+            }
+            ColumnType::Uuid => {
+                let u = Uuid::parse_str(v).map_err(|_| ValueError::InvalidUuid(v.to_owned()))?;
+                Ok(Some(Value::Uuid(u)))
+            }
+            _ => Err(self.wrong_type()),
+        }
+    }
+
+    fn serialize_bytes(self, _v: &[u8]) -> Result<Self::Ok, Self::Error> {
+        Err(self.wrong_type())
+    }
+
+    fn serialize_none(self) -> Result<Self::Ok, Self::Error> {
+        Ok(None)
+    }
+
+    fn serialize_some<T: ?Sized + Serialize>(self, value: &T) -> Result<Self::Ok, Self::Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<Self::Ok, Self::Error> {
+        Ok(None)
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Self::Ok, Self::Error> {
+        Ok(None)
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+    ) -> Result<Self::Ok, Self::Error> {
+        self.variant(variant)
+    }
+
+    fn serialize_newtype_struct<T: ?Sized + Serialize>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<Self::Ok, Self::Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: ?Sized + Serialize>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<Self::Ok, Self::Error> {
+        Err(self.wrong_type())
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq, Self::Error> {
+        Err(self.wrong_type())
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple, Self::Error> {
+        Err(self.wrong_type())
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleStruct, Self::Error> {
+        Err(self.wrong_type())
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant, Self::Error> {
+        Err(self.wrong_type())
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, Self::Error> {
+        Err(self.wrong_type())
+    }
+
+    fn serialize_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStruct, Self::Error> {
+        Err(self.wrong_type())
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant, Self::Error> {
+        Err(self.wrong_type())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::NaiveDate;
+    use super::*;
+
+    #[derive(Serialize)]
+    enum Class {
+        #[serde(rename = "BIRD")]
+        Bird,
+    }
+
+    #[derive(Serialize)]
+    struct Animal {
+        caretaker: Option<String>,
+        id: u32,
+        name: String,
+        birthday: NaiveDate,
+        class: Class,
+        weight: f64,
+    }
+
+    fn zoo_schema() -> CsvxSchema {
+        CsvxSchema::from_string(
+            "id,type,constraints,description\r\n\
+             id,INTEGER,UNIQUE,Internal zoo id\r\n\
+             name,STRING,,Name of the animal\r\n\
+             birthday,DATE,,The animals birthday\r\n\
+             class,\"ENUM(MAMMAL,BIRD)\",,Class of species\r\n\
+             weight,DECIMAL,,Weight in kilograms\r\n\
+             caretaker,STRING,NULLABLE,Designated caretaker\r\n",
+            "schema.csv",
+        ).unwrap()
+    }
+
+    #[test]
+    fn serializes_structs_in_schema_order() {
+        let schema = zoo_schema();
+        let row = schema
+            .serialize_row(&Animal {
+                caretaker: None,
+                id: 2,
+                name: "Tweety".to_owned(),
+                birthday: NaiveDate::from_ymd_opt(1999, 12, 9).unwrap(),
+                class: Class::Bird,
+                weight: 0.03,
+            })
+            .unwrap();
+
+        let rendered: Vec<_> = schema
+            .iter_columns()
+            .zip(row.iter())
+            .map(|(col, value)| col.render_value(value).unwrap())
+            .collect();
+        assert_eq!(rendered, vec!["2", "Tweety", "19991209", "BIRD", "0.03", ""]);
+    }
+
+    #[test]
+    fn mismatched_records_are_rejected() {
+        let schema = zoo_schema();
+
+        // wrong type for `name`
+        let err = schema.serialize_row(&(1, 2)).unwrap_err();
+        assert_eq!(*err.location(), 2);
+        match *err.error() {
+            ValidationError::ValueError(ValueError::WrongType(_)) => (),
+            ref e => panic!("unexpected error {:?}", e),
+        }
+
+        #[derive(Serialize)]
+        struct Plant {
+            id: i64,
+            genus: String,
+        }
+
+        let err = schema
+            .serialize_row(&Plant {
+                id: 1,
+                genus: "Ficus".to_owned(),
+            })
+            .unwrap_err();
+        assert_eq!(*err.location(), 0);
+        match *err.error() {
+            ValidationError::Serde(_) => (),
+            ref e => panic!("unexpected error {:?}", e),
+        }
+    }
+}
@@ -0,0 +1,257 @@
+//! Excerpts of input files, shown along with errors
+
+use err::Location;
+use reader::{Record, Records};
+use std::{io, path};
+use std::io::Read;
+use super::{open_file, CsvxSchema};
+
+/// Contents of a file, used to show the parts errors are located at
+///
+/// Fields are labeled using the file's header row, unless the column ids of
+/// a schema are given.
+pub struct Source {
+    filename: String,
+    text: String,
+    /// Byte offset of the start of each line
+    line_starts: Vec<usize>,
+    /// Line each record starts on
+    record_lines: Vec<usize>,
+    columns: Vec<String>,
+}
+
+/// A line of an excerpt
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ExcerptLine {
+    pub number: usize,
+    pub text: String,
+    /// First column and number of characters to underline
+    pub underline: Option<(usize, usize)>,
+}
+
+/// The lines an error is located at
+///
+/// `None` stands for lines left out, as only the first and last line of
+/// fields spanning several lines are shown.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Excerpt {
+    pub lines: Vec<Option<ExcerptLine>>,
+    /// Id of the column the underlined field belongs to
+    pub label: Option<String>,
+}
+
+impl Source {
+    /// Load a (possibly compressed) file
+    pub fn from_file<P: AsRef<path::Path>>(filename: P) -> io::Result<Source> {
+        let filename_s = filename.as_ref().to_string_lossy().to_string();
+
+        let mut data = Vec::new();
+        open_file(filename)?.read_to_end(&mut data)?;
+        Ok(Source::new(&filename_s, &data))
+    }
+
+    /// Create a source from data in memory
+    ///
+    /// `filename` must match the one used in error locations.
+    pub fn new(filename: &str, data: &[u8]) -> Source {
+        let text = String::from_utf8_lossy(data).into_owned();
+        let line_starts = Some(0)
+            .into_iter()
+            .chain(text.match_indices('\n').map(|(idx, _)| idx + 1))
+            .collect();
+
+        // records after broken csv data cannot be located
+        let mut records = Records::new(data).flexible(true);
+        let mut record_lines = Vec::new();
+        let mut columns = None;
+        while let Some(Ok(record)) = records.next_record() {
+            record_lines.push(record.line());
+            if columns.is_none() {
+                columns = Some(record.fields);
+            }
+        }
+
+        Source {
+            filename: filename.to_owned(),
+            text: text,
+            line_starts: line_starts,
+            record_lines: record_lines,
+            columns: columns.unwrap_or_default(),
+        }
+    }
+
+    pub fn filename(&self) -> &str {
+        &self.filename
+    }
+
+    /// Label fields with the column ids of `schema`
+    pub fn with_schema(mut self, schema: &CsvxSchema) -> Source {
+        self.columns = schema.iter_columns().map(|col| col.id.clone()).collect();
+        self
+    }
+
+    /// Excerpt for a location, `None` if it is not located in this file
+    pub fn excerpt(&self, location: &Location) -> Option<Excerpt> {
+        if location.file() != Some(self.filename.as_str()) {
+            return None;
+        }
+
+        match *location {
+            Location::FileLineColumn(_, line, column) => Some(Excerpt {
+                lines: vec![Some(self.excerpt_line(line, Some((column, 1)))?)],
+                label: None,
+            }),
+            Location::FileLineField(_, line, field) => self.field_excerpt(line, field),
+            Location::FileLine(_, line) => Some(Excerpt {
+                lines: vec![Some(self.excerpt_line(line, None)?)],
+                label: None,
+            }),
+            Location::File(_) | Location::Unspecified => None,
+        }
+    }
+
+    /// Text of a 1-based line, without its line break
+    fn line(&self, line: usize) -> Option<&str> {
+        let start = *self.line_starts.get(line.checked_sub(1)?)?;
+        let end = self.line_starts.get(line).cloned().unwrap_or(self.text.len());
+        Some(self.text[start..end].trim_end_matches(&['\r', '\n'][..]))
+    }
+
+    fn excerpt_line(
+        &self,
+        number: usize,
+        underline: Option<(usize, usize)>,
+    ) -> Option<ExcerptLine> {
+        Some(ExcerptLine {
+            number: number,
+            text: self.line(number)?.to_owned(),
+            underline: underline,
+        })
+    }
+
+    /// Value of the field with the 1-based index `field`, starting on `line`
+    pub fn field(&self, line: usize, field: usize) -> Option<String> {
+        let (_, record) = self.record(line)?;
+        record.fields.get(field.checked_sub(1)?).cloned()
+    }
+
+    /// The record containing `line`, along with the line it starts on
+    fn record(&self, line: usize) -> Option<(usize, Record)> {
+        // the record containing the line starts on the same line or earlier
+        let idx = match self.record_lines.binary_search(&line) {
+            Ok(idx) => idx,
+            Err(0) => return None,
+            Err(idx) => idx - 1,
+        };
+        let record_line = self.record_lines[idx];
+        let start = self.line_starts[record_line - 1];
+        let record = Records::new(&self.text.as_bytes()[start..])
+            .flexible(true)
+            .next_record()?
+            .ok()?;
+        Some((record_line, record))
+    }
+
+    /// Excerpt of the field with the 1-based index `field`, starting on `line`
+    fn field_excerpt(&self, line: usize, field: usize) -> Option<Excerpt> {
+        let (record_line, record) = self.record(line)?;
+
+        let col_idx = field.checked_sub(1)?;
+        let value = record.fields.get(col_idx)?;
+        let pos = record.positions[col_idx];
+        let first = record_line + pos.line - 1;
+        let last = first + value.matches('\n').count();
+
+        // fields end right before the comma preceding the next one
+        let end = match record.positions.get(col_idx + 1) {
+            Some(next) => next.column - 1,
+            None => self.line(last)?.chars().count() + 1,
+        };
+
+        let mut lines = Vec::new();
+        for number in first..last + 1 {
+            let from = if number == first { pos.column } else { 1 };
+            let to = if number == last {
+                end
+            } else {
+                self.line(number)?.chars().count() + 1
+            };
+
+            // empty fields are marked by a single caret
+            lines.push(Some(self.excerpt_line(number, Some((from, span_len(from, to))))?));
+        }
+        if lines.len() > 2 {
+            let len = lines.len();
+            lines.splice(1..len - 1, Some(None));
+        }
+
+        Some(Excerpt {
+            lines: lines,
+            label: self.columns.get(col_idx).cloned(),
+        })
+    }
+}
+
+/// Number of characters from `from` up to `to`, but at least one
+fn span_len(from: usize, to: usize) -> usize {
+    if to > from { to - from } else { 1 }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn underlined(excerpt: &Excerpt) -> Vec<Option<(usize, String)>> {
+        excerpt
+            .lines
+            .iter()
+            .map(|line| {
+                line.as_ref().map(|l| {
+                    let (from, len) = l.underline.unwrap_or((1, 0));
+                    (l.number, l.text.chars().skip(from - 1).take(len).collect())
+                })
+            })
+            .collect()
+    }
+
+    #[test]
+    fn excerpts_underline_fields() {
+        let source = Source::new(
+            "zoo.csv",
+            "id,name,caretaker\r\n\
+             1,Brian,Sam\r\n\
+             2,\"Pinky\r\nthe\r\nmouse\",\r\n\
+             3,Äffchen,\"Big\r\nBird\"\r\n"
+                .as_bytes(),
+        );
+        let at = |line, field| {
+            source
+                .excerpt(&Location::FileLineField("zoo.csv".to_owned(), line, field))
+                .unwrap()
+        };
+
+        let excerpt = at(2, 2);
+        assert_eq!(underlined(&excerpt), vec![Some((2, "Brian".to_owned()))]);
+        assert_eq!(excerpt.label, Some("name".to_owned()));
+
+        // long fields are cut short, empty ones get a single caret
+        assert_eq!(
+            underlined(&at(3, 2)),
+            vec![Some((3, "\"Pinky".to_owned())), None, Some((5, "mouse\"".to_owned()))]
+        );
+        assert_eq!(underlined(&at(5, 3)), vec![Some((5, "".to_owned()))]);
+        assert_eq!(at(5, 3).lines[0].as_ref().unwrap().underline, Some((8, 1)));
+
+        // columns count characters
+        assert_eq!(
+            underlined(&at(6, 3)),
+            vec![Some((6, "\"Big".to_owned())), Some((7, "Bird\"".to_owned()))]
+        );
+
+        assert_eq!(source.field(3, 2), Some("Pinky\r\nthe\r\nmouse".to_owned()));
+
+        // other files and fields past the end of the row have no excerpt
+        assert!(source.excerpt(&Location::FileLine("other.csv".to_owned(), 2)).is_none());
+        assert!(source.excerpt(&Location::FileLineField("zoo.csv".to_owned(), 2, 4)).is_none());
+    }
+}
@@ -0,0 +1,278 @@
+use err::{ErrorAtLocation, ErrorLoc, ValidationError};
+use serde::Serialize;
+use std::io;
+use super::{CsvxSchema, Value};
+use unicode_normalization::{is_nfc, UnicodeNormalization};
+
+/// Writes canonical csvx files
+///
+/// Rows are checked against the schema before being written; header,
+/// quoting and line endings are always conformant.
+pub struct CsvxWriter<'a, W> {
+    schema: &'a CsvxSchema,
+    out: W,
+}
+
+impl<'a, W: io::Write> CsvxWriter<'a, W> {
+    /// Create a new writer, immediately writing the header
+    pub fn new(schema: &'a CsvxSchema, mut out: W) -> Result<CsvxWriter<'a, W>, ValidationError> {
+        let headers: Vec<_> = schema.iter_columns().map(|col| col.id.as_str()).collect();
+        write_record(&mut out, &headers)?;
+
+        Ok(CsvxWriter {
+            schema: schema,
+            out: out,
+        })
+    }
+
+    /// Write a single row
+    ///
+    /// Strings are normalized to NFC before being checked. Errors are located
+    /// at the 1-based index of the offending field, or at `0` if the row as a
+    /// whole does not fit the schema. If an error is returned, nothing has
+    /// been written.
+    pub fn write_row<T: AsRef<[Option<Value>]>>(
+        &mut self,
+        row: &T,
+    ) -> Result<(), ErrorAtLocation<ValidationError, usize>> {
+        let normalized: Vec<Option<Value>>;
+        let row = if row.as_ref().iter().all(is_normalized) {
+            row.as_ref()
+        } else {
+            normalized = row.as_ref().iter().map(normalize).collect();
+            &normalized
+        };
+
+        if row.len() != self.schema.columns.len() {
+            return Err(ValidationError::SchemaMismatch.at(0));
+        }
+
+        let mut fields = Vec::with_capacity(row.len());
+        for (idx, (col, value)) in self.schema.iter_columns().zip(row.iter()).enumerate() {
+            match col.render_value(value) {
+                Ok(s) => fields.push(s),
+                Err(e) => return Err(ValidationError::ValueError(e).at(idx + 1)),
+            }
+        }
+
+        if let Some(e) = self.schema.check_row(row).into_iter().next() {
+            return Err(e);
+        }
+
+        write_record(&mut self.out, &fields).map_err(|e| ValidationError::Io(e).at(0))
+    }
+
+    /// Serialize and write a single record
+    ///
+    /// Fields are matched to columns by name (or by position for tuples);
+    /// errors are located like those of `write_row`.
+    pub fn serialize<T: Serialize>(
+        &mut self,
+        record: &T,
+    ) -> Result<(), ErrorAtLocation<ValidationError, usize>> {
+        let row = self.schema.serialize_row(record)?;
+        self.write_row(&row)
+    }
+
+    /// Unwrap the underlying writer
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
+
+fn is_normalized(value: &Option<Value>) -> bool {
+    match *value {
+        Some(Value::String(ref s)) => is_nfc(s),
+        _ => true,
+    }
+}
+
+fn normalize(value: &Option<Value>) -> Option<Value> {
+    match *value {
+        Some(Value::String(ref s)) => Some(Value::String(s.nfc().collect())),
+        ref value => value.clone(),
+    }
+}
+
+/// Write a single record, using minimal quoting and `\r\n` line endings
+pub fn write_record<W: io::Write, S: AsRef<str>>(out: &mut W, fields: &[S]) -> io::Result<()> {
+    for (idx, field) in fields.iter().enumerate() {
+        if idx > 0 {
+            out.write_all(b",")?;
+        }
+
+        let field = field.as_ref();
+
+        // a record consisting of a single empty field must be quoted, as it
+        // would be an empty line otherwise
+        if field.contains(&[',', '"', '\r', '\n'][..]) ||
+            (field.is_empty() && fields.len() == 1)
+        {
+            out.write_all(b"\"")?;
+            out.write_all(field.replace('"', "\"\"").as_bytes())?;
+            out.write_all(b"\"")?;
+        } else {
+            out.write_all(field.as_bytes())?;
+        }
+    }
+    out.write_all(b"\r\n")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::NaiveDate;
+    use err::ValueError;
+    use format::check_format;
+    use std::{env, fs};
+
+    fn zoo_schema() -> CsvxSchema {
+        CsvxSchema::from_string(
+            "id,type,constraints,description\r\n\
+             id,INTEGER,UNIQUE,Internal zoo id\r\n\
+             name,STRING,,Name of the animal\r\n\
+             birthday,DATE,,The animals birthday\r\n\
+             class,\"ENUM(MAMMAL,BIRD,REPTILE,INSECT)\",,Class of species\r\n\
+             vaccinated,BOOL,,Vaccination status\r\n\
+             caretaker,STRING,NULLABLE,Designated caretaker\r\n",
+            "schema.csv",
+        ).unwrap()
+    }
+
+    #[test]
+    fn writes_canonical_rows() {
+        let schema = zoo_schema();
+        let mut wtr = CsvxWriter::new(&schema, Vec::new()).unwrap();
+
+        wtr.write_row(&vec![
+            Some(Value::Integer(1)),
+            Some(Value::String("Brian, the \"Gorilla\"".to_owned())),
+            Some(Value::Date(NaiveDate::from_ymd(2014, 11, 25))),
+            Some(Value::Enum(0)),
+            Some(Value::Bool(true)),
+            Some(Value::String("Sam".to_owned())),
+        ]).unwrap();
+        wtr.write_row(&vec![
+            Some(Value::Integer(2)),
+            Some(Value::String("Tweety".to_owned())),
+            Some(Value::Date(NaiveDate::from_ymd(1999, 12, 9))),
+            Some(Value::Enum(1)),
+            Some(Value::Bool(false)),
+            None,
+        ]).unwrap();
+
+        let out = wtr.into_inner();
+        assert_eq!(
+            String::from_utf8(out.clone()).unwrap(),
+            "id,name,birthday,class,vaccinated,caretaker\r\n\
+             1,\"Brian, the \"\"Gorilla\"\"\",20141125,MAMMAL,TRUE,Sam\r\n\
+             2,Tweety,19991209,BIRD,FALSE,\r\n"
+        );
+        assert!(check_format(&out, "out.csv").is_empty());
+    }
+
+    #[test]
+    fn normalizes_strings() {
+        let schema = zoo_schema();
+        let mut wtr = CsvxWriter::new(&schema, Vec::new()).unwrap();
+
+        wtr.write_row(&vec![
+            Some(Value::Integer(1)),
+            Some(Value::String("Rene\u{301}".to_owned())),
+            Some(Value::Date(NaiveDate::from_ymd(2014, 11, 25))),
+            Some(Value::Enum(0)),
+            Some(Value::Bool(true)),
+            None,
+        ]).unwrap();
+
+        let out = wtr.into_inner();
+        assert!(String::from_utf8(out.clone()).unwrap().contains(",Ren\u{e9},"));
+        let path = env::temp_dir().join("writer-nfc_20170401_animals-2.csv");
+        fs::write(&path, &out).unwrap();
+        assert!(schema.validate_file(&path).is_ok());
+    }
+
+    #[test]
+    fn serializes_records() {
+        #[derive(Serialize)]
+        struct Animal<'a> {
+            id: u32,
+            name: &'a str,
+            birthday: NaiveDate,
+            class: &'a str,
+            vaccinated: bool,
+            caretaker: Option<&'a str>,
+        }
+
+        let schema = zoo_schema();
+        let mut wtr = CsvxWriter::new(&schema, Vec::new()).unwrap();
+
+        wtr.serialize(&Animal {
+            id: 1,
+            name: "Brian",
+            birthday: NaiveDate::from_ymd_opt(2014, 11, 25).unwrap(),
+            class: "MAMMAL",
+            vaccinated: true,
+            caretaker: None,
+        }).unwrap();
+
+        let err = wtr.serialize(&Animal {
+            id: 2,
+            name: "Pinky",
+            birthday: NaiveDate::from_ymd_opt(2015, 2, 1).unwrap(),
+            class: "RODENT",
+            vaccinated: false,
+            caretaker: None,
+        }).unwrap_err();
+        assert_eq!(*err.location(), 4);
+
+        let out = wtr.into_inner();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "id,name,birthday,class,vaccinated,caretaker\r\n\
+             1,Brian,20141125,MAMMAL,TRUE,\r\n"
+        );
+    }
+
+    #[test]
+    fn rejects_rows_not_fitting_schema() {
+        let schema = zoo_schema();
+        let mut wtr = CsvxWriter::new(&schema, Vec::new()).unwrap();
+
+        let err = wtr.write_row(&vec![
+            Some(Value::Integer(1)),
+            None,
+            Some(Value::Date(NaiveDate::from_ymd(2014, 11, 25))),
+            Some(Value::Enum(7)),
+            Some(Value::Bool(true)),
+            None,
+        ]).unwrap_err();
+        assert_eq!(*err.location(), 2);
+        match *err.error() {
+            ValidationError::ValueError(ValueError::NonNullable) => (),
+            ref e => panic!("unexpected error {:?}", e),
+        }
+
+        let err = wtr.write_row(&vec![Some(Value::Bool(true))]).unwrap_err();
+        assert_eq!(*err.location(), 0);
+
+        let err = wtr.write_row(&vec![
+            Some(Value::String("1".to_owned())),
+            Some(Value::String("Brian".to_owned())),
+            Some(Value::Date(NaiveDate::from_ymd(2014, 11, 25))),
+            Some(Value::Enum(0)),
+            Some(Value::Bool(true)),
+            None,
+        ]).unwrap_err();
+        match *err.error() {
+            ValidationError::ValueError(ValueError::WrongType(ref ty)) => assert_eq!(ty, "INTEGER"),
+            ref e => panic!("unexpected error {:?}", e),
+        }
+
+        // nothing but the header has been written
+        assert_eq!(
+            wtr.into_inner(),
+            b"id,name,birthday,class,vaccinated,caretaker\r\n".to_vec()
+        );
+    }
+}